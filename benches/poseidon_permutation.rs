@@ -0,0 +1,45 @@
+//! Throughput of the native Poseidon permutation. Run once without and once with the `simd` feature to compare
+//! backends:
+//!
+//! `cargo +nightly bench --bench poseidon_permutation`
+//! `RUSTFLAGS="-C target_feature=+avx2" cargo +nightly bench --bench poseidon_permutation --features simd`
+//!
+//! The output is checked against a known answer before timing so both runs are known to compute the same hash.
+
+#![allow(non_snake_case)]
+
+extern crate bulletproofs_examples;
+extern crate criterion;
+
+use bulletproofs_examples::prelude::*;
+use bulletproofs_examples::gadget_poseidon::Poseidon_permutation;
+#[cfg(not(feature = "generated-constants"))]
+use bulletproofs_examples::scalar_utils::get_hex_from_scalar;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+// `Poseidon_hash_2(1, 2)` with the inverse S-box and the hardcoded constants
+#[cfg(not(feature = "generated-constants"))]
+const KNOWN_HASH_2: &str = "0x044897fb7a350fe508ce0c93165c54570775808e853c7c6a382fdbddafe32daa";
+
+fn poseidon_permutation(c: &mut Criterion) {
+    let params = PoseidonParams::default();
+    let input: Vec<Scalar> = (0..params.width).map(|i| Scalar::from(i as u64)).collect();
+
+    #[cfg(not(feature = "generated-constants"))]
+    {
+        let hash = Poseidon_hash_2(Scalar::from(1u64), Scalar::from(2u64), &params, &SboxType::Inverse).unwrap();
+        assert_eq!(get_hex_from_scalar(&hash), KNOWN_HASH_2);
+    }
+
+    let mut group = c.benchmark_group("poseidon_permutation");
+    for (name, sbox) in &[("cube", SboxType::Cube), ("inverse", SboxType::Inverse)] {
+        let expected = Poseidon_permutation(&input, &params, sbox);
+        group.bench_with_input(BenchmarkId::new("permutation", name), &input, |b, input| {
+            b.iter(|| assert_eq!(Poseidon_permutation(input, &params, sbox), expected))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, poseidon_permutation);
+criterion_main!(benches);
@@ -0,0 +1,106 @@
+//! Native hashing, proving and verifying of 1 Poseidon hash with the cube S-box for widths 3, 5 and 6, with the
+//! parameters of `PoseidonRegistry`. A width `w` hash takes `w - 2` inputs (1 slot is the capacity element and
+//! 1 the padding constant), so it is the node of a `w - 2`-ary tree. Width 3 is listed for its cost per hash but
+//! takes a single input so it cannot build a tree.
+//!
+//! Run with `cargo +nightly bench --bench poseidon_widths`.
+
+#![allow(non_snake_case)]
+
+extern crate bulletproofs_examples;
+extern crate criterion;
+
+use bulletproofs_examples::prelude::*;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const WIDTHS: [usize; 3] = [3, 5, 6];
+// Leaves of the tree used to compare widths
+const NUM_LEAVES: usize = 1 << 16;
+
+/// Constrain the hash of committed `vars` to be `expected`. The permutation input is the same as in `Poseidon_hash_n`
+/// with the padding and zeroes as constants.
+fn hash_n_circuit<CS: ConstraintSystem>(cs: &mut CS, vars: &[Variable], params: &PoseidonParams, sbox: &SboxType,
+                                        expected: &Scalar) -> Result<(), R1CSError> {
+    let mut input = vec![LinearCombination::from(Scalar::from(ZERO_CONST))];
+    input.extend(vars.iter().map(|v| LinearCombination::from(*v)));
+    input.push(LinearCombination::from(Scalar::from(PADDING_CONST)));
+    input.resize(params.width, LinearCombination::from(Scalar::from(ZERO_CONST)));
+    let output = Poseidon_permutation_constraints(cs, input, params, sbox)?;
+    constrain_lc_with_scalar(cs, output[1].clone(), expected);
+    Ok(())
+}
+
+fn prove(inputs: &[Scalar], params: &PoseidonParams, sbox: &SboxType, expected: &Scalar,
+         pc_gens: &PedersenGens, bp_gens: &BulletproofGens) -> (R1CSProof, Vec<CompressedRistretto>) {
+    let mut rng = rand::thread_rng();
+    let mut transcript = Transcript::new(b"PoseidonWidthsBench");
+    let mut prover = Prover::new(pc_gens, &mut transcript);
+    let (comms, vars): (Vec<_>, Vec<_>) = inputs.iter().map(|i| prover.commit(*i, Scalar::random(&mut rng))).unzip();
+    hash_n_circuit(&mut prover, &vars, params, sbox, expected).unwrap();
+    (prover.prove(bp_gens).unwrap(), comms)
+}
+
+fn verify(proof: &R1CSProof, comms: &[CompressedRistretto], params: &PoseidonParams, sbox: &SboxType, expected: &Scalar,
+          pc_gens: &PedersenGens, bp_gens: &BulletproofGens) -> Result<(), R1CSError> {
+    let mut transcript = Transcript::new(b"PoseidonWidthsBench");
+    let mut verifier = Verifier::new(&mut transcript);
+    let vars: Vec<Variable> = comms.iter().map(|c| verifier.commit(*c)).collect();
+    hash_n_circuit(&mut verifier, &vars, params, sbox, expected)?;
+    verifier.verify(proof, pc_gens, bp_gens)
+}
+
+fn num_multipliers(inputs: &[Scalar], params: &PoseidonParams, sbox: &SboxType, expected: &Scalar, pc_gens: &PedersenGens) -> usize {
+    let mut transcript = Transcript::new(b"PoseidonWidthsBench");
+    let mut prover = Prover::new(pc_gens, &mut transcript);
+    let vars: Vec<Variable> = inputs.iter().map(|i| prover.commit(*i, Scalar::one()).1).collect();
+    hash_n_circuit(&mut prover, &vars, params, sbox, expected).unwrap();
+    prover.num_multipliers()
+}
+
+fn poseidon_widths(c: &mut Criterion) {
+    let sbox = SboxType::Cube;
+    let registry = PoseidonRegistry::new(SboxType::Cube);
+    let pc_gens = PedersenGens::default();
+    let bp_gens = BulletproofGens::new(2048, 1);
+
+    let mut group = c.benchmark_group("poseidon_widths");
+    group.sample_size(10);
+    for width in WIDTHS.iter() {
+        let params = registry.get(*width).unwrap();
+        let inputs: Vec<Scalar> = (0..width - 2).map(|i| Scalar::from(i as u64 + 1)).collect();
+        let expected = Poseidon_hash_n(&inputs, params, &sbox).unwrap().into_scalar();
+
+        // Fail before timing if the circuit does not match the native hash
+        let (proof, comms) = prove(&inputs, params, &sbox, &expected, &pc_gens, &bp_gens);
+        assert!(verify(&proof, &comms, params, &sbox, &expected, &pc_gens, &bp_gens).is_ok());
+        assert!(verify(&proof, &comms, params, &sbox, &(expected + Scalar::one()), &pc_gens, &bp_gens).is_err());
+
+        // The multipliers per hash and, for a tree of `NUM_LEAVES` leaves, per path go in the benchmark id so the
+        // report has the cost of a hash next to the depth of the tree
+        let arity = width - 2;
+        let multipliers = num_multipliers(&inputs, params, &sbox, &expected, &pc_gens);
+        let id = if arity > 1 {
+            let mut depth = 0;
+            while arity.pow(depth) < NUM_LEAVES {
+                depth += 1;
+            }
+            format!("width {}, {} multipliers, depth {}, {} multipliers per path", width, multipliers, depth, depth as usize * multipliers)
+        } else {
+            format!("width {}, {} multipliers, no tree", width, multipliers)
+        };
+
+        group.bench_with_input(BenchmarkId::new("native", &id), &inputs, |b, inputs| {
+            b.iter(|| assert_eq!(Poseidon_hash_n(inputs, params, &sbox).unwrap().into_scalar(), expected))
+        });
+        group.bench_with_input(BenchmarkId::new("prove", &id), &inputs, |b, inputs| {
+            b.iter(|| prove(inputs, params, &sbox, &expected, &pc_gens, &bp_gens))
+        });
+        group.bench_with_input(BenchmarkId::new("verify", &id), &inputs, |b, _| {
+            b.iter(|| assert!(verify(&proof, &comms, params, &sbox, &expected, &pc_gens, &bp_gens).is_ok()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, poseidon_widths);
+criterion_main!(benches);
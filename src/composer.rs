@@ -0,0 +1,205 @@
+extern crate bulletproofs;
+extern crate curve25519_dalek;
+extern crate merlin;
+
+use bulletproofs::r1cs::{ConstraintSystem, R1CSError, R1CSProof, Prover, Verifier};
+use curve25519_dalek::scalar::Scalar;
+use bulletproofs::{BulletproofGens, PedersenGens};
+use curve25519_dalek::ristretto::CompressedRistretto;
+use merlin::Transcript;
+
+use crate::scalar_utils::scalar_to_u64;
+use crate::r1cs_utils::{AllocatedQuantity, AllocatedScalar, positive_no_gadget};
+use crate::gadget_set_membership_1::set_membership_1_gadget;
+use crate::gadget_poseidon::{PoseidonParams, SboxType, Poseidon_hash_2_gadget,
+                             allocate_statics_for_prover, allocate_statics_for_verifier, check_width};
+
+/// Refers to a commitment registered with `CircuitBuilder::commit`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Handle(usize);
+
+enum GadgetSpec<'a> {
+    PoseidonHash2 {
+        xl: Handle,
+        xr: Handle,
+        output: Scalar,
+        params: &'a PoseidonParams,
+        sbox: &'a SboxType
+    },
+    Range {
+        v: Handle,
+        bit_size: usize
+    },
+    SetMembership {
+        v: Handle,
+        set: Vec<u64>
+    }
+}
+
+/// Build a circuit from several gadgets over shared commitments. Commitments are registered once and the gadgets
+/// refer to them by `Handle`. The same builder is used by the prover and the verifier.
+pub struct CircuitBuilder<'a> {
+    num_commitments: usize,
+    gadgets: Vec<GadgetSpec<'a>>
+}
+
+impl<'a> CircuitBuilder<'a> {
+    pub fn new() -> CircuitBuilder<'a> {
+        CircuitBuilder {
+            num_commitments: 0,
+            gadgets: vec![]
+        }
+    }
+
+    /// Register a commitment. Commitments are created in the order of registration.
+    pub fn commit(&mut self) -> Handle {
+        self.num_commitments += 1;
+        Handle(self.num_commitments - 1)
+    }
+
+    /// Prove `Poseidon_hash_2(xl, xr) == output`
+    pub fn poseidon_hash_2(&mut self, xl: Handle, xr: Handle, output: &Scalar, params: &'a PoseidonParams, sbox: &'a SboxType) -> &mut Self {
+        self.gadgets.push(GadgetSpec::PoseidonHash2 { xl, xr, output: *output, params, sbox });
+        self
+    }
+
+    /// Prove `v` is in [0, 2^bit_size). `bit_size` can be at most 64.
+    pub fn range(&mut self, v: Handle, bit_size: usize) -> &mut Self {
+        self.gadgets.push(GadgetSpec::Range { v, bit_size });
+        self
+    }
+
+    /// Prove `v` is one of the elements of `set`
+    pub fn set_membership(&mut self, v: Handle, set: &[u64]) -> &mut Self {
+        self.gadgets.push(GadgetSpec::SetMembership { v, set: set.to_vec() });
+        self
+    }
+
+    /// Commit to `values` with `blindings`, one for each registered commitment in order, and prove all gadgets.
+    pub fn prove(&self, values: &[Scalar], blindings: &[Scalar], transcript_label: &'static [u8],
+                 pc_gens: &PedersenGens, bp_gens: &BulletproofGens) -> Result<(R1CSProof, Vec<CompressedRistretto>), R1CSError> {
+        if values.len() != self.num_commitments || blindings.len() != self.num_commitments {
+            return Err(R1CSError::GadgetError {
+                description: format!("Expected {} values and blindings but found {} and {}", self.num_commitments, values.len(), blindings.len())
+            })
+        }
+
+        let mut prover_transcript = Transcript::new(transcript_label);
+        let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+        let mut comms = vec![];
+        let mut allocs = vec![];
+        for i in 0..self.num_commitments {
+            let (com, var) = prover.commit(values[i], blindings[i]);
+            comms.push(com);
+            allocs.push(AllocatedScalar::known(var, values[i]));
+        }
+        let statics = allocate_statics_for_prover(&mut prover, self.num_statics());
+
+        self.synthesize(&mut prover, &allocs, &statics)?;
+
+        let proof = prover.prove(&bp_gens)?;
+        Ok((proof, comms))
+    }
+
+    pub fn verify(&self, proof: R1CSProof, commitments: &[CompressedRistretto], transcript_label: &'static [u8],
+                  pc_gens: &PedersenGens, bp_gens: &BulletproofGens) -> Result<(), R1CSError> {
+        if commitments.len() != self.num_commitments {
+            return Err(R1CSError::GadgetError {
+                description: format!("Expected {} commitments but found {}", self.num_commitments, commitments.len())
+            })
+        }
+
+        let mut verifier_transcript = Transcript::new(transcript_label);
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let allocs: Vec<AllocatedScalar> = commitments.iter().map(|c| AllocatedScalar::unknown(verifier.commit(*c))).collect();
+        let statics = allocate_statics_for_verifier(&mut verifier, self.num_statics(), pc_gens);
+
+        self.synthesize(&mut verifier, &allocs, &statics)?;
+
+        verifier.verify(&proof, &pc_gens, &bp_gens)
+    }
+
+    /// Number of statics needed by the Poseidon gadgets, 0 if there are none
+    fn num_statics(&self) -> usize {
+        self.gadgets.iter().map(|g| match g {
+            GadgetSpec::PoseidonHash2 { params, .. } => params.width.saturating_sub(2),
+            _ => 0
+        }).max().unwrap_or(0)
+    }
+
+    fn synthesize<CS: ConstraintSystem>(&self, cs: &mut CS, allocs: &[AllocatedScalar], statics: &[AllocatedScalar]) -> Result<(), R1CSError> {
+        let get = |h: &Handle| allocs.get(h.0).copied().ok_or(R1CSError::GadgetError {
+            description: format!("Unknown commitment {:?}", h)
+        });
+
+        for g in self.gadgets.iter() {
+            match g {
+                GadgetSpec::PoseidonHash2 { xl, xr, output, params, sbox } => {
+                    check_width(params, 3)?;
+                    let statics = statics[..params.width - 2].to_vec();
+                    Poseidon_hash_2_gadget(cs, get(xl)?, get(xr)?, statics, params, sbox, output)?;
+                }
+                GadgetSpec::Range { v, bit_size } => {
+                    let v = get(v)?;
+                    let assignment = match v.assignment {
+                        Some(s) => Some(scalar_to_u64(&s).ok_or(R1CSError::GadgetError {
+                            description: String::from("Value for range check does not fit in 64 bits")
+                        })?),
+                        None => None
+                    };
+                    positive_no_gadget(cs, AllocatedQuantity { variable: v.variable, assignment }, *bit_size)?;
+                }
+                GadgetSpec::SetMembership { v, set } => {
+                    let v = get(v)?;
+                    let mut diff_vars = vec![];
+                    for s in set {
+                        let diff = v.assignment.map(|a| Scalar::from(*s) - a);
+                        diff_vars.push(AllocatedScalar::new(cs.allocate(diff)?, diff));
+                    }
+                    set_membership_1_gadget(cs, v, diff_vars, set)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gadget_poseidon::Poseidon_hash_2;
+
+    fn hash_and_range(x: u64, y: u64, bit_size: usize, set: &[u64]) -> Result<(), R1CSError> {
+        let p_params = PoseidonParams::new(6, 4, 4, 140);
+        let sbox = SboxType::Inverse;
+        let (x, y) = (Scalar::from(x), Scalar::from(y));
+        let output = Poseidon_hash_2(x, y, &p_params, &sbox).unwrap();
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(4096, 1);
+        let mut rng = rand::thread_rng();
+        let label = b"CircuitBuilderTest";
+
+        let mut builder = CircuitBuilder::new();
+        let hx = builder.commit();
+        let hy = builder.commit();
+        builder.poseidon_hash_2(hx, hy, &output, &p_params, &sbox)
+            .range(hx, bit_size)
+            .set_membership(hy, set);
+
+        let blindings = vec![Scalar::random(&mut rng), Scalar::random(&mut rng)];
+        let (proof, commitments) = builder.prove(&[x, y], &blindings, label, &pc_gens, &bp_gens)?;
+        builder.verify(proof, &commitments, label, &pc_gens, &bp_gens)
+    }
+
+    #[test]
+    fn test_circuit_builder() {
+        assert!(hash_and_range(100, 7, 8, &[2, 7, 11]).is_ok());
+        // x is not in [0, 2^8)
+        assert!(hash_and_range(300, 7, 8, &[2, 7, 11]).is_err());
+        // y not in the set
+        assert!(hash_and_range(100, 5, 8, &[2, 7, 11]).is_err());
+    }
+}
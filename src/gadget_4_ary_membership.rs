@@ -0,0 +1,310 @@
+extern crate rand;
+extern crate curve25519_dalek;
+extern crate merlin;
+extern crate bulletproofs;
+
+use curve25519_dalek::scalar::Scalar;
+use bulletproofs::r1cs::{ConstraintSystem, R1CSError, R1CSProof, Variable, Prover, Verifier};
+use bulletproofs::{BulletproofGens, PedersenGens};
+use merlin::Transcript;
+use bulletproofs::r1cs::LinearCombination;
+
+use crate::r1cs_utils::{AllocatedScalar, constrain_lc_with_scalar};
+use crate::gadget_poseidon::{PoseidonParams, Poseidon_hash_2, Poseidon_hash_4, Poseidon_hash_2_constraints,
+                             Poseidon_hash_4_constraints, SboxType, poseidon_hash_ct_eq, allocate_statics_for_prover, allocate_statics_for_verifier,
+                             check_width, PoseidonError};
+
+/// The 3 siblings of a node in the path from leaf to root
+pub type ProofNode = [Scalar; 3];
+
+/// Leaf of the tree is a Poseidon commitment to a secret, i.e. `Poseidon_hash_2(secret, nonce).into_scalar()`
+pub fn leaf_commitment(secret: Scalar, nonce: Scalar, hash_params: &PoseidonParams, sbox: &SboxType) -> Result<Scalar, PoseidonError> {
+    Ok(Poseidon_hash_2(secret, nonce, hash_params, sbox)?.into_scalar())
+}
+
+/// Merkle tree with width 4, .i.e each node has 4 children. Unlike `VanillaSparseMerkleTree_4`, the tree is built once
+/// from a list of leaves. Missing leaves are taken as zero.
+pub struct FourAryMerkleTree<'a> {
+    pub depth: usize,
+    /// `levels[0]` are the leaves and `levels[depth]` contains only the root
+    levels: Vec<Vec<Scalar>>,
+    hash_params: &'a PoseidonParams,
+    sbox: &'a SboxType,
+}
+
+impl<'a> FourAryMerkleTree<'a> {
+    /// Panics if there are more leaves than the tree can have or the width of `hash_params` is less than 5
+    pub fn new(leaves: &[Scalar], depth: usize, hash_params: &'a PoseidonParams, sbox: &'a SboxType) -> FourAryMerkleTree<'a> {
+        if let Err(e) = check_width(hash_params, 5) {
+            panic!("{}", e);
+        }
+        let num_leaves = 1usize << (2 * depth);
+        if leaves.len() > num_leaves {
+            panic!("Tree of depth {} can have at most {} leaves but {} were given", depth, num_leaves, leaves.len());
+        }
+
+        let mut level = leaves.to_vec();
+        level.resize(num_leaves, Scalar::zero());
+        let mut levels = vec![level];
+        for i in 0..depth {
+            let next = levels[i].chunks(4).map(|c| {
+                Poseidon_hash_4([c[0], c[1], c[2], c[3]], hash_params, sbox).unwrap().into_scalar()
+            }).collect::<Vec<_>>();
+            levels.push(next);
+        }
+
+        FourAryMerkleTree {
+            depth,
+            levels,
+            hash_params,
+            sbox
+        }
+    }
+
+    pub fn root(&self) -> Scalar {
+        self.levels[self.depth][0]
+    }
+
+    pub fn get_leaf(&self, index: usize) -> Scalar {
+        self.levels[0][index]
+    }
+
+    /// Returns the siblings and the position (0 to 3) of the node among its siblings for each level, starting from the leaf
+    pub fn get_proof(&self, index: usize) -> (Vec<ProofNode>, Vec<u8>) {
+        let mut proof = vec![];
+        let mut positions = vec![];
+        let mut cur_idx = index;
+        for i in 0..self.depth {
+            let pos = cur_idx % 4;
+            let start = cur_idx - pos;
+            let mut node: ProofNode = [Scalar::zero(); 3];
+            let mut j = 0;
+            for k in 0..4 {
+                if k != pos {
+                    node[j] = self.levels[i][start + k];
+                    j += 1;
+                }
+            }
+            proof.push(node);
+            positions.push(pos as u8);
+            cur_idx /= 4;
+        }
+        (proof, positions)
+    }
+
+    /// Verify a merkle proof, if `root` is None, use the current root else use given root
+    pub fn verify_proof(&self, leaf: Scalar, positions: &[u8], proof: &[ProofNode], root: Option<&Scalar>) -> bool {
+        if positions.len() != self.depth || proof.len() != self.depth {
+            return false
+        }
+        let mut cur_val = leaf;
+        for i in 0..self.depth {
+            if positions[i] > 3 {
+                return false
+            }
+            let mut children = proof[i].to_vec();
+            children.insert(positions[i] as usize, cur_val);
+            cur_val = Poseidon_hash_4([children[0], children[1], children[2], children[3]], self.hash_params, self.sbox).unwrap().into_scalar();
+        }
+
+        match root {
+            Some(r) => poseidon_hash_ct_eq(&cur_val, r).into(),
+            None => poseidon_hash_ct_eq(&cur_val, &self.root()).into()
+        }
+    }
+}
+
+/// Prove that `Poseidon_hash_2(secret, nonce).into_scalar()` is a leaf of the 4-ary tree with root `root`. `positions` are the committed
+/// positions (0 to 3) of the node among its siblings at each level and `proof_nodes` the siblings, 3 per level,
+/// both starting from the leaf. `statics` should have `width - 2` elements, as for `Poseidon_hash_2`.
+/// The children at each level are arranged as in `vanilla_merkle_merkle_tree_4_verif_gadget`.
+pub fn four_ary_membership_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    depth: usize,
+    root: &Scalar,
+    secret: AllocatedScalar,
+    nonce: AllocatedScalar,
+    positions: Vec<AllocatedScalar>,
+    proof_nodes: Vec<AllocatedScalar>,
+    statics: Vec<AllocatedScalar>,
+    poseidon_params: &PoseidonParams,
+    sbox_type: &SboxType
+) -> Result<(), R1CSError> {
+    if positions.len() != depth || proof_nodes.len() != 3 * depth {
+        return Err(R1CSError::GadgetError { description: format!("Need {} positions and {} proof nodes for depth {} but found {} and {}",
+                                                                 depth, 3 * depth, depth, positions.len(), proof_nodes.len()) })
+    }
+
+    check_width(poseidon_params, 5)?;
+    if statics.len() != poseidon_params.width - 2 {
+        return Err(R1CSError::GadgetError { description: format!("Expected {} statics but found {}", poseidon_params.width - 2, statics.len()) })
+    }
+
+    let statics: Vec<LinearCombination> = statics.iter().map(|s| s.variable.into()).collect();
+    // The 4:1 hash only needs the zero and the padding constant
+    let statics_4 = statics[..poseidon_params.width - 4].to_vec();
+
+    let mut prev_hash = Poseidon_hash_2_constraints::<CS>(cs, secret.variable.into(), nonce.variable.into(), statics, poseidon_params, sbox_type)?;
+
+    for i in 0..depth {
+        let pos = positions[i];
+
+        // Decompose the position into 2 bits and check that both are actually bits
+        let (b0, b0_1, o) = cs.allocate_multiplier(pos.assignment.map(|p| {
+            let bit = (p.as_bytes()[0] & 1) as u64;
+            (bit.into(), (1 - bit).into())
+        }))?;
+        cs.constrain(o.into());
+        cs.constrain(b0 + (b0_1 - 1u64));
+
+        let (b1, b1_1, o) = cs.allocate_multiplier(pos.assignment.map(|p| {
+            let bit = ((p.as_bytes()[0] >> 1) & 1) as u64;
+            (bit.into(), (1 - bit).into())
+        }))?;
+        cs.constrain(o.into());
+        cs.constrain(b1 + (b1_1 - 1u64));
+
+        // position = 2*b1 + b0
+        cs.constrain(b1 * Scalar::from(2u64) + b0 - pos.variable);
+
+        let N1: LinearCombination = proof_nodes[3 * i].variable.into();
+        let N2: LinearCombination = proof_nodes[3 * i + 1].variable.into();
+        let N3: LinearCombination = proof_nodes[3 * i + 2].variable.into();
+
+        // Notation: b0_1 = 1 - b0 and b1_1 = 1 - b1 and prev_hash = N
+
+        // (1 - b0)*(1 - b1)
+        let (_, _, b0_1_b1_1) = cs.multiply(b0_1.into(), b1_1.into());
+        // (1 - b0)*b1
+        let (_, _, b0_1_b1) = cs.multiply(b0_1.into(), b1.into());
+        // b0*(1 - b1)
+        let (_, _, b0_b1_1) = cs.multiply(b0.into(), b1_1.into());
+        // b0*b1
+        let (_, _, b0_b1) = cs.multiply(b0.into(), b1.into());
+
+        // c0 = (1-b0)*(1-b1)*N + b0*N1 + (1-b0)*b1*N1
+        let (_, _, c0_1) = cs.multiply(b0_1_b1_1.into(), prev_hash.clone());
+        let (_, _, c0_2) = cs.multiply(b0.into(), N1.clone());
+        let (_, _, c0_3) = cs.multiply(b0_1_b1.into(), N1.clone());
+        let c0 = c0_1 + c0_2 + c0_3;
+
+        // c1 = (1-b0)*(1-b1)*N1 + (1-b1)*b0*N + (1-b0)*b1*N2 + b0*b1*N2
+        let (_, _, c1_1) = cs.multiply(b0_1_b1_1.into(), N1.clone());
+        let (_, _, c1_2) = cs.multiply(b0_b1_1.into(), prev_hash.clone());
+        let (_, _, c1_3) = cs.multiply(b0_1_b1.into(), N2.clone());
+        let (_, _, c1_4) = cs.multiply(b0_b1.into(), N2.clone());
+        let c1 = c1_1 + c1_2 + c1_3 + c1_4;
+
+        // c2 = (1-b1)*N2 + (1-b0)*b1*N + b0*b1*N3
+        let (_, _, c2_1) = cs.multiply(b1_1.into(), N2.clone());
+        let (_, _, c2_2) = cs.multiply(b0_1_b1.into(), prev_hash.clone());
+        let (_, _, c2_3) = cs.multiply(b0_b1.into(), N3.clone());
+        let c2 = c2_1 + c2_2 + c2_3;
+
+        // c3 = (1-b1)*N3 + (1-b0)*b1*N3 + b1*b0*N
+        let (_, _, c3_1) = cs.multiply(b1_1.into(), N3.clone());
+        let (_, _, c3_2) = cs.multiply(b0_1_b1.into(), N3.clone());
+        let (_, _, c3_3) = cs.multiply(b0_b1.into(), prev_hash.clone());
+        let c3 = c3_1 + c3_2 + c3_3;
+
+        let input: [LinearCombination; 4] = [c0, c1, c2, c3];
+        prev_hash = Poseidon_hash_4_constraints::<CS>(cs, input, statics_4.clone(), poseidon_params, sbox_type)?;
+    }
+
+    constrain_lc_with_scalar::<CS>(cs, prev_hash, root);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use merlin::Transcript;
+    use rand::SeedableRng;
+    use super::rand::rngs::StdRng;
+
+    fn four_ary_membership(positions_override: Option<Vec<u8>>) -> Result<(), R1CSError> {
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+
+        let width = 6;
+        let (full_b, full_e) = (4, 4);
+        let partial_rounds = 140;
+        let p_params = PoseidonParams::new(width, full_b, full_e, partial_rounds);
+        let sbox_type = SboxType::Inverse;
+        let depth = 2;
+
+        let secret = Scalar::random(&mut test_rng);
+        let nonce = Scalar::random(&mut test_rng);
+        let leaf_index = 9;
+        let mut leaves = (0..16).map(|_| Scalar::random(&mut test_rng)).collect::<Vec<_>>();
+        leaves[leaf_index] = leaf_commitment(secret, nonce, &p_params, &sbox_type).unwrap();
+        let tree = FourAryMerkleTree::new(&leaves, depth, &p_params, &sbox_type);
+        let root = tree.root();
+
+        let (merkle_proof, positions) = tree.get_proof(leaf_index);
+        assert_eq!(positions, vec![1, 2]);
+        assert!(tree.verify_proof(tree.get_leaf(leaf_index), &positions, &merkle_proof, None));
+        let positions = positions_override.unwrap_or(positions);
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(8192, 1);
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"4AryMembership");
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let mut comms = vec![];
+            let mut commit = |prover: &mut Prover, v: Scalar, comms: &mut Vec<_>| {
+                let (c, var) = prover.commit(v, Scalar::random(&mut test_rng));
+                comms.push(c);
+                AllocatedScalar {
+                    variable: var,
+                    assignment: Some(v),
+                }
+            };
+
+            let secret_alloc = commit(&mut prover, secret, &mut comms);
+            let nonce_alloc = commit(&mut prover, nonce, &mut comms);
+            let position_allocs = positions.iter().map(|p| commit(&mut prover, Scalar::from(*p), &mut comms)).collect::<Vec<_>>();
+            let mut proof_allocs = vec![];
+            for p in merkle_proof.iter() {
+                for n in p {
+                    proof_allocs.push(commit(&mut prover, *n, &mut comms));
+                }
+            }
+
+            let statics = allocate_statics_for_prover(&mut prover, width - 2);
+            four_ary_membership_gadget(&mut prover, depth, &root, secret_alloc, nonce_alloc,
+                                       position_allocs, proof_allocs, statics, &p_params, &sbox_type)?;
+
+            let proof = prover.prove(&bp_gens)?;
+            (proof, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"4AryMembership");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let mut allocs = commitments.iter().map(|c| AllocatedScalar {
+            variable: verifier.commit(*c),
+            assignment: None,
+        }).collect::<Vec<_>>();
+        let proof_allocs = allocs.split_off(2 + depth);
+        let position_allocs = allocs.split_off(2);
+
+        let statics = allocate_statics_for_verifier(&mut verifier, width - 2, &pc_gens);
+        four_ary_membership_gadget(&mut verifier, depth, &root, allocs[0], allocs[1],
+                                   position_allocs, proof_allocs, statics, &p_params, &sbox_type)?;
+
+        verifier.verify(&proof, &pc_gens, &bp_gens)
+    }
+
+    #[test]
+    fn test_4_ary_membership() {
+        assert!(four_ary_membership(None).is_ok());
+
+        // Wrong position for the leaf
+        assert!(four_ary_membership(Some(vec![0, 2])).is_err());
+
+        // Position that cannot be represented with 2 bits
+        assert!(four_ary_membership(Some(vec![5, 2])).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+extern crate bulletproofs;
+extern crate curve25519_dalek;
+
+use bulletproofs::r1cs::{ConstraintSystem, R1CSError, Variable};
+use bulletproofs::r1cs::LinearCombination;
+use curve25519_dalek::scalar::Scalar;
+
+use crate::r1cs_utils::{AllocatedScalar, constrain_lc_with_scalar};
+use crate::gadget_bitwise::boolean_gadget;
+use crate::gadget_merkle::parent_constraints;
+use crate::gadget_nullifier::nullifier_gadget;
+use crate::gadget_poseidon::{PoseidonParams, SboxType, PoseidonError, Poseidon_hash_n, Poseidon_permutation_constraints,
+                             PADDING_CONST, ZERO_CONST};
+
+/// Element of the accumulator for `secret`, which is `Poseidon_hash_n(&[secret])`. This is not
+/// `Poseidon_hash_2(secret, domain)` for any domain so the element does not reveal a nullifier.
+pub fn accumulator_element(secret: Scalar, params: &PoseidonParams, sbox: &SboxType) -> Result<Scalar, PoseidonError> {
+    Ok(Poseidon_hash_n(&[secret], params, sbox)?.into_scalar())
+}
+
+/// Prove that the element of committed `secret` is in the binary merkle tree with the public `root` and that
+/// `nullifier` is the nullifier of the secret in `nullifier_domain`. `path` is as in `batch_membership_gadget`.
+/// A verifier rejecting seen nullifiers rejects a second proof with the same secret.
+pub fn accumulator_membership_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    secret: AllocatedScalar,
+    path: Vec<(AllocatedScalar, Variable)>,
+    root: Scalar,
+    nullifier_domain: Scalar,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+    nullifier: &Scalar
+) -> Result<(), R1CSError> {
+    let width = params.width;
+    if width < 3 {
+        return Err(PoseidonError::WidthTooSmall { width, min_width: 3 }.into())
+    }
+
+    // Same permutation input as `Poseidon_hash_n` with a single input
+    let mut input = vec![LinearCombination::from(Scalar::from(ZERO_CONST)), secret.variable.into(),
+                         LinearCombination::from(Scalar::from(PADDING_CONST))];
+    input.resize(width, LinearCombination::from(Scalar::from(ZERO_CONST)));
+    let mut cur = Poseidon_permutation_constraints(cs, input, params, sbox)?[1].to_owned();
+
+    for (sibling, is_right) in path {
+        boolean_gadget(cs, is_right)?;
+        cur = parent_constraints(cs, cur, sibling, is_right, params, sbox)?;
+    }
+    constrain_lc_with_scalar(cs, cur, &root);
+
+    nullifier_gadget(cs, secret, nullifier_domain, params, sbox, nullifier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::r1cs::{Prover, Verifier};
+    use bulletproofs::{BulletproofGens, PedersenGens};
+    use merlin::Transcript;
+    use crate::gadget_merkle::build_merkle_root;
+    use crate::gadget_nullifier::compute_nullifier;
+    use crate::gadget_poseidon::Poseidon_hash_2;
+
+    #[test]
+    fn test_accumulator_membership_gadget() {
+        let p_params = PoseidonParams::new(6, 4, 4, 6);
+        let sbox = SboxType::Inverse;
+        let domain = Scalar::from(99u64);
+        let secrets: Vec<Scalar> = (0..4u64).map(|i| Scalar::from(i + 1000)).collect();
+        let elements: Vec<Scalar> = secrets.iter().map(|s| accumulator_element(*s, &p_params, &sbox).unwrap()).collect();
+        let root = build_merkle_root(&elements, &p_params, &sbox).unwrap();
+
+        // Path of element 2
+        let parent_01 = Poseidon_hash_2(elements[0], elements[1], &p_params, &sbox).unwrap().into_scalar();
+        let path = vec![(elements[3], false), (parent_01, true)];
+        let nullifier = compute_nullifier(secrets[2], domain, &p_params, &sbox).unwrap();
+        assert_ne!(nullifier, elements[2]);
+
+        assert!(accumulator_helper(secrets[2], &path, root, domain, &nullifier, &p_params, &sbox).is_ok());
+
+        // A second spend of the same secret gives the same nullifier, which the verifier has seen, and a fresh
+        // nullifier to avoid that fails the proof
+        assert_eq!(compute_nullifier(secrets[2], domain, &p_params, &sbox).unwrap(), nullifier);
+        assert!(accumulator_helper(secrets[2], &path, root, domain, &(nullifier + Scalar::one()), &p_params, &sbox).is_err());
+        // Nullifier of another member
+        let other = compute_nullifier(secrets[1], domain, &p_params, &sbox).unwrap();
+        assert!(accumulator_helper(secrets[2], &path, root, domain, &other, &p_params, &sbox).is_err());
+
+        // Secret not in the accumulator or wrong root
+        let outsider = Scalar::from(5u64);
+        assert!(accumulator_helper(outsider, &path, root, domain, &compute_nullifier(outsider, domain, &p_params, &sbox).unwrap(), &p_params, &sbox).is_err());
+        assert!(accumulator_helper(secrets[2], &path, root + Scalar::one(), domain, &nullifier, &p_params, &sbox).is_err());
+    }
+
+    fn accumulator_helper(secret: Scalar, path: &[(Scalar, bool)], root: Scalar, domain: Scalar, nullifier: &Scalar,
+                          params: &PoseidonParams, sbox: &SboxType) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let label = b"AccumulatorMembershipTest";
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(label);
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com, var) = prover.commit(secret, Scalar::random(&mut rng));
+            let mut comms = vec![com];
+            let mut path_allocs = vec![];
+            for (sibling, is_right) in path {
+                let (com_s, var_s) = prover.commit(*sibling, Scalar::random(&mut rng));
+                let (com_b, var_b) = prover.commit(Scalar::from(*is_right as u64), Scalar::random(&mut rng));
+                comms.push(com_s);
+                comms.push(com_b);
+                path_allocs.push((AllocatedScalar::known(var_s, *sibling), var_b));
+            }
+
+            accumulator_membership_gadget(&mut prover, AllocatedScalar::known(var, secret), path_allocs, root, domain, params, sbox, nullifier)?;
+            (prover.prove(&bp_gens)?, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(label);
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let secret = AllocatedScalar::unknown(verifier.commit(commitments[0]));
+        let path_allocs = commitments[1..].chunks(2)
+            .map(|c| (AllocatedScalar::unknown(verifier.commit(c[0])), verifier.commit(c[1])))
+            .collect();
+
+        accumulator_membership_gadget(&mut verifier, secret, path_allocs, root, domain, params, sbox, nullifier)?;
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+}
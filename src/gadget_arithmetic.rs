@@ -0,0 +1,328 @@
+extern crate bulletproofs;
+extern crate curve25519_dalek;
+
+use bulletproofs::r1cs::{ConstraintSystem, R1CSError};
+use bulletproofs::r1cs::LinearCombination;
+use curve25519_dalek::scalar::Scalar;
+
+use crate::r1cs_utils::{AllocatedScalar, constrain_lc_with_scalar};
+
+/// Prove that `z = a*x + b*y` for committed `x`, `y` and `z` and public coefficients `a` and `b`. Needs no
+/// multipliers since the relation is linear.
+pub fn linear_combination_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    x: AllocatedScalar,
+    y: AllocatedScalar,
+    a: Scalar,
+    b: Scalar,
+    z: AllocatedScalar
+) -> Result<(), R1CSError> {
+    let lc: LinearCombination = x.variable * a + y.variable * b - z.variable;
+    constrain_lc_with_scalar(cs, lc, &Scalar::zero());
+    Ok(())
+}
+
+/// Prove that the sum of committed `inputs` is the public `total`. With no inputs, `total` must be 0.
+pub fn sum_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    inputs: Vec<AllocatedScalar>,
+    total: Scalar
+) -> Result<(), R1CSError> {
+    let sum: LinearCombination = inputs.iter().map(|i| (i.variable, Scalar::one())).collect();
+    constrain_lc_with_scalar(cs, sum, &total);
+    Ok(())
+}
+
+/// Prove that committed `(x, y)` is on the public line `y = a*x + b`. Multiplying by the public `a` is a scaling so
+/// this needs no multipliers. When `a` is 0, the line is constant and only `y = b` is constrained, `x` can be anything.
+pub fn on_line_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    x: AllocatedScalar,
+    y: AllocatedScalar,
+    a: Scalar,
+    b: Scalar
+) -> Result<(), R1CSError> {
+    let lc: LinearCombination = x.variable * a - y.variable;
+    constrain_lc_with_scalar(cs, lc, &-b);
+    Ok(())
+}
+
+/// Prove that committed `neg` is `-v` for committed `v`, i.e. `v + neg = 0`. Needs no multipliers.
+pub fn negation_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    v: AllocatedScalar,
+    neg: AllocatedScalar
+) -> Result<(), R1CSError> {
+    let sum: LinearCombination = v.variable + neg.variable;
+    constrain_lc_with_scalar(cs, sum, &Scalar::zero());
+    Ok(())
+}
+
+/// Prove that committed `z` is the dot product of committed `a` and `b`, i.e. `z = sum(a_i * b_i)`. Needs 1
+/// multiplier per element. The vectors must have the same length.
+pub fn dot_product_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    a: Vec<AllocatedScalar>,
+    b: Vec<AllocatedScalar>,
+    z: AllocatedScalar
+) -> Result<(), R1CSError> {
+    if a.len() != b.len() {
+        return Err(R1CSError::GadgetError {
+            description: format!("Vectors of the dot product should have the same length, found {} and {}", a.len(), b.len())
+        })
+    }
+    let mut sum = LinearCombination::default();
+    for (a_i, b_i) in a.iter().zip(b.iter()) {
+        let (_, _, o) = cs.multiply(a_i.variable.into(), b_i.variable.into());
+        sum = sum + o;
+    }
+    cs.constrain(sum - z.variable);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::r1cs::{Prover, Verifier};
+    use bulletproofs::{BulletproofGens, PedersenGens};
+    use curve25519_dalek::ristretto::CompressedRistretto;
+    use merlin::Transcript;
+
+    #[test]
+    fn test_linear_combination_gadget() {
+        let (x, y) = (Scalar::from(120u64), Scalar::from(35u64));
+        let (a, b) = (Scalar::from(3u64), -Scalar::from(2u64));
+        let z = a * x + b * y;
+        assert!(linear_combination_helper(x, y, a, b, z).is_ok());
+        // z is off by 1
+        assert!(linear_combination_helper(x, y, a, b, z + Scalar::one()).is_err());
+        // Coefficients swapped
+        assert!(linear_combination_helper(x, y, b, a, z).is_err());
+    }
+
+    fn linear_combination_helper(x: Scalar, y: Scalar, a: Scalar, b: Scalar, z: Scalar) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(8, 1);
+
+        let (proof, commitments) = {
+            let mut comms: Vec<CompressedRistretto> = vec![];
+
+            let mut prover_transcript = Transcript::new(b"LinearCombinationTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let mut allocs = vec![];
+            for v in &[x, y, z] {
+                let (com, var) = prover.commit(*v, Scalar::random(&mut rng));
+                comms.push(com);
+                allocs.push(AllocatedScalar::known(var, *v));
+            }
+
+            linear_combination_gadget(&mut prover, allocs[0], allocs[1], a, b, allocs[2])?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"LinearCombinationTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let allocs: Vec<AllocatedScalar> = commitments.iter().map(|c| AllocatedScalar::unknown(verifier.commit(*c))).collect();
+        linear_combination_gadget(&mut verifier, allocs[0], allocs[1], a, b, allocs[2])?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+
+    #[test]
+    fn test_sum_gadget() {
+        let inputs = [Scalar::from(10u64), Scalar::from(25u64), Scalar::from(7u64)];
+        assert!(sum_helper(&inputs, Scalar::from(42u64)).is_ok());
+        assert!(sum_helper(&inputs, Scalar::from(43u64)).is_err());
+        assert!(sum_helper(&inputs, Scalar::from(41u64)).is_err());
+        // No inputs
+        assert!(sum_helper(&[], Scalar::zero()).is_ok());
+        assert!(sum_helper(&[], Scalar::one()).is_err());
+    }
+
+    fn sum_helper(inputs: &[Scalar], total: Scalar) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(8, 1);
+
+        let (proof, commitments) = {
+            let mut comms: Vec<CompressedRistretto> = vec![];
+
+            let mut prover_transcript = Transcript::new(b"SumTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let mut allocs = vec![];
+            for v in inputs {
+                let (com, var) = prover.commit(*v, Scalar::random(&mut rng));
+                comms.push(com);
+                allocs.push(AllocatedScalar::known(var, *v));
+            }
+
+            sum_gadget(&mut prover, allocs, total)?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"SumTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let allocs = commitments.iter().map(|c| AllocatedScalar::unknown(verifier.commit(*c))).collect();
+        sum_gadget(&mut verifier, allocs, total)?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+
+    #[test]
+    fn test_on_line_gadget() {
+        let (a, b) = (Scalar::from(7u64), Scalar::from(11u64));
+        let x = Scalar::from(5u64);
+        assert!(on_line_helper(x, a * x + b, a, b).is_ok());
+        // Point off the line
+        assert!(on_line_helper(x, a * x + b + Scalar::one(), a, b).is_err());
+        assert!(on_line_helper(x + Scalar::one(), a * x + b, a, b).is_err());
+
+        // Constant line
+        let a = Scalar::zero();
+        assert!(on_line_helper(x, b, a, b).is_ok());
+        assert!(on_line_helper(Scalar::from(1000u64), b, a, b).is_ok());
+        assert!(on_line_helper(x, x, a, b).is_err());
+    }
+
+    fn on_line_helper(x: Scalar, y: Scalar, a: Scalar, b: Scalar) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(8, 1);
+
+        let (proof, commitments) = {
+            let mut comms: Vec<CompressedRistretto> = vec![];
+
+            let mut prover_transcript = Transcript::new(b"OnLineTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let mut allocs = vec![];
+            for v in &[x, y] {
+                let (com, var) = prover.commit(*v, Scalar::random(&mut rng));
+                comms.push(com);
+                allocs.push(AllocatedScalar::known(var, *v));
+            }
+
+            on_line_gadget(&mut prover, allocs[0], allocs[1], a, b)?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"OnLineTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let allocs: Vec<AllocatedScalar> = commitments.iter().map(|c| AllocatedScalar::unknown(verifier.commit(*c))).collect();
+        on_line_gadget(&mut verifier, allocs[0], allocs[1], a, b)?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+
+    #[test]
+    fn test_negation_gadget() {
+        let v = Scalar::from(12345u64);
+        assert!(negation_helper(v, -v).is_ok());
+        assert!(negation_helper(-v, v).is_ok());
+        assert!(negation_helper(v, v).is_err());
+        assert!(negation_helper(v, -v + Scalar::one()).is_err());
+        // 0 is its own negation
+        assert!(negation_helper(Scalar::zero(), Scalar::zero()).is_ok());
+        assert!(negation_helper(Scalar::zero(), Scalar::one()).is_err());
+        assert!(negation_helper(Scalar::zero(), -Scalar::one()).is_err());
+    }
+
+    fn negation_helper(v: Scalar, neg: Scalar) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(8, 1);
+
+        let (proof, commitments) = {
+            let mut comms: Vec<CompressedRistretto> = vec![];
+
+            let mut prover_transcript = Transcript::new(b"NegationTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let mut allocs = vec![];
+            for s in &[v, neg] {
+                let (com, var) = prover.commit(*s, Scalar::random(&mut rng));
+                comms.push(com);
+                allocs.push(AllocatedScalar::known(var, *s));
+            }
+
+            negation_gadget(&mut prover, allocs[0], allocs[1])?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"NegationTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let allocs: Vec<AllocatedScalar> = commitments.iter().map(|c| AllocatedScalar::unknown(verifier.commit(*c))).collect();
+        negation_gadget(&mut verifier, allocs[0], allocs[1])?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+
+    #[test]
+    fn test_dot_product_gadget() {
+        let a = [3u64, 5, 7];
+        let b = [2u64, 11, 4];
+        // 6 + 55 + 28
+        assert!(dot_product_helper(&a, &b, Scalar::from(89u64)).is_ok());
+        assert!(dot_product_helper(&a, &b, Scalar::from(90u64)).is_err());
+        assert!(dot_product_helper(&a, &b, Scalar::from(88u64)).is_err());
+        // Lengths differ
+        assert!(dot_product_helper(&a, &b[..2], Scalar::from(61u64)).is_err());
+    }
+
+    fn dot_product_helper(a: &[u64], b: &[u64], z: Scalar) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(8, 1);
+
+        let (proof, commitments) = {
+            let mut comms: Vec<CompressedRistretto> = vec![];
+
+            let mut prover_transcript = Transcript::new(b"DotProductTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let mut allocs = vec![];
+            for s in a.iter().chain(b.iter()).map(|v| Scalar::from(*v)).chain(std::iter::once(z)) {
+                let (com, var) = prover.commit(s, Scalar::random(&mut rng));
+                comms.push(com);
+                allocs.push(AllocatedScalar::known(var, s));
+            }
+            let z_alloc = allocs.pop().unwrap();
+            let b_allocs = allocs.split_off(a.len());
+
+            dot_product_gadget(&mut prover, allocs, b_allocs, z_alloc)?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"DotProductTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let mut allocs: Vec<AllocatedScalar> = commitments.iter().map(|c| AllocatedScalar::unknown(verifier.commit(*c))).collect();
+        let z_alloc = allocs.pop().unwrap();
+        let b_allocs = allocs.split_off(a.len());
+        dot_product_gadget(&mut verifier, allocs, b_allocs, z_alloc)?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+}
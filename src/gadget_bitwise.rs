@@ -0,0 +1,398 @@
+use bulletproofs::r1cs::{ConstraintSystem, R1CSError, R1CSProof, Variable, Prover, Verifier};
+use curve25519_dalek::scalar::Scalar;
+use bulletproofs::{BulletproofGens, PedersenGens};
+use curve25519_dalek::ristretto::CompressedRistretto;
+use bulletproofs::r1cs::LinearCombination;
+
+use crate::r1cs_utils::{AllocatedQuantity, AllocatedScalar, bit_decompose, constrain_lc_with_scalar};
+use crate::scalar_utils::scalar_to_u64;
+use crate::gadget_zero_nonzero::nonzero_gadget;
+
+/// Constrain `v` to be 0 or 1 by enforcing v * (1 - v) = 0
+pub fn boolean_gadget<CS: ConstraintSystem>(cs: &mut CS, v: Variable) -> Result<(), R1CSError> {
+    let (_, _, o) = cs.multiply(v.into(), LinearCombination::from(Scalar::one()) - v);
+    cs.constrain(o.into());
+    Ok(())
+}
+
+/// Constrain `a` and `b` to be bits and return a variable for `a XOR b` which is `a + b - 2*a*b`. For bits, this is
+/// `(a - b)^2` so the output of a single multiplication is the XOR.
+pub fn xor_gadget<CS: ConstraintSystem>(cs: &mut CS, a: Variable, b: Variable) -> Result<Variable, R1CSError> {
+    boolean_gadget(cs, a)?;
+    boolean_gadget(cs, b)?;
+
+    let (_, _, xor) = cs.multiply(a - b, a - b);
+
+    Ok(xor)
+}
+
+/// Decompose `v` into `n` bits and return the bit at `position`, 0 being the least significant bit. `n` is at
+/// most 64 and `v` must fit in `n` bits.
+pub fn bit_at_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    v: AllocatedScalar,
+    position: usize,
+    n: usize
+) -> Result<Variable, R1CSError> {
+    if position >= n || n > 64 {
+        return Err(R1CSError::GadgetError {
+            description: format!("Position {} should be less than the number of bits {} which is at most 64", position, n)
+        })
+    }
+    let bits = bit_decompose_scalar(cs, v, n)?;
+    Ok(bits[position])
+}
+
+/// Prove that `v` is a power of 2 by decomposing it into `n` bits, `n` at most 64, and constraining the sum of the
+/// bits to be 1. Fails for 0 since no bit is set.
+pub fn is_power_of_two_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    v: AllocatedScalar,
+    n: usize
+) -> Result<(), R1CSError> {
+    if n > 64 {
+        return Err(R1CSError::GadgetError {
+            description: format!("Number of bits {} should be at most 64", n)
+        })
+    }
+    let bits = bit_decompose_scalar(cs, v, n)?;
+    let sum: LinearCombination = bits.iter().map(|b| (*b, Scalar::one())).collect();
+    constrain_lc_with_scalar(cs, sum, &Scalar::one());
+    Ok(())
+}
+
+/// Prove that `v`, which fits in `n` bits, `n` at most 64, is not a power of 2, i.e. the number of set bits is
+/// not 1. This is done by proving `popcount - 1` is non-zero, which holds for 0 and more than 1 set bits. As the
+/// popcount is at most 64, `popcount - 1` cannot wrap around to 0. Note that `popcount * (popcount - 1)` being
+/// non-zero would also exclude 0.
+pub fn not_power_of_two_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    v: AllocatedScalar,
+    n: usize
+) -> Result<(), R1CSError> {
+    if n > 64 {
+        return Err(R1CSError::GadgetError {
+            description: format!("Number of bits {} should be at most 64", n)
+        })
+    }
+    let bits = bit_decompose_scalar(cs, v, n)?;
+    let popcount_minus_1: LinearCombination = bits.iter().map(|b| (*b, Scalar::one())).collect::<LinearCombination>() - Scalar::one();
+
+    let diff = cs.evaluate_lc(&popcount_minus_1);
+    let var_diff = cs.allocate(diff)?;
+    cs.constrain(popcount_minus_1 - var_diff);
+    nonzero_gadget(cs, AllocatedScalar::new(var_diff, diff))
+}
+
+/// Prove that `result` is the bitwise AND of `a` and `b` by decomposing all 3 into `n` bits, `n` at most 64, and
+/// constraining each bit of `result` to be the product of the bits of `a` and `b`. All 3 must fit in `n` bits.
+pub fn bitwise_and_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    a: AllocatedScalar,
+    b: AllocatedScalar,
+    result: AllocatedScalar,
+    n: usize
+) -> Result<(), R1CSError> {
+    if n > 64 {
+        return Err(R1CSError::GadgetError {
+            description: format!("Number of bits {} should be at most 64", n)
+        })
+    }
+    let a_bits = bit_decompose_scalar(cs, a, n)?;
+    let b_bits = bit_decompose_scalar(cs, b, n)?;
+    let result_bits = bit_decompose_scalar(cs, result, n)?;
+    for i in 0..n {
+        let (_, _, o) = cs.multiply(a_bits[i].into(), b_bits[i].into());
+        cs.constrain(o - result_bits[i]);
+    }
+    Ok(())
+}
+
+/// Prove that `v` fits in 64 bits, i.e. is the scalar of a `u64`, by decomposing it into exactly 64 bits
+pub fn is_u64_gadget<CS: ConstraintSystem>(cs: &mut CS, v: AllocatedScalar) -> Result<(), R1CSError> {
+    bit_decompose_scalar(cs, v, 64)?;
+    Ok(())
+}
+
+/// `bit_decompose` for a scalar which should fit in 64 bits
+fn bit_decompose_scalar<CS: ConstraintSystem>(cs: &mut CS, v: AllocatedScalar, n: usize) -> Result<Vec<Variable>, R1CSError> {
+    let assignment = match v.assignment {
+        Some(a) => Some(scalar_to_u64(&a).ok_or(R1CSError::GadgetError {
+            description: String::from("Value does not fit in 64 bits")
+        })?),
+        None => None
+    };
+    bit_decompose(cs, AllocatedQuantity { variable: v.variable, assignment }, n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use merlin::Transcript;
+
+    #[test]
+    fn test_xor_gadget() {
+        for (a, b) in &[(0u64, 0u64), (0, 1), (1, 0), (1, 1)] {
+            assert!(xor_gadget_helper(*a, *b, a ^ b).is_ok());
+            // Wrong output
+            assert!(xor_gadget_helper(*a, *b, 1 - (a ^ b)).is_err());
+        }
+
+        // 1 multiplier for each boolean constraint and 1 for the XOR
+        let pc_gens = PedersenGens::default();
+        let mut transcript = Transcript::new(b"XorTest");
+        let mut prover = Prover::new(&pc_gens, &mut transcript);
+        let (_, var_a) = prover.commit(Scalar::one(), Scalar::one());
+        let (_, var_b) = prover.commit(Scalar::zero(), Scalar::one());
+        xor_gadget(&mut prover, var_a, var_b).unwrap();
+        assert_eq!(prover.num_multipliers(), 3);
+    }
+
+    #[test]
+    fn test_xor_gadget_non_boolean() {
+        // 2 XOR 1 would be 2 + 1 - 2*2*1 = -1 and 2 XOR 0 would be 2 so both should be rejected
+        assert!(xor_gadget_helper(2, 1, 1).is_err());
+        assert!(xor_gadget_helper(2, 0, 0).is_err());
+        assert!(xor_gadget_helper(0, 2, 0).is_err());
+    }
+
+    // Prove that XOR of committed `a` and `b` is `expected`
+    fn xor_gadget_helper(a: u64, b: u64, expected: u64) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(16, 1);
+
+        let (proof, commitments) = {
+            let mut comms: Vec<CompressedRistretto> = vec![];
+
+            let mut prover_transcript = Transcript::new(b"XorTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com_a, var_a) = prover.commit(Scalar::from(a), Scalar::random(&mut rng));
+            let (com_b, var_b) = prover.commit(Scalar::from(b), Scalar::random(&mut rng));
+            comms.push(com_a);
+            comms.push(com_b);
+
+            let xor = xor_gadget(&mut prover, var_a, var_b)?;
+            constrain_lc_with_scalar(&mut prover, xor.into(), &Scalar::from(expected));
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"XorTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let var_a = verifier.commit(commitments[0]);
+        let var_b = verifier.commit(commitments[1]);
+
+        let xor = xor_gadget(&mut verifier, var_a, var_b)?;
+        constrain_lc_with_scalar(&mut verifier, xor.into(), &Scalar::from(expected));
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+
+    #[test]
+    fn test_bit_at_gadget() {
+        let v = 0b1011_0010u64;
+        for position in 0..8 {
+            let bit = (v >> position) & 1;
+            assert!(bit_at_gadget_helper(v, position, 8, bit).is_ok());
+            assert!(bit_at_gadget_helper(v, position, 8, 1 - bit).is_err());
+        }
+        // Position out of range
+        assert!(bit_at_gadget_helper(v, 8, 8, 0).is_err());
+        // Value needs more bits
+        assert!(bit_at_gadget_helper(v, 0, 4, 0).is_err());
+    }
+
+    // Prove that bit at `position` of committed `v` is `expected`
+    fn bit_at_gadget_helper(v: u64, position: usize, n: usize, expected: u64) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(16, 1);
+
+        let (proof, commitment) = {
+            let mut prover_transcript = Transcript::new(b"BitAtTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com_v, var_v) = prover.commit(Scalar::from(v), Scalar::random(&mut rng));
+
+            let bit = bit_at_gadget(&mut prover, AllocatedScalar::known(var_v, Scalar::from(v)), position, n)?;
+            constrain_lc_with_scalar(&mut prover, bit.into(), &Scalar::from(expected));
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, com_v)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"BitAtTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let var_v = AllocatedScalar::unknown(verifier.commit(commitment));
+        let bit = bit_at_gadget(&mut verifier, var_v, position, n)?;
+        constrain_lc_with_scalar(&mut verifier, bit.into(), &Scalar::from(expected));
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+
+    #[test]
+    fn test_is_power_of_two_gadget() {
+        for v in &[1u64, 2, 8, 128] {
+            assert!(is_power_of_two_helper(*v, 8).is_ok());
+        }
+        for v in &[0u64, 3, 6, 255] {
+            assert!(is_power_of_two_helper(*v, 8).is_err());
+        }
+        // Does not fit in the bits
+        assert!(is_power_of_two_helper(256, 8).is_err());
+    }
+
+    fn is_power_of_two_helper(v: u64, n: usize) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(16, 1);
+
+        let (proof, commitment) = {
+            let mut prover_transcript = Transcript::new(b"PowerOfTwoTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com_v, var_v) = prover.commit(Scalar::from(v), Scalar::random(&mut rng));
+            is_power_of_two_gadget(&mut prover, AllocatedScalar::known(var_v, Scalar::from(v)), n)?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, com_v)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"PowerOfTwoTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let var_v = AllocatedScalar::unknown(verifier.commit(commitment));
+        is_power_of_two_gadget(&mut verifier, var_v, n)?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+
+    #[test]
+    fn test_not_power_of_two_gadget() {
+        for v in &[0u64, 3, 6, 255] {
+            assert!(not_power_of_two_helper(*v, 8).is_ok());
+        }
+        for v in &[1u64, 2, 8, 128] {
+            assert!(not_power_of_two_helper(*v, 8).is_err());
+        }
+    }
+
+    fn not_power_of_two_helper(v: u64, n: usize) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(16, 1);
+
+        let (proof, commitment) = {
+            let mut prover_transcript = Transcript::new(b"NotPowerOfTwoTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com_v, var_v) = prover.commit(Scalar::from(v), Scalar::random(&mut rng));
+            not_power_of_two_gadget(&mut prover, AllocatedScalar::known(var_v, Scalar::from(v)), n)?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, com_v)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"NotPowerOfTwoTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let var_v = AllocatedScalar::unknown(verifier.commit(commitment));
+        not_power_of_two_gadget(&mut verifier, var_v, n)?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+
+    #[test]
+    fn test_bitwise_and_gadget() {
+        assert!(bitwise_and_helper(0b1100, 0b1010, 0b1000, 4).is_ok());
+        assert!(bitwise_and_helper(0b1111, 0b0110, 0b0110, 4).is_ok());
+        // Wrong result
+        assert!(bitwise_and_helper(0b1100, 0b1010, 0b1100, 4).is_err());
+        assert!(bitwise_and_helper(0b1100, 0b1010, 0b1110, 4).is_err());
+        // Inputs need more than 4 bits, even though the AND fits
+        assert!(bitwise_and_helper(0b11100, 0b1010, 0b1000, 4).is_err());
+        assert!(bitwise_and_helper(0b1100, 0b11010, 0b1000, 4).is_err());
+    }
+
+    fn bitwise_and_helper(a: u64, b: u64, result: u64, n: usize) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(16, 1);
+
+        let (proof, commitments) = {
+            let mut comms: Vec<CompressedRistretto> = vec![];
+
+            let mut prover_transcript = Transcript::new(b"BitwiseAndTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let mut allocs = vec![];
+            for v in &[a, b, result] {
+                let (com, var) = prover.commit(Scalar::from(*v), Scalar::random(&mut rng));
+                comms.push(com);
+                allocs.push(AllocatedScalar::known(var, Scalar::from(*v)));
+            }
+
+            bitwise_and_gadget(&mut prover, allocs[0], allocs[1], allocs[2], n)?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"BitwiseAndTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let allocs: Vec<AllocatedScalar> = commitments.iter().map(|c| AllocatedScalar::unknown(verifier.commit(*c))).collect();
+        bitwise_and_gadget(&mut verifier, allocs[0], allocs[1], allocs[2], n)?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+
+    #[test]
+    fn test_is_u64_gadget() {
+        let two_64 = Scalar::from(u64::MAX) + Scalar::one();
+        for v in &[0u64, 1, 12345, u64::MAX] {
+            assert!(is_u64_helper(Scalar::from(*v), Scalar::from(*v)).is_ok());
+        }
+        assert!(is_u64_helper(two_64, two_64).is_err());
+        assert!(is_u64_helper(-Scalar::one(), -Scalar::one()).is_err());
+        // Prover lies about the committed value to get bits
+        assert!(is_u64_helper(two_64, Scalar::zero()).is_err());
+        assert!(is_u64_helper(two_64, Scalar::from(u64::MAX)).is_err());
+    }
+
+    fn is_u64_helper(v: Scalar, assignment: Scalar) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(64, 1);
+
+        let (proof, commitment) = {
+            let mut prover_transcript = Transcript::new(b"IsU64Test");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com, var) = prover.commit(v, Scalar::random(&mut rng));
+            is_u64_gadget(&mut prover, AllocatedScalar::known(var, assignment))?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, com)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"IsU64Test");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let var = AllocatedScalar::unknown(verifier.commit(commitment));
+        is_u64_gadget(&mut verifier, var)?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+}
@@ -12,7 +12,8 @@ use merlin::Transcript;
 use rand::{RngCore, CryptoRng};
 use std::cmp;
 
-use crate::r1cs_utils::{AllocatedQuantity, positive_no_gadget, constrain_lc_with_scalar};
+use crate::r1cs_utils::{AllocatedQuantity, AllocatedScalar, positive_no_gadget, constrain_lc_with_scalar};
+use crate::scalar_utils::scalar_to_u64;
 
 
 pub fn bound_check_gadget<CS: ConstraintSystem>(
@@ -44,6 +45,70 @@ pub fn bound_check_gadget<CS: ConstraintSystem>(
     Ok(())
 }
 
+/// Prove `lo <= v <= hi` where the bounds are committed as well. `v - lo` and `hi - v` are allocated and each is
+/// constrained in [0, 2^n), `n` at most 64, so a negative difference (a large scalar) does not fit in `n` bits.
+pub fn committed_range_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    v: AllocatedScalar,
+    lo: AllocatedScalar,
+    hi: AllocatedScalar,
+    n: usize
+) -> Result<(), R1CSError> {
+    if n > 64 {
+        return Err(R1CSError::GadgetError {
+            description: format!("Number of bits {} should be at most 64", n)
+        })
+    }
+    for (larger, smaller) in &[(v, lo), (hi, v)] {
+        let diff = match (larger.assignment, smaller.assignment) {
+            (Some(l), Some(s)) => Some(l - s),
+            _ => None
+        };
+        let diff_var = cs.allocate(diff)?;
+        cs.constrain(larger.variable - smaller.variable - diff_var);
+
+        let assignment = match diff {
+            Some(d) => Some(scalar_to_u64(&d).ok_or(R1CSError::GadgetError {
+                description: String::from("Value is not within the bounds")
+            })?),
+            None => None
+        };
+        positive_no_gadget(cs, AllocatedQuantity { variable: diff_var, assignment }, n)?;
+    }
+    Ok(())
+}
+
+/// Prove `cur > prev` for committed `cur` and `prev` by constraining `cur - prev - 1` in [0, 2^n), `n` at most 64.
+/// Equal values give -1, a large scalar, which does not fit in `n` bits. `cur` can then exceed `prev` by at most 2^n.
+/// The comparison is over the field so the caller must range check `cur` and `prev`, e.g. a committed `prev = -1`
+/// with `cur = 0` passes.
+pub fn strictly_greater_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    cur: AllocatedScalar,
+    prev: AllocatedScalar,
+    n: usize
+) -> Result<(), R1CSError> {
+    if n > 64 {
+        return Err(R1CSError::GadgetError {
+            description: format!("Number of bits {} should be at most 64", n)
+        })
+    }
+    let diff = match (cur.assignment, prev.assignment) {
+        (Some(c), Some(p)) => Some(c - p - Scalar::one()),
+        _ => None
+    };
+    let diff_var = cs.allocate(diff)?;
+    cs.constrain(cur.variable - prev.variable - Scalar::one() - diff_var);
+
+    let assignment = match diff {
+        Some(d) => Some(scalar_to_u64(&d).ok_or(R1CSError::GadgetError {
+            description: String::from("Value is not greater than the previous value")
+        })?),
+        None => None
+    };
+    positive_no_gadget(cs, AllocatedQuantity { variable: diff_var, assignment }, n)
+}
+
 /// Accepts the num for which the bounds have to proved and optionally the randomness used in committing to that number.
 /// This randomness argument is accepted so that this can be used as a sub-protocol where the protocol on upper layer will create the commitment.
 pub fn gen_proof_of_bounded_num<R: RngCore + CryptoRng>(val: u64, randomness: Option<Scalar>, lower: u64, upper: u64,
@@ -169,4 +234,88 @@ mod tests {
         bound_check(min, max, bit_size);
     }
 
-}
\ No newline at end of file
+    fn committed_range(v: u64, lo: u64, hi: u64, n: usize) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(128, 1);
+        let label = b"CommittedRangeTest";
+
+        let values = [v, lo, hi];
+        let (proof, commitments) = {
+            let mut rng = rand::thread_rng();
+            let mut prover_transcript = Transcript::new(label);
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let mut comms = vec![];
+            let mut allocs = vec![];
+            for val in values.iter() {
+                let (com, var) = prover.commit(Scalar::from(*val), Scalar::random(&mut rng));
+                comms.push(com);
+                allocs.push(AllocatedScalar::known(var, Scalar::from(*val)));
+            }
+
+            committed_range_gadget(&mut prover, allocs[0], allocs[1], allocs[2], n)?;
+            (prover.prove(&bp_gens)?, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(label);
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let allocs: Vec<AllocatedScalar> = commitments.iter().map(|c| AllocatedScalar::unknown(verifier.commit(*c))).collect();
+
+        committed_range_gadget(&mut verifier, allocs[0], allocs[1], allocs[2], n)?;
+        verifier.verify(&proof, &pc_gens, &bp_gens)
+    }
+
+    #[test]
+    fn test_committed_range_gadget() {
+        assert!(committed_range(50, 10, 100, 8).is_ok());
+        assert!(committed_range(10, 10, 100, 8).is_ok());
+        assert!(committed_range(100, 10, 100, 8).is_ok());
+        // Below lo
+        assert!(committed_range(5, 10, 100, 8).is_err());
+        // Above hi
+        assert!(committed_range(101, 10, 100, 8).is_err());
+        // Too many bits
+        assert!(committed_range(50, 10, 100, 65).is_err());
+        assert!(committed_range(50, 10, 100, 253).is_err());
+    }
+
+    fn strictly_greater(cur: u64, prev: u64, n: usize) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(16, 1);
+        let label = b"StrictlyGreaterTest";
+
+        let (proof, commitments) = {
+            let mut rng = rand::thread_rng();
+            let mut prover_transcript = Transcript::new(label);
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com_c, var_c) = prover.commit(Scalar::from(cur), Scalar::random(&mut rng));
+            let (com_p, var_p) = prover.commit(Scalar::from(prev), Scalar::random(&mut rng));
+            strictly_greater_gadget(&mut prover, AllocatedScalar::known(var_c, Scalar::from(cur)), AllocatedScalar::known(var_p, Scalar::from(prev)), n)?;
+            (prover.prove(&bp_gens)?, vec![com_c, com_p])
+        };
+
+        let mut verifier_transcript = Transcript::new(label);
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let allocs: Vec<AllocatedScalar> = commitments.iter().map(|c| AllocatedScalar::unknown(verifier.commit(*c))).collect();
+
+        strictly_greater_gadget(&mut verifier, allocs[0], allocs[1], n)?;
+        verifier.verify(&proof, &pc_gens, &bp_gens)
+    }
+
+    #[test]
+    fn test_strictly_greater_gadget() {
+        assert!(strictly_greater(11, 10, 8).is_ok());
+        assert!(strictly_greater(100, 10, 8).is_ok());
+        // Largest difference that fits in 8 bits
+        assert!(strictly_greater(266, 10, 8).is_ok());
+        assert!(strictly_greater(10, 10, 8).is_err());
+        assert!(strictly_greater(9, 10, 8).is_err());
+        assert!(strictly_greater(0, 1, 8).is_err());
+        // Difference too large for the range bound
+        assert!(strictly_greater(267, 10, 8).is_err());
+        // -1 would fit in 253 bits
+        assert!(strictly_greater(11, 10, 65).is_err());
+        assert!(strictly_greater(10, 10, 253).is_err());
+    }
+}
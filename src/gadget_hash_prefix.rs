@@ -0,0 +1,139 @@
+extern crate bulletproofs;
+extern crate curve25519_dalek;
+
+use bulletproofs::r1cs::{ConstraintSystem, R1CSError};
+use bulletproofs::r1cs::LinearCombination;
+use curve25519_dalek::scalar::Scalar;
+
+use crate::r1cs_utils::AllocatedScalar;
+use crate::gadget_bitwise::boolean_gadget;
+use crate::gadget_poseidon::{PoseidonParams, SboxType, Poseidon_hash_2_constraints, constant_statics, check_width};
+use crate::scalar_utils::get_bits;
+
+/// Number of bits of a scalar, the group order is less than 2^253
+pub const SCALAR_BITS: usize = 253;
+
+/// Number of leading zero bits of `s` as a `SCALAR_BITS` bit number, the most significant bit is bit 252 of the
+/// little-endian encoding
+pub fn leading_zero_bits(s: &Scalar) -> usize {
+    let bits = get_bits(s, SCALAR_BITS);
+    bits.iter().rev().take_while(|b| **b == 0).count()
+}
+
+/// Prove knowledge of committed `preimage` and `nonce` such that `Poseidon_hash_2(preimage, nonce)` has at least
+/// `leading_zero_bits` leading zero bits. The hash is seen as a `SCALAR_BITS` bit number with the most significant bit
+/// first, so the top `k` bits being 0 means the hash is less than 2^(SCALAR_BITS - k). The hash is decomposed into its
+/// low `SCALAR_BITS - k` bits, least significant bit first, which must sum to the hash. The top bits are thus 0.
+pub fn hash_prefix_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    preimage: AllocatedScalar,
+    nonce: AllocatedScalar,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+    leading_zero_bits: usize
+) -> Result<(), R1CSError> {
+    if leading_zero_bits > SCALAR_BITS {
+        return Err(R1CSError::GadgetError {
+            description: format!("Cannot have {} leading zero bits in a {} bit hash", leading_zero_bits, SCALAR_BITS)
+        })
+    }
+
+    check_width(params, 3)?;
+    let statics = constant_statics(params.width - 2);
+    let hash = Poseidon_hash_2_constraints(cs, preimage.variable.into(), nonce.variable.into(), statics, params, sbox)?;
+
+    let num_bits = SCALAR_BITS - leading_zero_bits;
+    let hash_bits = match cs.evaluate_lc(&hash) {
+        Some(h) => {
+            let zeros = self::leading_zero_bits(&h);
+            if zeros < leading_zero_bits {
+                return Err(R1CSError::GadgetError {
+                    description: format!("Hash has {} leading zero bits but {} are needed", zeros, leading_zero_bits)
+                })
+            }
+            Some(get_bits(&h, num_bits))
+        }
+        None => None
+    };
+
+    let mut sum = LinearCombination::default();
+    let mut exp_2 = Scalar::one();
+    for i in 0..num_bits {
+        let bit = cs.allocate(hash_bits.as_ref().map(|b| Scalar::from(b[i] as u64)))?;
+        boolean_gadget(cs, bit)?;
+        sum = sum + bit * exp_2;
+        exp_2 = exp_2 + exp_2;
+    }
+    cs.constrain(sum - hash);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::r1cs::{Prover, Verifier};
+    use bulletproofs::{BulletproofGens, PedersenGens};
+    use curve25519_dalek::ristretto::CompressedRistretto;
+    use merlin::Transcript;
+    use crate::gadget_poseidon::Poseidon_hash_2;
+
+    #[test]
+    fn test_hash_prefix_gadget() {
+        let p_params = PoseidonParams::default();
+        let sbox = SboxType::Inverse;
+        let preimage = Scalar::from(42u64);
+        let difficulty = 4;
+
+        let hash_zeros = |nonce: u64| leading_zero_bits(&Poseidon_hash_2(preimage, Scalar::from(nonce), &p_params, &sbox).unwrap().into_scalar());
+        let good_nonce = (0u64..).find(|n| hash_zeros(*n) >= difficulty).unwrap();
+        let bad_nonce = (0u64..).find(|n| hash_zeros(*n) < difficulty).unwrap();
+
+        assert!(hash_prefix_helper(preimage, Scalar::from(good_nonce), difficulty, &p_params, &sbox).is_ok());
+        assert!(hash_prefix_helper(preimage, Scalar::from(bad_nonce), difficulty, &p_params, &sbox).is_err());
+        assert!(hash_prefix_helper(preimage, Scalar::from(bad_nonce), 0, &p_params, &sbox).is_ok());
+        assert!(hash_prefix_helper(preimage, Scalar::from(good_nonce), SCALAR_BITS + 1, &p_params, &sbox).is_err());
+    }
+
+    #[test]
+    fn test_leading_zero_bits() {
+        assert_eq!(leading_zero_bits(&Scalar::zero()), SCALAR_BITS);
+        assert_eq!(leading_zero_bits(&Scalar::one()), SCALAR_BITS - 1);
+        assert_eq!(leading_zero_bits(&Scalar::from(u64::max_value())), SCALAR_BITS - 64);
+        assert_eq!(leading_zero_bits(&-Scalar::one()), 0);
+    }
+
+    fn hash_prefix_helper(preimage: Scalar, nonce: Scalar, leading_zero_bits: usize, params: &PoseidonParams, sbox: &SboxType) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+
+        let (proof, commitments) = {
+            let mut comms: Vec<CompressedRistretto> = vec![];
+
+            let mut prover_transcript = Transcript::new(b"HashPrefixTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com_p, var_p) = prover.commit(preimage, Scalar::random(&mut rng));
+            comms.push(com_p);
+            let (com_n, var_n) = prover.commit(nonce, Scalar::random(&mut rng));
+            comms.push(com_n);
+
+            hash_prefix_gadget(&mut prover, AllocatedScalar::known(var_p, preimage), AllocatedScalar::known(var_n, nonce),
+                               params, sbox, leading_zero_bits)?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"HashPrefixTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let var_p = AllocatedScalar::unknown(verifier.commit(commitments[0]));
+        let var_n = AllocatedScalar::unknown(verifier.commit(commitments[1]));
+        hash_prefix_gadget(&mut verifier, var_p, var_n, params, sbox, leading_zero_bits)?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+}
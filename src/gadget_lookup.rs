@@ -0,0 +1,205 @@
+extern crate bulletproofs;
+extern crate curve25519_dalek;
+
+use bulletproofs::r1cs::{ConstraintSystem, R1CSError, Variable};
+use bulletproofs::r1cs::LinearCombination;
+use curve25519_dalek::scalar::Scalar;
+
+use crate::r1cs_utils::AllocatedScalar;
+use crate::gadget_bitwise::boolean_gadget;
+
+/// Prove that `v == table[index]` where `index_bits` are the bits of the index, least significant bit first.
+/// The table length must be `2^index_bits.len()`, pad the table if needed. `table[index]` is selected by a tree of
+/// multiplexers, each level picking between pairs of the previous level using 1 index bit.
+pub fn indexed_lookup_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    v: AllocatedScalar,
+    index_bits: Vec<Variable>,
+    table: &[Scalar]
+) -> Result<(), R1CSError> {
+    if index_bits.len() >= 64 || table.len() != 1 << index_bits.len() {
+        return Err(R1CSError::GadgetError {
+            description: format!("Table length {} should be 2^{}", table.len(), index_bits.len())
+        })
+    }
+
+    for b in index_bits.iter() {
+        boolean_gadget(cs, *b)?;
+    }
+
+    if index_bits.is_empty() {
+        cs.constrain(v.variable - table[0]);
+        return Ok(())
+    }
+
+    // a + b*(c - a) is a when b is 0 and c when b is 1. Table entries are constants so the first level needs no
+    // multiplications.
+    let mut level: Vec<LinearCombination> = table.chunks(2)
+        .map(|pair| LinearCombination::from(pair[0]) + index_bits[0] * (pair[1] - pair[0]))
+        .collect();
+    for b in index_bits[1..].iter() {
+        let mut next = vec![];
+        for pair in level.chunks(2) {
+            let (a, c) = (pair[0].clone(), pair[1].clone());
+            let (_, _, o) = cs.multiply((*b).into(), c - a.clone());
+            next.push(a + o);
+        }
+        level = next;
+    }
+
+    cs.constrain(level[0].clone() - v.variable);
+    Ok(())
+}
+
+/// Prove that `result == array[index]` where the array, `index_bits` and `result` are all committed. `index_bits` are
+/// the bits of the index, least significant bit first, and the array length must be `2^index_bits.len()`. Same
+/// multiplexer tree as `indexed_lookup_gadget` but every level, including the first, needs a multiplication.
+pub fn private_lookup_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    array: Vec<AllocatedScalar>,
+    index_bits: Vec<Variable>,
+    result: AllocatedScalar
+) -> Result<(), R1CSError> {
+    if index_bits.len() >= 64 || array.len() != 1 << index_bits.len() {
+        return Err(R1CSError::GadgetError {
+            description: format!("Array length {} should be 2^{}", array.len(), index_bits.len())
+        })
+    }
+
+    for b in index_bits.iter() {
+        boolean_gadget(cs, *b)?;
+    }
+
+    let mut level: Vec<LinearCombination> = array.iter().map(|a| a.variable.into()).collect();
+    for b in index_bits.iter() {
+        let mut next = vec![];
+        for pair in level.chunks(2) {
+            let (a, c) = (pair[0].clone(), pair[1].clone());
+            let (_, _, o) = cs.multiply((*b).into(), c - a.clone());
+            next.push(a + o);
+        }
+        level = next;
+    }
+
+    cs.constrain(level[0].clone() - result.variable);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::r1cs::{Prover, Verifier};
+    use bulletproofs::{BulletproofGens, PedersenGens};
+    use curve25519_dalek::ristretto::CompressedRistretto;
+    use merlin::Transcript;
+
+    #[test]
+    fn test_indexed_lookup_gadget() {
+        let table: Vec<Scalar> = (0..8u64).map(|i| Scalar::from(i * i + 7)).collect();
+        for index in 0..8 {
+            assert!(indexed_lookup_helper(table[index], index, 3, &table).is_ok());
+        }
+        // Value is at a different index
+        assert!(indexed_lookup_helper(table[2], 5, 3, &table).is_err());
+        // Value is not in the table
+        assert!(indexed_lookup_helper(Scalar::from(1000u64), 5, 3, &table).is_err());
+        // Table length is not a power of 2
+        assert!(indexed_lookup_helper(table[2], 2, 3, &table[..6]).is_err());
+    }
+
+    fn indexed_lookup_helper(v: Scalar, index: usize, num_bits: usize, table: &[Scalar]) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(32, 1);
+
+        let (proof, commitments) = {
+            let mut comms: Vec<CompressedRistretto> = vec![];
+
+            let mut prover_transcript = Transcript::new(b"IndexedLookupTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com_v, var_v) = prover.commit(v, Scalar::random(&mut rng));
+            comms.push(com_v);
+            let mut bits = vec![];
+            for i in 0..num_bits {
+                let (com, var) = prover.commit(Scalar::from(((index >> i) & 1) as u64), Scalar::random(&mut rng));
+                comms.push(com);
+                bits.push(var);
+            }
+
+            indexed_lookup_gadget(&mut prover, AllocatedScalar::known(var_v, v), bits, table)?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"IndexedLookupTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let var_v = AllocatedScalar::unknown(verifier.commit(commitments[0]));
+        let bits = commitments[1..].iter().map(|c| verifier.commit(*c)).collect();
+
+        indexed_lookup_gadget(&mut verifier, var_v, bits, table)?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+
+    #[test]
+    fn test_private_lookup_gadget() {
+        let array = vec![17u64, 3, 250, 42];
+        for index in 0..4 {
+            assert!(private_lookup_helper(&array, index, array[index]).is_ok());
+        }
+        // Result is another element of the array
+        assert!(private_lookup_helper(&array, 1, array[2]).is_err());
+        // Result is not in the array
+        assert!(private_lookup_helper(&array, 1, 4).is_err());
+    }
+
+    fn private_lookup_helper(array: &[u64], index: usize, result: u64) -> Result<(), R1CSError> {
+        let num_bits = 2;
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(32, 1);
+
+        let (proof, commitments) = {
+            let mut comms: Vec<CompressedRistretto> = vec![];
+
+            let mut prover_transcript = Transcript::new(b"PrivateLookupTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com_r, var_r) = prover.commit(Scalar::from(result), Scalar::random(&mut rng));
+            comms.push(com_r);
+            let mut array_alloc = vec![];
+            for a in array {
+                let (com, var) = prover.commit(Scalar::from(*a), Scalar::random(&mut rng));
+                comms.push(com);
+                array_alloc.push(AllocatedScalar::known(var, Scalar::from(*a)));
+            }
+            let mut bits = vec![];
+            for i in 0..num_bits {
+                let (com, var) = prover.commit(Scalar::from(((index >> i) & 1) as u64), Scalar::random(&mut rng));
+                comms.push(com);
+                bits.push(var);
+            }
+
+            private_lookup_gadget(&mut prover, array_alloc, bits, AllocatedScalar::known(var_r, Scalar::from(result)))?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"PrivateLookupTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let var_r = AllocatedScalar::unknown(verifier.commit(commitments[0]));
+        let array_alloc = commitments[1..array.len() + 1].iter().map(|c| AllocatedScalar::unknown(verifier.commit(*c))).collect();
+        let bits = commitments[array.len() + 1..].iter().map(|c| verifier.commit(*c)).collect();
+
+        private_lookup_gadget(&mut verifier, array_alloc, bits, var_r)?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+}
@@ -0,0 +1,434 @@
+extern crate bulletproofs;
+extern crate curve25519_dalek;
+
+use bulletproofs::r1cs::{ConstraintSystem, R1CSError, Variable};
+use bulletproofs::r1cs::LinearCombination;
+use curve25519_dalek::scalar::Scalar;
+
+use crate::r1cs_utils::{AllocatedScalar, constrain_lc_with_scalar};
+use crate::gadget_bitwise::boolean_gadget;
+use crate::gadget_poseidon::{PoseidonParams, Poseidon_hash_2, Poseidon_hash_2_constraints, SboxType, constant_statics, check_width, PoseidonError};
+
+/// Hash up a merkle path to get the root of a binary merkle tree. `path` has the sibling at each level, starting
+/// from the leaf, along with a bit which is true if the node is the right child, i.e. the sibling is on the left.
+/// Same as `VanillaSparseMerkleTree::verify_proof` where the bits come from the leaf index, least significant bit first.
+pub fn compute_merkle_root(leaf: Scalar, path: &[(Scalar, bool)], params: &PoseidonParams, sbox: &SboxType) -> Result<Scalar, PoseidonError> {
+    path.iter().try_fold(leaf, |cur_val, (sibling, is_right)| {
+        let hash = if *is_right {
+            Poseidon_hash_2(*sibling, cur_val, params, sbox)?
+        } else {
+            Poseidon_hash_2(cur_val, *sibling, params, sbox)?
+        };
+        Ok(hash.into_scalar())
+    })
+}
+
+/// Hashes of empty subtrees of each height from 0 to `depth`, so `depth + 1` hashes. Index 0 is `empty_leaf` and
+/// index `k + 1` is `Poseidon_hash_2` of 2 copies of index `k`.
+pub fn empty_subtree_hashes(depth: usize, empty_leaf: Scalar, params: &PoseidonParams, sbox: &SboxType) -> Result<Vec<Scalar>, PoseidonError> {
+    let mut hashes = vec![empty_leaf];
+    for i in 0..depth {
+        hashes.push(Poseidon_hash_2(hashes[i], hashes[i], params, sbox)?.into_scalar());
+    }
+    Ok(hashes)
+}
+
+/// Root of the binary merkle tree of `leaves`, hashing bottom-up. A level with an odd number of nodes (except the
+/// root) is padded by duplicating its last node, so 3 leaves `a, b, c` give `H(H(a, b), H(c, c))`. For a power of 2
+/// leaves, no padding is done and the root matches `compute_merkle_root` of any leaf. With the `parallel` feature,
+/// the nodes of each level are hashed in parallel. Panics if `leaves` is empty.
+pub fn build_merkle_root(leaves: &[Scalar], params: &PoseidonParams, sbox: &SboxType) -> Result<Scalar, PoseidonError> {
+    merkle_root(leaves, params, sbox, cfg!(feature = "parallel"))
+}
+
+fn merkle_root(leaves: &[Scalar], params: &PoseidonParams, sbox: &SboxType, parallel: bool) -> Result<Scalar, PoseidonError> {
+    assert!(!leaves.is_empty(), "Need at least 1 leaf");
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        if level.len() % 2 == 1 {
+            level.push(level[level.len() - 1]);
+        }
+        level = hash_level(&level, params, sbox, parallel)?;
+    }
+    Ok(level[0])
+}
+
+/// Hash each pair of nodes of an even sized level
+fn hash_level(level: &[Scalar], params: &PoseidonParams, sbox: &SboxType,
+              #[cfg_attr(not(feature = "parallel"), allow(unused_variables))] parallel: bool) -> Result<Vec<Scalar>, PoseidonError> {
+    let hash_pair = |c: &[Scalar]| Poseidon_hash_2(c[0], c[1], params, sbox).map(|h| h.into_scalar());
+    #[cfg(feature = "parallel")]
+    {
+        if parallel {
+            use rayon::prelude::*;
+            return level.par_chunks(2).map(hash_pair).collect()
+        }
+    }
+    level.chunks(2).map(hash_pair).collect()
+}
+
+/// Prove that each leaf is in the binary merkle tree with the public `root`. Each leaf has a path from the leaf to
+/// the root of the sibling at each level along with a bit which is 1 if the sibling is on the left, as in
+/// `compute_merkle_root`. All paths go in the same constraint system so a single proof covers them. The padding and
+/// zeroes of the hash are constants so they need no commitments.
+pub fn batch_membership_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    root: Scalar,
+    leaves: Vec<(AllocatedScalar, Vec<(AllocatedScalar, Variable)>)>,
+    params: &PoseidonParams,
+    sbox: &SboxType
+) -> Result<(), R1CSError> {
+    for (leaf, path) in leaves {
+        let mut cur: LinearCombination = leaf.variable.into();
+        for (sibling, is_right) in path {
+            boolean_gadget(cs, is_right)?;
+            cur = parent_constraints(cs, cur, sibling, is_right, params, sbox)?;
+        }
+        constrain_lc_with_scalar(cs, cur, &root);
+    }
+    Ok(())
+}
+
+/// Hash of `cur` and `sibling` in the order given by the bit `is_right`, which must already be constrained
+pub(crate) fn parent_constraints<CS: ConstraintSystem>(
+    cs: &mut CS,
+    cur: LinearCombination,
+    sibling: AllocatedScalar,
+    is_right: Variable,
+    params: &PoseidonParams,
+    sbox: &SboxType
+) -> Result<LinearCombination, R1CSError> {
+    check_width(params, 3)?;
+    // left = cur + is_right * (sibling - cur) and right = sibling + cur - left
+    let (_, _, o) = cs.multiply(is_right.into(), sibling.variable - cur.clone());
+    let left = cur.clone() + o;
+    let right = cur + sibling.variable - left.clone();
+
+    Poseidon_hash_2_constraints(cs, left, right, constant_statics(params.width - 2), params, sbox)
+}
+
+/// Prove that inserting `leaf` at an empty position changes the root of a binary merkle tree from `old_root` to
+/// `new_root`. An empty position has the leaf 0. `path` is as in `batch_membership_gadget` and the same siblings
+/// and bits hash up 0 to `old_root` and `leaf` to `new_root`, so only the leaf changed.
+pub fn merkle_update_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    leaf: AllocatedScalar,
+    path: Vec<(AllocatedScalar, Variable)>,
+    old_root: Scalar,
+    new_root: Scalar,
+    params: &PoseidonParams,
+    sbox: &SboxType
+) -> Result<(), R1CSError> {
+    let mut old_cur = LinearCombination::from(Scalar::zero());
+    let mut new_cur: LinearCombination = leaf.variable.into();
+    for (sibling, is_right) in path {
+        boolean_gadget(cs, is_right)?;
+        old_cur = parent_constraints(cs, old_cur, sibling, is_right, params, sbox)?;
+        new_cur = parent_constraints(cs, new_cur, sibling, is_right, params, sbox)?;
+    }
+    constrain_lc_with_scalar(cs, old_cur, &old_root);
+    constrain_lc_with_scalar(cs, new_cur, &new_root);
+    Ok(())
+}
+
+/// Prove that committed `left` and `right` are siblings with the committed `parent`, i.e.
+/// `Poseidon_hash_2(left, right) == parent`. The padding and zeroes of the hash are constants.
+pub fn siblings_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    left: AllocatedScalar,
+    right: AllocatedScalar,
+    parent: AllocatedScalar,
+    params: &PoseidonParams,
+    sbox: &SboxType
+) -> Result<(), R1CSError> {
+    check_width(params, 3)?;
+    let hash = Poseidon_hash_2_constraints(cs, left.variable.into(), right.variable.into(), constant_statics(params.width - 2), params, sbox)?;
+    cs.constrain(hash - parent.variable);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gadget_vsmt_2::{VanillaSparseMerkleTree, TreeDepth};
+    use crate::scalar_utils::ScalarBits;
+    use bulletproofs::r1cs::{Prover, Verifier};
+    use bulletproofs::{BulletproofGens, PedersenGens};
+    use merlin::Transcript;
+
+    #[test]
+    fn test_compute_merkle_root() {
+        let width = 6;
+        let (full_b, full_e) = (4, 4);
+        let partial_rounds = 6;
+        let p_params = PoseidonParams::new(width, full_b, full_e, partial_rounds);
+        let mut tree = VanillaSparseMerkleTree::new(&p_params);
+
+        for i in 1..10 {
+            let s = Scalar::from(i as u32);
+            tree.update(s, s);
+        }
+
+        for i in 1..10 {
+            let k = Scalar::from(i as u32);
+            let mut proof = Some(Vec::<Scalar>::new());
+            assert_eq!(k, tree.get(k, &mut proof));
+            let proof = proof.unwrap();
+
+            // Proof is from root to leaf but the path is from leaf to root
+            let bits = ScalarBits::from_scalar(&k, TreeDepth);
+            let path: Vec<(Scalar, bool)> = (0..TreeDepth).map(|j| (proof[TreeDepth - 1 - j], bits.bit_array[j] == 1)).collect();
+
+            assert_eq!(compute_merkle_root(k, &path, &p_params, &SboxType::Inverse).unwrap(), tree.root);
+            // Wrong leaf gives a different root
+            assert_ne!(compute_merkle_root(k + Scalar::one(), &path, &p_params, &SboxType::Inverse).unwrap(), tree.root);
+        }
+    }
+
+
+    // Levels of a binary merkle tree of the given leaves, from the leaves to the root
+    fn build_tree(leaves: Vec<Scalar>, params: &PoseidonParams, sbox: &SboxType) -> Vec<Vec<Scalar>> {
+        let mut levels = vec![leaves];
+        while levels.last().unwrap().len() > 1 {
+            let next = levels.last().unwrap().chunks(2).map(|c| Poseidon_hash_2(c[0], c[1], params, sbox).unwrap().into_scalar()).collect();
+            levels.push(next);
+        }
+        levels
+    }
+
+    fn get_path(levels: &[Vec<Scalar>], mut index: usize) -> Vec<(Scalar, bool)> {
+        let mut path = vec![];
+        for level in levels[..levels.len() - 1].iter() {
+            path.push((level[index ^ 1], index & 1 == 1));
+            index >>= 1;
+        }
+        path
+    }
+
+    fn batch_membership_helper(root: Scalar, leaves: Vec<(Scalar, Vec<(Scalar, bool)>)>, params: &PoseidonParams, sbox: &SboxType) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(8192, 1);
+        let label = b"BatchMembershipTest";
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(label);
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let mut comms = vec![];
+            let mut allocs = vec![];
+            for (leaf, path) in leaves.iter() {
+                let (com, var) = prover.commit(*leaf, Scalar::random(&mut rng));
+                comms.push(com);
+                let mut path_allocs = vec![];
+                for (sibling, is_right) in path {
+                    let (com_s, var_s) = prover.commit(*sibling, Scalar::random(&mut rng));
+                    let (com_b, var_b) = prover.commit(Scalar::from(*is_right as u64), Scalar::random(&mut rng));
+                    comms.push(com_s);
+                    comms.push(com_b);
+                    path_allocs.push((AllocatedScalar::known(var_s, *sibling), var_b));
+                }
+                allocs.push((AllocatedScalar::known(var, *leaf), path_allocs));
+            }
+
+            batch_membership_gadget(&mut prover, root, allocs, params, sbox)?;
+            (prover.prove(&bp_gens)?, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(label);
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let mut comms = commitments.iter();
+        let mut allocs = vec![];
+        for (_, path) in leaves.iter() {
+            let leaf = AllocatedScalar::unknown(verifier.commit(*comms.next().unwrap()));
+            let mut path_allocs = vec![];
+            for _ in path {
+                let sibling = AllocatedScalar::unknown(verifier.commit(*comms.next().unwrap()));
+                let is_right = verifier.commit(*comms.next().unwrap());
+                path_allocs.push((sibling, is_right));
+            }
+            allocs.push((leaf, path_allocs));
+        }
+
+        batch_membership_gadget(&mut verifier, root, allocs, params, sbox)?;
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+
+    #[test]
+    fn test_batch_membership_gadget() {
+        let p_params = PoseidonParams::new(6, 4, 4, 6);
+        let sbox = SboxType::Inverse;
+        let tree_leaves: Vec<Scalar> = (0..8u64).map(|i| Scalar::from(i + 100)).collect();
+        let levels = build_tree(tree_leaves.clone(), &p_params, &sbox);
+        let root = levels.last().unwrap()[0];
+
+        let indices = [0, 3, 5, 6];
+        let leaves: Vec<(Scalar, Vec<(Scalar, bool)>)> = indices.iter().map(|i| (tree_leaves[*i], get_path(&levels, *i))).collect();
+        for (leaf, path) in leaves.iter() {
+            assert_eq!(compute_merkle_root(*leaf, path, &p_params, &sbox).unwrap(), root);
+        }
+        assert!(batch_membership_helper(root, leaves.clone(), &p_params, &sbox).is_ok());
+
+        // One of the four paths is for a leaf not in the tree
+        let mut invalid = leaves.clone();
+        invalid[2].0 = Scalar::from(1000u64);
+        assert!(batch_membership_helper(root, invalid, &p_params, &sbox).is_err());
+    }
+
+
+    fn siblings_helper(left: Scalar, right: Scalar, parent: Scalar, params: &PoseidonParams, sbox: &SboxType) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let label = b"SiblingsTest";
+
+        let values = [left, right, parent];
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(label);
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let mut comms = vec![];
+            let mut allocs = vec![];
+            for v in values.iter() {
+                let (com, var) = prover.commit(*v, Scalar::random(&mut rng));
+                comms.push(com);
+                allocs.push(AllocatedScalar::known(var, *v));
+            }
+
+            siblings_gadget(&mut prover, allocs[0], allocs[1], allocs[2], params, sbox)?;
+            (prover.prove(&bp_gens)?, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(label);
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let allocs: Vec<AllocatedScalar> = commitments.iter().map(|c| AllocatedScalar::unknown(verifier.commit(*c))).collect();
+
+        siblings_gadget(&mut verifier, allocs[0], allocs[1], allocs[2], params, sbox)?;
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+
+    #[test]
+    fn test_siblings_gadget() {
+        let p_params = PoseidonParams::new(6, 4, 4, 6);
+        let sbox = SboxType::Inverse;
+        let tree_leaves: Vec<Scalar> = (0..4u64).map(|i| Scalar::from(i + 100)).collect();
+        let levels = build_tree(tree_leaves.clone(), &p_params, &sbox);
+
+        assert!(siblings_helper(tree_leaves[2], tree_leaves[3], levels[1][1], &p_params, &sbox).is_ok());
+        // Not siblings
+        assert!(siblings_helper(tree_leaves[1], tree_leaves[2], levels[1][1], &p_params, &sbox).is_err());
+        // Siblings in the wrong order
+        assert!(siblings_helper(tree_leaves[3], tree_leaves[2], levels[1][1], &p_params, &sbox).is_err());
+    }
+
+    #[test]
+    fn test_build_merkle_root() {
+        let p_params = PoseidonParams::new(6, 4, 4, 6);
+        let sbox = SboxType::Inverse;
+
+        let tree_leaves: Vec<Scalar> = (0..8u64).map(|i| Scalar::from(i + 100)).collect();
+        let levels = build_tree(tree_leaves.clone(), &p_params, &sbox);
+        assert_eq!(build_merkle_root(&tree_leaves, &p_params, &sbox).unwrap(), levels[3][0]);
+        assert_eq!(build_merkle_root(&tree_leaves[..1], &p_params, &sbox).unwrap(), tree_leaves[0]);
+
+        // Last leaf is duplicated
+        let (a, b, c) = (tree_leaves[0], tree_leaves[1], tree_leaves[2]);
+        let hash = |l: Scalar, r: Scalar| Poseidon_hash_2(l, r, &p_params, &sbox).unwrap().into_scalar();
+        assert_eq!(build_merkle_root(&[a, b, c], &p_params, &sbox).unwrap(), hash(hash(a, b), hash(c, c)));
+
+        let mut leaves: Vec<Scalar> = (0..1000u64).map(Scalar::from).collect();
+        let root = merkle_root(&leaves, &p_params, &sbox, false).unwrap();
+        assert_eq!(build_merkle_root(&leaves, &p_params, &sbox).unwrap(), root);
+        #[cfg(feature = "parallel")]
+        assert_eq!(merkle_root(&leaves, &p_params, &sbox, true).unwrap(), root);
+
+        leaves[567] += Scalar::one();
+        assert_ne!(build_merkle_root(&leaves, &p_params, &sbox).unwrap(), root);
+    }
+
+    fn merkle_update_helper(leaf: Scalar, path: &[(Scalar, bool)], old_root: Scalar, new_root: Scalar,
+                            params: &PoseidonParams, sbox: &SboxType) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let label = b"MerkleUpdateTest";
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(label);
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com, var) = prover.commit(leaf, Scalar::random(&mut rng));
+            let mut comms = vec![com];
+            let mut path_allocs = vec![];
+            for (sibling, is_right) in path {
+                let (com_s, var_s) = prover.commit(*sibling, Scalar::random(&mut rng));
+                let (com_b, var_b) = prover.commit(Scalar::from(*is_right as u64), Scalar::random(&mut rng));
+                comms.push(com_s);
+                comms.push(com_b);
+                path_allocs.push((AllocatedScalar::known(var_s, *sibling), var_b));
+            }
+
+            merkle_update_gadget(&mut prover, AllocatedScalar::known(var, leaf), path_allocs, old_root, new_root, params, sbox)?;
+            (prover.prove(&bp_gens)?, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(label);
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let leaf = AllocatedScalar::unknown(verifier.commit(commitments[0]));
+        let path_allocs = commitments[1..].chunks(2)
+            .map(|c| (AllocatedScalar::unknown(verifier.commit(c[0])), verifier.commit(c[1])))
+            .collect();
+
+        merkle_update_gadget(&mut verifier, leaf, path_allocs, old_root, new_root, params, sbox)?;
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+
+    #[test]
+    fn test_merkle_update_gadget() {
+        let p_params = PoseidonParams::new(6, 4, 4, 6);
+        let sbox = SboxType::Inverse;
+        // Positions 5 to 7 are empty
+        let mut tree_leaves: Vec<Scalar> = (0..8u64).map(|i| if i < 5 { Scalar::from(i + 100) } else { Scalar::zero() }).collect();
+        let old_levels = build_tree(tree_leaves.clone(), &p_params, &sbox);
+        let old_root = old_levels.last().unwrap()[0];
+
+        let leaf = Scalar::from(1000u64);
+        tree_leaves[5] = leaf;
+        let new_levels = build_tree(tree_leaves.clone(), &p_params, &sbox);
+        let new_root = new_levels.last().unwrap()[0];
+        // Siblings do not change with the insertion
+        let path = get_path(&old_levels, 5);
+        assert_eq!(path, get_path(&new_levels, 5));
+
+        assert!(merkle_update_helper(leaf, &path, old_root, new_root, &p_params, &sbox).is_ok());
+        // Wrong new root
+        assert!(merkle_update_helper(leaf, &path, old_root, old_root, &p_params, &sbox).is_err());
+        assert!(merkle_update_helper(leaf + Scalar::one(), &path, old_root, new_root, &p_params, &sbox).is_err());
+        // Position 3 is not empty
+        let path_3 = get_path(&new_levels, 3);
+        let mut replaced = tree_leaves.clone();
+        replaced[3] = leaf;
+        let replaced_root = build_tree(replaced, &p_params, &sbox).last().unwrap()[0];
+        assert!(merkle_update_helper(leaf, &path_3, new_root, replaced_root, &p_params, &sbox).is_err());
+    }
+
+    #[test]
+    fn test_empty_subtree_hashes() {
+        let p_params = PoseidonParams::new(6, 4, 4, 6);
+        let sbox = SboxType::Inverse;
+        let empty_leaf = Scalar::from(7u64);
+
+        let hashes = empty_subtree_hashes(4, empty_leaf, &p_params, &sbox).unwrap();
+        assert_eq!(hashes.len(), 5);
+        assert_eq!(hashes[0], empty_leaf);
+        for k in 0..4 {
+            assert_eq!(hashes[k + 1], Poseidon_hash_2(hashes[k], hashes[k], &p_params, &sbox).unwrap().into_scalar());
+        }
+        assert_eq!(hashes[4], build_merkle_root(&vec![empty_leaf; 16], &p_params, &sbox).unwrap());
+        assert_eq!(empty_subtree_hashes(0, empty_leaf, &p_params, &sbox).unwrap(), vec![empty_leaf]);
+
+        // Root of an empty sparse merkle tree
+        let tree = VanillaSparseMerkleTree::new(&p_params);
+        assert_eq!(empty_subtree_hashes(TreeDepth, Scalar::zero(), &p_params, &sbox).unwrap()[TreeDepth], tree.root);
+    }
+}
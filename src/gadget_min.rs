@@ -0,0 +1,112 @@
+extern crate bulletproofs;
+extern crate curve25519_dalek;
+
+use bulletproofs::r1cs::{ConstraintSystem, R1CSError, Variable};
+use bulletproofs::r1cs::LinearCombination;
+
+use crate::r1cs_utils::{AllocatedQuantity, AllocatedScalar, positive_no_gadget};
+use crate::scalar_utils::scalar_to_u64;
+
+/// Prove that `candidate` is the minimum of `others`, i.e. `candidate` is one of `others` and for each of `others`,
+/// `other - candidate` is in [0, 2^n), `n` at most 64, so a negative difference (a large scalar) does not fit in
+/// `n` bits.
+pub fn is_min_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    candidate: AllocatedScalar,
+    others: Vec<AllocatedScalar>,
+    n: usize
+) -> Result<(), R1CSError> {
+    if n > 64 {
+        return Err(R1CSError::GadgetError {
+            description: format!("Number of bits {} should be at most 64", n)
+        })
+    }
+    // Product of candidate - other_i is 0 when candidate is one of others
+    let mut product: LinearCombination = Variable::One().into();
+
+    for other in others.iter() {
+        let diff = match (other.assignment, candidate.assignment) {
+            (Some(o), Some(c)) => Some(o - c),
+            _ => None
+        };
+        let diff_var = cs.allocate(diff)?;
+        cs.constrain(other.variable - candidate.variable - diff_var);
+
+        let assignment = match diff {
+            Some(d) => Some(scalar_to_u64(&d).ok_or(R1CSError::GadgetError {
+                description: String::from("Candidate is greater than one of the values")
+            })?),
+            None => None
+        };
+        positive_no_gadget(cs, AllocatedQuantity { variable: diff_var, assignment }, n)?;
+
+        let (_, _, o) = cs.multiply(product, candidate.variable - other.variable);
+        product = o.into();
+    }
+
+    cs.constrain(product);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::r1cs::{Prover, Verifier};
+    use bulletproofs::{BulletproofGens, PedersenGens};
+    use curve25519_dalek::scalar::Scalar;
+    use curve25519_dalek::ristretto::CompressedRistretto;
+    use merlin::Transcript;
+
+    #[test]
+    fn test_is_min_gadget() {
+        let bids = vec![45u64, 12, 30, 99];
+        assert!(is_min_gadget_helper(12, &bids, 8).is_ok());
+        // Not the smallest
+        assert!(is_min_gadget_helper(30, &bids, 8).is_err());
+        // Smaller than all but not one of them
+        assert!(is_min_gadget_helper(10, &bids, 8).is_err());
+        // Too many bits
+        assert!(is_min_gadget_helper(12, &bids, 253).is_err());
+    }
+
+    fn is_min_gadget_helper(candidate: u64, others: &[u64], n: usize) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(128, 1);
+
+        let (proof, commitments) = {
+            let mut comms: Vec<CompressedRistretto> = vec![];
+
+            let mut prover_transcript = Transcript::new(b"IsMinTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com_c, var_c) = prover.commit(Scalar::from(candidate), Scalar::random(&mut rng));
+            comms.push(com_c);
+            let candidate = AllocatedScalar::known(var_c, Scalar::from(candidate));
+
+            let mut others_alloc = vec![];
+            for o in others {
+                let (com, var) = prover.commit(Scalar::from(*o), Scalar::random(&mut rng));
+                comms.push(com);
+                others_alloc.push(AllocatedScalar::known(var, Scalar::from(*o)));
+            }
+
+            is_min_gadget(&mut prover, candidate, others_alloc, n)?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"IsMinTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let candidate = AllocatedScalar::unknown(verifier.commit(commitments[0]));
+        let others_alloc = commitments[1..].iter().map(|c| AllocatedScalar::unknown(verifier.commit(*c))).collect();
+
+        is_min_gadget(&mut verifier, candidate, others_alloc, n)?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+}
@@ -0,0 +1,186 @@
+extern crate bulletproofs;
+extern crate curve25519_dalek;
+
+use bulletproofs::r1cs::{ConstraintSystem, R1CSError};
+use curve25519_dalek::scalar::Scalar;
+
+use crate::r1cs_utils::{AllocatedQuantity, AllocatedScalar, positive_no_gadget};
+use crate::scalar_utils::scalar_to_u64;
+
+/// Prove that `v mod m = r` for public `m` and `r < m` by allocating the quotient `q` and constraining
+/// `v = q*m + r`. Over the field, any `v` has such a `q` (`(v - r)/m`), so `q` is range checked to `n` bits, `n` at most
+/// 64. Then `q*m + r < 2^64 * 2^64 + 2^64` is less than the group order and `q` is the integer quotient.
+pub fn mod_residue_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    v: AllocatedScalar,
+    m: u64,
+    r: u64,
+    n: usize
+) -> Result<(), R1CSError> {
+    if m == 0 || r >= m || n > 64 {
+        return Err(R1CSError::GadgetError {
+            description: format!("Need 0 <= r < m and at most 64 bits for the quotient, found m={} r={} and {} bits", m, r, n)
+        })
+    }
+
+    let q = match v.assignment {
+        Some(a) => {
+            let val = scalar_to_u64(&a).ok_or(R1CSError::GadgetError {
+                description: String::from("Value does not fit in 64 bits")
+            })?;
+            if val % m != r {
+                return Err(R1CSError::GadgetError {
+                    description: format!("Value mod {} is {} and not {}", m, val % m, r)
+                })
+            }
+            Some(val / m)
+        }
+        None => None
+    };
+    let q_var = cs.allocate(q.map(Scalar::from))?;
+    cs.constrain(v.variable - q_var * Scalar::from(m) - Scalar::from(r));
+    positive_no_gadget(cs, AllocatedQuantity { variable: q_var, assignment: q }, n)?;
+
+    Ok(())
+}
+
+/// Prove that committed `q` is `floor(v / d)` for committed `v` and public `d`, by allocating the remainder `r`
+/// and constraining `v = q*d + r` with `0 <= r < d`. Both `r` and `d - 1 - r` are range checked to the bit length of
+/// `d - 1`, which bounds `r`, and `q` is range checked to `n` bits, `n` at most 64, so as in `mod_residue_gadget`,
+/// `q*d + r` does not wrap around the group order.
+pub fn integer_div_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    v: AllocatedScalar,
+    d: u64,
+    q: AllocatedScalar,
+    n: usize
+) -> Result<(), R1CSError> {
+    if d == 0 || n > 64 {
+        return Err(R1CSError::GadgetError {
+            description: format!("Need a non-zero divisor and at most 64 bits for the quotient, found d={} and {} bits", d, n)
+        })
+    }
+
+    let to_u64 = |a: Scalar| scalar_to_u64(&a).ok_or(R1CSError::GadgetError {
+        description: String::from("Value does not fit in 64 bits")
+    });
+    let (q_val, r_val) = match (v.assignment, q.assignment) {
+        (Some(v_a), Some(q_a)) => {
+            let (v_val, q_val) = (to_u64(v_a)?, to_u64(q_a)?);
+            if v_val / d != q_val {
+                return Err(R1CSError::GadgetError {
+                    description: format!("Quotient of {} by {} is {} and not {}", v_val, d, v_val / d, q_val)
+                })
+            }
+            (Some(q_val), Some(v_val % d))
+        }
+        _ => (None, None)
+    };
+
+    let r_var = cs.allocate(r_val.map(Scalar::from))?;
+    cs.constrain(v.variable - q.variable * Scalar::from(d) - r_var);
+
+    let r_bits = (64 - (d - 1).leading_zeros()) as usize;
+    positive_no_gadget(cs, AllocatedQuantity { variable: r_var, assignment: r_val }, r_bits)?;
+    let gap_val = r_val.map(|r| d - 1 - r);
+    let gap_var = cs.allocate(gap_val.map(Scalar::from))?;
+    cs.constrain(gap_var + r_var - Scalar::from(d - 1));
+    positive_no_gadget(cs, AllocatedQuantity { variable: gap_var, assignment: gap_val }, r_bits)?;
+
+    positive_no_gadget(cs, AllocatedQuantity { variable: q.variable, assignment: q_val }, n)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::r1cs::{Prover, Verifier};
+    use bulletproofs::{BulletproofGens, PedersenGens};
+    use merlin::Transcript;
+
+    #[test]
+    fn test_mod_residue_gadget() {
+        assert!(mod_residue_helper(103, 10, 3, 8).is_ok());
+        assert!(mod_residue_helper(7, 10, 7, 8).is_ok());
+        // Wrong residue
+        assert!(mod_residue_helper(103, 10, 4, 8).is_err());
+        // Residue not less than modulus
+        assert!(mod_residue_helper(103, 10, 13, 8).is_err());
+        // Quotient 10 needs more than 3 bits
+        assert!(mod_residue_helper(103, 10, 3, 3).is_err());
+    }
+
+    fn mod_residue_helper(v: u64, m: u64, r: u64, n: usize) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(16, 1);
+
+        let (proof, commitment) = {
+            let mut prover_transcript = Transcript::new(b"ModResidueTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com_v, var_v) = prover.commit(Scalar::from(v), Scalar::random(&mut rng));
+            mod_residue_gadget(&mut prover, AllocatedScalar::known(var_v, Scalar::from(v)), m, r, n)?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, com_v)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"ModResidueTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let var_v = AllocatedScalar::unknown(verifier.commit(commitment));
+        mod_residue_gadget(&mut verifier, var_v, m, r, n)?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+
+    #[test]
+    fn test_integer_div_gadget() {
+        // Exact
+        assert!(integer_div_helper(100, 10, 10, 8).is_ok());
+        assert!(integer_div_helper(0, 7, 0, 8).is_ok());
+        // Non-exact, remainders 3 and 6
+        assert!(integer_div_helper(103, 10, 10, 8).is_ok());
+        assert!(integer_div_helper(34, 7, 4, 8).is_ok());
+        // Divisor 1 has only remainder 0
+        assert!(integer_div_helper(34, 1, 34, 8).is_ok());
+        assert!(integer_div_helper(u64::MAX, 3, u64::MAX / 3, 64).is_ok());
+        // Wrong quotient
+        assert!(integer_div_helper(103, 10, 9, 8).is_err());
+        assert!(integer_div_helper(103, 10, 11, 8).is_err());
+        // Quotient 10 needs more than 3 bits
+        assert!(integer_div_helper(103, 10, 10, 3).is_err());
+        assert!(integer_div_helper(103, 0, 10, 8).is_err());
+    }
+
+    fn integer_div_helper(v: u64, d: u64, q: u64, n: usize) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(256, 1);
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"IntegerDivTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com_v, var_v) = prover.commit(Scalar::from(v), Scalar::random(&mut rng));
+            let (com_q, var_q) = prover.commit(Scalar::from(q), Scalar::random(&mut rng));
+            integer_div_gadget(&mut prover, AllocatedScalar::known(var_v, Scalar::from(v)), d, AllocatedScalar::known(var_q, Scalar::from(q)), n)?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, (com_v, com_q))
+        };
+
+        let mut verifier_transcript = Transcript::new(b"IntegerDivTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let var_v = AllocatedScalar::unknown(verifier.commit(commitments.0));
+        let var_q = AllocatedScalar::unknown(verifier.commit(commitments.1));
+        integer_div_gadget(&mut verifier, var_v, d, var_q, n)?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+}
@@ -25,6 +25,32 @@ pub fn not_equals_gadget<CS: ConstraintSystem>(
     Ok(())
 }
 
+/// Ensure `values` are pairwise distinct by proving `values[i] - values[j]` is non-zero for each pair. The
+/// differences and their inverses are allocated so `k` values need `k*(k-1)/2` non-zero checks, i.e. the number of
+/// constraints grows quadratically in `k`.
+pub fn all_distinct_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    values: Vec<AllocatedScalar>
+) -> Result<(), R1CSError> {
+    for i in 0..values.len() {
+        for j in i+1..values.len() {
+            let diff = match (values[i].assignment, values[j].assignment) {
+                (Some(a), Some(b)) => Some(a - b),
+                _ => None
+            };
+            let (var_diff, var_diff_inv, _) = cs.allocate_multiplier(diff.map(|d| (d, d.invert())))?;
+            cs.constrain(values[i].variable - values[j].variable - var_diff);
+
+            is_nonzero_gadget(
+                cs,
+                AllocatedScalar::new(var_diff, diff),
+                AllocatedScalar::new(var_diff_inv, diff.map(|d| d.invert()))
+            )?;
+        }
+    }
+
+    Ok(())
+}
 
 #[cfg(test)]
 mod tests {
@@ -108,4 +134,46 @@ mod tests {
 
         Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_all_distinct_gadget() {
+        assert!(all_distinct_gadget_helper(&[3, 17, 5, 100]).is_ok());
+        assert!(all_distinct_gadget_helper(&[3]).is_ok());
+        // 2 values are equal
+        assert!(all_distinct_gadget_helper(&[3, 17, 5, 17]).is_err());
+    }
+
+    fn all_distinct_gadget_helper(vals: &[u64]) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(128, 1);
+
+        let (proof, commitments) = {
+            let mut comms: Vec<CompressedRistretto> = vec![];
+
+            let mut prover_transcript = Transcript::new(b"AllDistinctTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let mut allocs = vec![];
+            for v in vals {
+                let (com, var) = prover.commit(Scalar::from(*v), Scalar::random(&mut rng));
+                comms.push(com);
+                allocs.push(AllocatedScalar::known(var, Scalar::from(*v)));
+            }
+
+            all_distinct_gadget(&mut prover, allocs)?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"AllDistinctTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let allocs = commitments.iter().map(|c| AllocatedScalar::unknown(verifier.commit(*c))).collect();
+        all_distinct_gadget(&mut verifier, allocs)?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+}
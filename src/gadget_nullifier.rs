@@ -0,0 +1,79 @@
+extern crate bulletproofs;
+extern crate curve25519_dalek;
+
+use bulletproofs::r1cs::{ConstraintSystem, R1CSError};
+use curve25519_dalek::scalar::Scalar;
+
+use crate::r1cs_utils::AllocatedScalar;
+use crate::gadget_poseidon::{PoseidonParams, SboxType, PoseidonError};
+use crate::gadget_prf::{compute_prf, prf_gadget};
+
+/// Nullifier of `secret` in `domain`, which is `Poseidon_hash_2(secret, domain)`, the PRF of the domain with the secret as key
+pub fn compute_nullifier(secret: Scalar, domain: Scalar, params: &PoseidonParams, sbox: &SboxType) -> Result<Scalar, PoseidonError> {
+    compute_prf(secret, domain, params, sbox)
+}
+
+/// Prove that the nullifier of committed `secret` in the public `domain` is `expected`. The domain, padding and
+/// zeroes are constants in the circuit.
+pub fn nullifier_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    secret: AllocatedScalar,
+    domain: Scalar,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+    expected: &Scalar
+) -> Result<(), R1CSError> {
+    prf_gadget(cs, secret, domain, params, sbox, expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::r1cs::{Prover, Verifier};
+    use bulletproofs::{BulletproofGens, PedersenGens};
+    use merlin::Transcript;
+
+    #[test]
+    fn test_nullifier_gadget() {
+        let p_params = PoseidonParams::default();
+        let sbox = SboxType::Inverse;
+        let secret = Scalar::from(12345u64);
+        let (domain_1, domain_2) = (Scalar::from(1u64), Scalar::from(2u64));
+
+        let nullifier_1 = compute_nullifier(secret, domain_1, &p_params, &sbox).unwrap();
+        let nullifier_2 = compute_nullifier(secret, domain_2, &p_params, &sbox).unwrap();
+        assert_eq!(nullifier_1, compute_nullifier(secret, domain_1, &p_params, &sbox).unwrap());
+        assert_ne!(nullifier_1, nullifier_2);
+
+        assert!(nullifier_gadget_helper(secret, domain_1, &nullifier_1, &p_params, &sbox).is_ok());
+        assert!(nullifier_gadget_helper(secret, domain_2, &nullifier_2, &p_params, &sbox).is_ok());
+        // Nullifier of another domain
+        assert!(nullifier_gadget_helper(secret, domain_1, &nullifier_2, &p_params, &sbox).is_err());
+    }
+
+    fn nullifier_gadget_helper(secret: Scalar, domain: Scalar, expected: &Scalar, params: &PoseidonParams, sbox: &SboxType) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+
+        let (proof, commitment) = {
+            let mut prover_transcript = Transcript::new(b"NullifierTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com_s, var_s) = prover.commit(secret, Scalar::random(&mut rng));
+            nullifier_gadget(&mut prover, AllocatedScalar::known(var_s, secret), domain, params, sbox, expected)?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, com_s)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"NullifierTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let var_s = AllocatedScalar::unknown(verifier.commit(commitment));
+        nullifier_gadget(&mut verifier, var_s, domain, params, sbox, expected)?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+}
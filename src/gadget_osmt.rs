@@ -17,7 +17,7 @@ use crate::scalar_utils::{ScalarBytes, ScalarBits, get_bits, scalar_to_u64_array
 use crate::r1cs_utils::AllocatedScalar;
 //use crate::gadget_mimc::{mimc, MIMC_ROUNDS};
 use crate::gadget_poseidon::{PoseidonParams, Poseidon_hash_2, Poseidon_hash_2_constraints, Poseidon_hash_2_gadget, SboxType,
-                             allocate_statics_for_prover, allocate_statics_for_verifier};
+                             allocate_statics_for_prover, allocate_statics_for_verifier, check_width};
 use curve25519_dalek::constants::BASEPOINT_ORDER;
 
 // DBVal.0 is not None when DBVal is a single key value, i.e. DBVal.1 is a key and DBVal.2 is a value.
@@ -38,7 +38,11 @@ pub struct OptmzSparseMerkleTree<'a> {
 }
 
 impl<'a> OptmzSparseMerkleTree<'a> {
+    /// Panics if the width of `hash_params` is less than 3
     pub fn new(hash_params: &'a PoseidonParams, depth: usize) -> OptmzSparseMerkleTree<'a> {
+        if let Err(e) = check_width(hash_params, 3) {
+            panic!("{}", e);
+        }
         let depth = depth;
         let mut db = HashMap::new();
         let mut empty_tree_hashes: Vec<Scalar> = vec![];
@@ -46,7 +50,7 @@ impl<'a> OptmzSparseMerkleTree<'a> {
         for _ in 0..depth {
             let prev = empty_tree_hashes[0];
             //let new = mimc(&prev, &prev, hash_constants);
-            let new = Poseidon_hash_2(prev.clone(), prev.clone(), hash_params, &SboxType::Inverse);
+            let new = Poseidon_hash_2(prev.clone(), prev.clone(), hash_params, &SboxType::Inverse).unwrap().into_scalar();
             empty_tree_hashes.insert(0, new);
         }
 
@@ -133,7 +137,7 @@ impl<'a> OptmzSparseMerkleTree<'a> {
                 }
             } else {
                 //let expected_hash = mimc(&proof_node.1, &proof_node.2, self.hash_constants);
-                let expected_hash = Poseidon_hash_2(proof_node.1.clone(), proof_node.2.clone(), self.hash_params, &SboxType::Inverse);
+                let expected_hash = Poseidon_hash_2(proof_node.1.clone(), proof_node.2.clone(), self.hash_params, &SboxType::Inverse).unwrap().into_scalar();
                 if expected_hash != prev_hash {
                     return false
                 }
@@ -176,13 +180,13 @@ impl<'a> OptmzSparseMerkleTree<'a> {
                 if path.is_msb_set() {
                     let new_right = self._update(&new_path, val, &child.2, depth+1);
                     //let root = mimc(&child.1, &new_right, self.hash_constants);
-                    let root = Poseidon_hash_2(child.1.clone(), new_right.clone(), self.hash_params, &SboxType::Inverse);
+                    let root = Poseidon_hash_2(child.1.clone(), new_right.clone(), self.hash_params, &SboxType::Inverse).unwrap().into_scalar();
                     self.update_db(&root, (None, child.1.clone(), new_right));
                     root
                 } else {
                     let new_left = self._update(&new_path, val, &child.1, depth+1);
                     //let root = mimc(&new_left, &child.2, self.hash_constants);
-                    let root = Poseidon_hash_2(new_left.clone(), child.2.clone(), self.hash_params, &SboxType::Inverse);
+                    let root = Poseidon_hash_2(new_left.clone(), child.2.clone(), self.hash_params, &SboxType::Inverse).unwrap().into_scalar();
                     self.update_db(&root, (None, new_left, child.2.clone()));
                     root
                 }
@@ -230,7 +234,7 @@ impl<'a> OptmzSparseMerkleTree<'a> {
         };
 
         //let root = mimc(&left, &right, self.hash_constants);
-        let root = Poseidon_hash_2(left.clone(), right.clone(), self.hash_params, &SboxType::Inverse);
+        let root = Poseidon_hash_2(left.clone(), right.clone(), self.hash_params, &SboxType::Inverse).unwrap().into_scalar();
         self.update_db(&root, (None, left, right));
         root
     }
@@ -251,7 +255,7 @@ impl<'a> OptmzSparseMerkleTree<'a> {
                  self.empty_tree_hashes[depth+1].clone())
             }
         };
-        Poseidon_hash_2(l.clone(), r.clone(), self.hash_params, &SboxType::Inverse)
+        Poseidon_hash_2(l.clone(), r.clone(), self.hash_params, &SboxType::Inverse).unwrap().into_scalar()
     }
 
     fn update_db(&mut self, key: &Scalar, val: DBVal) {
@@ -11,19 +11,135 @@ use bulletproofs::r1cs::{ConstraintSystem, R1CSError, R1CSProof, Variable, Prove
 use bulletproofs::{BulletproofGens, PedersenGens};
 use merlin::Transcript;
 use bulletproofs::r1cs::LinearCombination;
+use rand::{RngCore, CryptoRng};
 
 use crate::r1cs_utils::{AllocatedScalar, constrain_lc_with_scalar};
 use crate::gadget_zero_nonzero::is_nonzero_gadget;
 use crate::poseidon_constants::{MDS_ENTRIES, ROUND_CONSTS};
-use crate::scalar_utils::get_scalar_from_hex;
+#[cfg(feature = "generated-constants")]
+use crate::poseidon_grain::GrainLfsr;
+use crate::scalar_utils::{get_scalar_from_hex, get_hex_from_scalar, derive_blinding};
+use serde_derive::{Serialize, Deserialize};
 use rand::SeedableRng;
 use rand::rngs::StdRng;
 use curve25519_dalek::ristretto::CompressedRistretto;
 
+use subtle::{Choice, ConstantTimeEq};
 use std::mem;
+use std::ops::Deref;
+use std::fmt;
 use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+/// Security level (in bits) the round numbers are checked against
+pub const SECURITY_LEVEL: usize = 128;
+
+/// Number of bits needed to represent an element of the Ristretto scalar field
+pub const SCALAR_FIELD_BITS: usize = 253;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PoseidonError {
+    /// The configured rounds are fewer than the minimum needed for `SECURITY_LEVEL` bits of security
+    InsufficientRounds {
+        full_rounds: usize,
+        min_full_rounds: usize,
+        partial_rounds: usize,
+        min_partial_rounds: usize
+    },
+    /// The parameters could not be deserialized
+    InvalidSerialization(String),
+    /// No parameters for this width
+    UnsupportedWidth(usize),
+    /// The sponge state does not match the parameters
+    InvalidSpongeState(String),
+    /// More inputs than can be hashed with the permutation's width
+    TooManyInputs {
+        width: usize,
+        max_inputs: usize,
+        found: usize
+    },
+    /// The width is too small for the hash function
+    WidthTooSmall {
+        width: usize,
+        min_width: usize
+    },
+    /// Fewer round keys than needed for the rounds
+    NotEnoughRoundKeys {
+        needed: usize,
+        found: usize
+    },
+    /// More rounds than the configured limit
+    TooManyRounds {
+        rounds: usize,
+        max_rounds: usize
+    },
+    /// Partial rounds should apply the S-box to between 1 and `width` elements
+    InvalidPartialSboxCount {
+        count: usize,
+        width: usize
+    },
+    /// The round keys or MDS matrix do not match the width and rounds. `found_mds_columns` is the length of the
+    /// first row of the wrong length, or the width if all rows have it.
+    InconsistentSizes {
+        width: usize,
+        expected_round_keys: usize,
+        found_round_keys: usize,
+        found_mds_rows: usize,
+        found_mds_columns: usize
+    },
+}
+
+impl fmt::Display for PoseidonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoseidonError::InsufficientRounds { full_rounds, min_full_rounds, partial_rounds, min_partial_rounds } => {
+                write!(f, "Insecure round numbers, need at least {} full and {} partial rounds, found {} full and {} partial rounds",
+                       min_full_rounds, min_partial_rounds, full_rounds, partial_rounds)
+            }
+            PoseidonError::InvalidSerialization(e) => write!(f, "Invalid serialized parameters: {}", e),
+            PoseidonError::UnsupportedWidth(w) => write!(f, "No Poseidon parameters for width {}", w),
+            PoseidonError::InvalidSpongeState(e) => write!(f, "Invalid sponge state: {}", e),
+            PoseidonError::TooManyInputs { width, max_inputs, found } => {
+                write!(f, "Width {} can hash at most {} inputs, found {}", width, max_inputs, found)
+            }
+            PoseidonError::WidthTooSmall { width, min_width } => {
+                write!(f, "Width should be at least {}, found {}", min_width, width)
+            }
+            PoseidonError::NotEnoughRoundKeys { needed, found } => {
+                write!(f, "Need {} round keys, found {}", needed, found)
+            }
+            PoseidonError::TooManyRounds { rounds, max_rounds } => {
+                write!(f, "At most {} rounds are allowed, found {}", max_rounds, rounds)
+            }
+            PoseidonError::InvalidPartialSboxCount { count, width } => {
+                write!(f, "Partial rounds can apply the S-box to 1 to {} elements, found {}", width, count)
+            }
+            PoseidonError::InconsistentSizes { width, expected_round_keys, found_round_keys, found_mds_rows, found_mds_columns } => {
+                write!(f, "Need {} round keys and a {}x{} MDS matrix, found {} round keys and a {}x{} MDS matrix",
+                       expected_round_keys, width, width, found_round_keys, found_mds_rows, found_mds_columns)
+            }
+        }
+    }
+}
 
-// TODO: Add serialization with serde
+impl std::error::Error for PoseidonError {}
+
+impl From<PoseidonError> for R1CSError {
+    fn from(e: PoseidonError) -> R1CSError {
+        R1CSError::GadgetError { description: e.to_string() }
+    }
+}
+
+/// Error if the width of `params` is less than `min_width`. Do this before computing the number of statics, like
+/// `width - 2`, so a small width is an error and not an underflow.
+pub(crate) fn check_width(params: &PoseidonParams, min_width: usize) -> Result<(), PoseidonError> {
+    if params.width < min_width {
+        return Err(PoseidonError::WidthTooSmall { width: params.width, min_width })
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct PoseidonParams {
     pub width: usize,
     // Number of full SBox rounds in beginning
@@ -33,13 +149,93 @@ pub struct PoseidonParams {
     // Number of partial SBox rounds in beginning
     pub partial_rounds: usize,
     pub round_keys: Vec<Scalar>,
-    pub MDS_matrix: Vec<Vec<Scalar>>
+    pub MDS_matrix: Vec<Vec<Scalar>>,
+    /// Number of elements, the last ones of the state, going through the S-box in a partial round. Standard
+    /// Poseidon has 1, set with `with_partial_sbox_count`.
+    #[serde(default = "default_partial_sbox_count")]
+    pub partial_sbox_count: usize,
+    /// When set, the partial rounds of `Poseidon_permutation` and `Poseidon_permutation_constraints` use sparse matrices. Not serialized,
+    /// call `use_sparse_partial_rounds` after deserializing.
+    #[serde(skip)]
+    pub sparse_partial_rounds: Option<SparsePartialRounds>
+}
+
+fn default_partial_sbox_count() -> usize {
+    1
+}
+
+/// Sparse matrix `[[I, col], [row, diag]]` where `I` is the identity matrix of size `width - 1`, `col` is a column,
+/// `row` is a row and `diag` is the last diagonal element. Multiplying a state by it needs about `2*width`
+/// operations rather than `width^2`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SparseMDS {
+    pub row: Vec<Scalar>,
+    pub col: Vec<Scalar>,
+    pub diag: Scalar
+}
+
+impl SparseMDS {
+    pub fn apply(&self, state: &[Scalar]) -> Vec<Scalar> {
+        let width = state.len();
+        let last = state[width - 1];
+        let mut new_last = self.diag * last;
+        let mut new_state = vec![];
+        for i in 0..width - 1 {
+            new_last += self.row[i] * state[i];
+            new_state.push(state[i] + self.col[i] * last);
+        }
+        new_state.push(new_last);
+        new_state
+    }
+
+    /// Same as `apply` but for linear combinations. No constraints are needed since the result is linear in the state.
+    pub fn apply_sparse(&self, state: Vec<LinearCombination>) -> Vec<LinearCombination> {
+        let width = state.len();
+        let last = state[width - 1].clone();
+        let mut new_last = last.clone() * self.diag;
+        let mut new_state = vec![];
+        for (i, s) in state.into_iter().take(width - 1).enumerate() {
+            new_last = new_last + s.clone() * self.row[i];
+            new_state.push(simplify_lc(s + last.clone() * self.col[i]));
+        }
+        new_state.push(simplify_lc(new_last));
+        new_state
+    }
+}
+
+/// Equivalent form of the partial rounds where the round constants, except the one for the S-box, are moved
+/// out of the partial rounds and the MDS matrix of each partial round is factored into a sparse matrix and a
+/// matrix that is moved to the previous round. The partial rounds then become
+/// 1. Multiply the state by `pre_matrix`
+/// 2. For each partial round `i`, add `round_constants[i]` to the last element, apply the S-box to it and multiply by `sparse_matrices[i]`
+/// 3. Add `post_constants` to the state
+/// See appendix B of the Poseidon paper.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SparsePartialRounds {
+    pub pre_matrix: Vec<Vec<Scalar>>,
+    pub round_constants: Vec<Scalar>,
+    pub sparse_matrices: Vec<SparseMDS>,
+    pub post_constants: Vec<Scalar>
+}
+
+/// `PoseidonParams` with scalars as hex strings, used by `to_hex_json` and `from_hex_json`
+#[derive(Serialize, Deserialize)]
+struct PoseidonParamsHex {
+    width: usize,
+    full_rounds_beginning: usize,
+    full_rounds_end: usize,
+    partial_rounds: usize,
+    #[serde(default = "default_partial_sbox_count")]
+    partial_sbox_count: usize,
+    round_keys: Vec<String>,
+    MDS_matrix: Vec<Vec<String>>
 }
 
 impl PoseidonParams {
+    /// Round keys and MDS matrix come from the tables in `poseidon_constants`. With the `generated-constants`
+    /// feature, they are generated by the Grain LFSR and as a Cauchy matrix instead, which changes every hash.
     pub fn new(width: usize, full_rounds_beginning: usize, full_rounds_end: usize, partial_rounds: usize) -> PoseidonParams {
-        let total_rounds = full_rounds_beginning + partial_rounds + full_rounds_end;
-        let round_keys = Self::gen_round_keys(width, total_rounds);
+        let round_keys = Self::gen_round_keys(width, full_rounds_beginning + full_rounds_end, partial_rounds);
         let matrix_2 = Self::gen_MDS_matrix(width);
         PoseidonParams {
             width,
@@ -47,13 +243,305 @@ impl PoseidonParams {
             full_rounds_end,
             partial_rounds,
             round_keys,
-            MDS_matrix: matrix_2
+            MDS_matrix: matrix_2,
+            partial_sbox_count: 1,
+            sparse_partial_rounds: None
+        }
+    }
+
+    /// Parameters with the given round keys and MDS matrix, such as hand edited constants. Fails if they do not match
+    /// the width and rounds, see `validate`.
+    pub fn from_parts(width: usize, full_rounds_beginning: usize, full_rounds_end: usize, partial_rounds: usize,
+                      round_keys: Vec<Scalar>, MDS_matrix: Vec<Vec<Scalar>>) -> Result<PoseidonParams, PoseidonError> {
+        let params = PoseidonParams {
+            width,
+            full_rounds_beginning,
+            full_rounds_end,
+            partial_rounds,
+            round_keys,
+            MDS_matrix,
+            partial_sbox_count: 1,
+            sparse_partial_rounds: None
+        };
+        params.validate()?;
+        Ok(params)
+    }
+
+    /// Check that there are `width` round keys for each round and that the MDS matrix is `width x width`, so the
+    /// permutation cannot index out of bounds
+    pub fn validate(&self) -> Result<(), PoseidonError> {
+        let width = self.width;
+        let expected_round_keys = self.get_total_rounds() * width;
+        let found_mds_columns = self.MDS_matrix.iter().map(|r| r.len()).find(|l| *l != width).unwrap_or(width);
+        if self.round_keys.len() != expected_round_keys || self.MDS_matrix.len() != width || found_mds_columns != width {
+            return Err(PoseidonError::InconsistentSizes {
+                width,
+                expected_round_keys,
+                found_round_keys: self.round_keys.len(),
+                found_mds_rows: self.MDS_matrix.len(),
+                found_mds_columns
+            })
+        }
+        Ok(())
+    }
+
+    /// Same as `new` but fails if there are more than `max_rounds` rounds in total or not enough round constants
+    /// for them, rather than generating huge parameters or panicking. Use for parameters from untrusted sources.
+    pub fn with_limits(width: usize, full_rounds_beginning: usize, full_rounds_end: usize, partial_rounds: usize,
+                       max_rounds: usize) -> Result<PoseidonParams, PoseidonError> {
+        let rounds = full_rounds_beginning.saturating_add(full_rounds_end).saturating_add(partial_rounds);
+        if rounds > max_rounds {
+            return Err(PoseidonError::TooManyRounds { rounds, max_rounds })
+        }
+        let needed = rounds * width;
+        if cfg!(not(feature = "generated-constants")) && needed > ROUND_CONSTS.len() {
+            return Err(PoseidonError::NotEnoughRoundKeys { needed, found: ROUND_CONSTS.len() })
+        }
+        Ok(Self::new(width, full_rounds_beginning, full_rounds_end, partial_rounds))
+    }
+
+    /// Same as `new` but the partial rounds of the permutation and its constraints use sparse matrices
+    pub fn new_optimized(width: usize, full_rounds_beginning: usize, full_rounds_end: usize, partial_rounds: usize) -> PoseidonParams {
+        let mut params = Self::new(width, full_rounds_beginning, full_rounds_end, partial_rounds);
+        params.use_sparse_partial_rounds();
+        params
+    }
+
+    /// Same parameters but with `partial_rounds` partial rounds. The round keys are a prefix of the current round keys
+    /// so this gives the same parameters as `PoseidonParams::new` with `partial_rounds`. Fails if more round keys
+    /// are needed than the current ones. With the `generated-constants` feature, the round keys depend on the
+    /// number of rounds so they are generated again and this never fails.
+    pub fn with_partial_rounds(&self, partial_rounds: usize) -> Result<PoseidonParams, PoseidonError> {
+        self.with_rounds(self.full_rounds_beginning, self.full_rounds_end, partial_rounds)
+    }
+
+    /// Same parameters for the S-box `target`, with the round numbers of `recommended_rounds` for it. The full rounds
+    /// are split evenly between the beginning and end, with the extra one in the beginning. The round keys are taken
+    /// as in `with_partial_rounds` so this fails if more round keys are needed than the current ones.
+    pub fn convert_sbox(&self, target: SboxType, field_bits: usize) -> Result<PoseidonParams, PoseidonError> {
+        let (full_rounds, partial_rounds) = recommended_rounds(self.width, &target, field_bits);
+        self.with_rounds((full_rounds + 1) / 2, full_rounds / 2, partial_rounds)
+    }
+
+    fn with_rounds(&self, full_rounds_beginning: usize, full_rounds_end: usize, partial_rounds: usize) -> Result<PoseidonParams, PoseidonError> {
+        #[cfg(feature = "generated-constants")]
+        let round_keys = Self::gen_round_keys(self.width, full_rounds_beginning + full_rounds_end, partial_rounds);
+        #[cfg(not(feature = "generated-constants"))]
+        let round_keys = {
+            let needed = (full_rounds_beginning + partial_rounds + full_rounds_end) * self.width;
+            if needed > self.round_keys.len() {
+                return Err(PoseidonError::NotEnoughRoundKeys { needed, found: self.round_keys.len() })
+            }
+            self.round_keys[..needed].to_vec()
+        };
+        let mut params = PoseidonParams {
+            width: self.width,
+            full_rounds_beginning,
+            full_rounds_end,
+            partial_rounds,
+            round_keys,
+            MDS_matrix: self.MDS_matrix.clone(),
+            partial_sbox_count: self.partial_sbox_count,
+            sparse_partial_rounds: None
+        };
+        if self.sparse_partial_rounds.is_some() {
+            params.use_sparse_partial_rounds();
+        }
+        Ok(params)
+    }
+
+    /// Same parameters but partial rounds apply the S-box to the last `count` elements of the state. With
+    /// `count == width`, partial rounds are the same as full rounds. Drops the sparse partial rounds as they need
+    /// a single S-box per round.
+    pub fn with_partial_sbox_count(mut self, count: usize) -> Result<PoseidonParams, PoseidonError> {
+        if count == 0 || count > self.width {
+            return Err(PoseidonError::InvalidPartialSboxCount { count, width: self.width })
+        }
+        self.partial_sbox_count = count;
+        if count != 1 {
+            self.sparse_partial_rounds = None;
+        }
+        Ok(self)
+    }
+
+    /// Total number of S-boxes in a permutation
+    pub fn num_sboxes(&self) -> usize {
+        (self.full_rounds_beginning + self.full_rounds_end) * self.width + self.partial_rounds * self.partial_sbox_count
+    }
+
+    /// Compute the sparse form of the partial rounds and use it in the permutation constraints
+    pub fn use_sparse_partial_rounds(&mut self) {
+        self.sparse_partial_rounds = Some(self.gen_sparse_partial_rounds());
+    }
+
+    /// Panics if partial rounds apply more than 1 S-box
+    pub fn gen_sparse_partial_rounds(&self) -> SparsePartialRounds {
+        assert_eq!(self.partial_sbox_count, 1, "Sparse partial rounds need a single S-box per partial round");
+        let width = self.width;
+        let offset = self.full_rounds_beginning * width;
+
+        // Move the round constants of elements not going through the S-box to the next round. After the
+        // last partial round, they are added to the state.
+        let mut round_constants = vec![];
+        let mut carry = vec![Scalar::zero(); width];
+        for r in 0..self.partial_rounds {
+            let mut consts: Vec<Scalar> = (0..width).map(|i| self.round_keys[offset + r * width + i] + carry[i]).collect();
+            round_constants.push(consts[width - 1]);
+            consts[width - 1] = Scalar::zero();
+            carry = mat_vec_mul(&self.MDS_matrix, &consts);
+        }
+
+        // Starting from the last partial round, write the round's matrix as `M'' * M'` where `M''` is sparse and
+        // `M' = [[A, 0], [0, 1]]`. As `M'` does not touch the last element, it commutes with the S-box and is moved to
+        // the previous round whose matrix becomes `M' * MDS`.
+        let mut sparse_matrices = vec![];
+        let mut pre_matrix = identity_matrix(width);
+        let mut matrix = self.MDS_matrix.clone();
+        for _ in 0..self.partial_rounds {
+            let a: Vec<Vec<Scalar>> = matrix[..width - 1].iter().map(|r| r[..width - 1].to_vec()).collect();
+            // Any square submatrix of a Cauchy matrix is invertible
+            let a_inv = mat_inverse(&a).expect("Submatrix of MDS matrix should be invertible");
+            let col = (0..width - 1).map(|i| matrix[i][width - 1]).collect();
+            let c = matrix[width - 1][..width - 1].to_vec();
+            let row = (0..width - 1).map(|j| (0..width - 1).fold(Scalar::zero(), |sum, k| sum + c[k] * a_inv[k][j])).collect();
+            sparse_matrices.push(SparseMDS {
+                row,
+                col,
+                diag: matrix[width - 1][width - 1]
+            });
+
+            pre_matrix = identity_matrix(width);
+            for i in 0..width - 1 {
+                for j in 0..width - 1 {
+                    pre_matrix[i][j] = a[i][j];
+                }
+            }
+            matrix = mat_mul(&pre_matrix, &self.MDS_matrix);
+        }
+        sparse_matrices.reverse();
+
+        SparsePartialRounds {
+            pre_matrix,
+            round_constants,
+            sparse_matrices,
+            post_constants: carry
+        }
+    }
+
+    /// Serialize as JSON with each scalar as a "0x" prefixed big-endian hex string, same as in the
+    /// constant tables, so that a parameter file can be read and edited by hand.
+    pub fn to_hex_json(&self) -> String {
+        let hex_params = PoseidonParamsHex {
+            width: self.width,
+            full_rounds_beginning: self.full_rounds_beginning,
+            full_rounds_end: self.full_rounds_end,
+            partial_rounds: self.partial_rounds,
+            partial_sbox_count: self.partial_sbox_count,
+            round_keys: self.round_keys.iter().map(|k| get_hex_from_scalar(k)).collect(),
+            MDS_matrix: self.MDS_matrix.iter().map(|r| r.iter().map(|e| get_hex_from_scalar(e)).collect()).collect()
+        };
+        // Serializing strings and integers cannot fail
+        serde_json::to_string_pretty(&hex_params).unwrap()
+    }
+
+    /// Inverse of `to_hex_json`
+    pub fn from_hex_json(json: &str) -> Result<PoseidonParams, PoseidonError> {
+        let hex_params: PoseidonParamsHex = serde_json::from_str(json)
+            .map_err(|e| PoseidonError::InvalidSerialization(e.to_string()))?;
+        let width = hex_params.width;
+        if hex_params.partial_sbox_count == 0 || hex_params.partial_sbox_count > width {
+            return Err(PoseidonError::InvalidPartialSboxCount { count: hex_params.partial_sbox_count, width })
+        }
+        let total_rounds = hex_params.full_rounds_beginning + hex_params.partial_rounds + hex_params.full_rounds_end;
+        if hex_params.round_keys.len() != width * total_rounds {
+            return Err(PoseidonError::InvalidSerialization(format!("Need {} round keys, found {}", width * total_rounds, hex_params.round_keys.len())))
+        }
+        if hex_params.MDS_matrix.len() != width || hex_params.MDS_matrix.iter().any(|r| r.len() != width) {
+            return Err(PoseidonError::InvalidSerialization(format!("MDS matrix should be {}x{}", width, width)))
+        }
+
+        let parse = |h: &String| get_scalar_from_hex(h).map_err(|e| PoseidonError::InvalidSerialization(format!("{} for {}", e, h)));
+        let round_keys = hex_params.round_keys.iter().map(parse).collect::<Result<Vec<_>, _>>()?;
+        let mut MDS_matrix = vec![];
+        for r in hex_params.MDS_matrix.iter() {
+            MDS_matrix.push(r.iter().map(parse).collect::<Result<Vec<_>, _>>()?);
+        }
+
+        Ok(PoseidonParams {
+            width,
+            full_rounds_beginning: hex_params.full_rounds_beginning,
+            full_rounds_end: hex_params.full_rounds_end,
+            partial_rounds: hex_params.partial_rounds,
+            partial_sbox_count: hex_params.partial_sbox_count,
+            round_keys,
+            MDS_matrix,
+            sparse_partial_rounds: None
+        })
+    }
+
+    /// Write the round keys, one hex scalar per line, followed by the MDS matrix, one row per line with the entries
+    /// separated by spaces. This is the format read by `from_reader`.
+    pub fn to_writer<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for k in self.round_keys.iter() {
+            writeln!(writer, "{}", get_hex_from_scalar(k))?;
+        }
+        for r in self.MDS_matrix.iter() {
+            writeln!(writer, "{}", r.iter().map(get_hex_from_scalar).collect::<Vec<_>>().join(" "))?;
+        }
+        Ok(())
+    }
+
+    /// Read the round keys and MDS matrix written by `to_writer`. Blank lines are ignored.
+    pub fn from_reader<R: Read>(reader: R, width: usize, full_b: usize, full_e: usize, partial_rounds: usize) -> Result<PoseidonParams, PoseidonError> {
+        let total_rounds = full_b + partial_rounds + full_e;
+        let num_keys = width * total_rounds;
+        let parse = |h: &str| get_scalar_from_hex(h).map_err(|e| PoseidonError::InvalidSerialization(format!("{} for {}", e, h)));
+
+        let mut lines = vec![];
+        for line in BufReader::new(reader).lines() {
+            let line = line.map_err(|e| PoseidonError::InvalidSerialization(e.to_string()))?;
+            if !line.trim().is_empty() {
+                lines.push(line);
+            }
+        }
+        if lines.len() != num_keys + width {
+            return Err(PoseidonError::InvalidSerialization(format!("Need {} lines for round keys and {} for MDS matrix, found {} lines", num_keys, width, lines.len())))
+        }
+
+        let round_keys = lines[..num_keys].iter().map(|l| parse(l.trim())).collect::<Result<Vec<_>, _>>()?;
+        let mut MDS_matrix = vec![];
+        for l in lines[num_keys..].iter() {
+            let row = l.split_whitespace().map(parse).collect::<Result<Vec<_>, _>>()?;
+            if row.len() != width {
+                return Err(PoseidonError::InvalidSerialization(format!("MDS matrix row should have {} entries, found {}", width, row.len())))
+            }
+            MDS_matrix.push(row);
         }
+
+        Ok(PoseidonParams {
+            width,
+            full_rounds_beginning: full_b,
+            full_rounds_end: full_e,
+            partial_rounds,
+            partial_sbox_count: 1,
+            round_keys,
+            MDS_matrix,
+            sparse_partial_rounds: None
+        })
+    }
+
+    /// With the `generated-constants` feature, the round keys come from the Grain LFSR of the Poseidon reference
+    /// implementation, which depends on the number of rounds.
+    #[cfg(feature = "generated-constants")]
+    fn gen_round_keys(width: usize, full_rounds: usize, partial_rounds: usize) -> Vec<Scalar> {
+        GrainLfsr::new(SCALAR_FIELD_BITS, width, full_rounds, partial_rounds)
+            .round_constants((full_rounds + partial_rounds) * width)
     }
 
     // TODO: Write logic to generate correct round keys.
-    fn gen_round_keys(width: usize, total_rounds: usize) -> Vec<Scalar> {
-        let cap = total_rounds * width;
+    #[cfg(not(feature = "generated-constants"))]
+    fn gen_round_keys(width: usize, full_rounds: usize, partial_rounds: usize) -> Vec<Scalar> {
+        let cap = (full_rounds + partial_rounds) * width;
         /*let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
         vec![Scalar::random(&mut test_rng); cap]*/
         if ROUND_CONSTS.len() < cap {
@@ -68,12 +556,12 @@ impl PoseidonParams {
         rc
     }
 
-    // TODO: Write logic to generate correct MDS matrix. Currently loading hardcoded constants.
+    // Loads hardcoded constants for width 6 and generates the Cauchy matrix for other widths.
     fn gen_MDS_matrix(width: usize) -> Vec<Vec<Scalar>> {
         /*let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
         vec![vec![Scalar::random(&mut test_rng); width]; width]*/
-        if MDS_ENTRIES.len() != width {
-            panic!("Incorrect width, only width {} is supported now", width);
+        if cfg!(feature = "generated-constants") || MDS_ENTRIES.len() != width {
+            return Self::gen_cauchy_matrix(width);
         }
         let mut mds: Vec<Vec<Scalar>> = vec![vec![Scalar::zero(); width]; width];
         for i in 0..width {
@@ -88,9 +576,146 @@ impl PoseidonParams {
         mds
     }
 
+    /// The Cauchy matrix `M[i][j] = 1/(i + j + width)`. This is the matrix in `MDS_ENTRIES` for width 6.
+    fn gen_cauchy_matrix(width: usize) -> Vec<Vec<Scalar>> {
+        let mut mds: Vec<Vec<Scalar>> = vec![vec![Scalar::zero(); width]; width];
+        for i in 0..width {
+            for j in 0..width {
+                mds[i][j] = Scalar::from((i + j + width) as u64).invert();
+            }
+        }
+        mds
+    }
+
     pub fn get_total_rounds(&self) -> usize {
         self.full_rounds_beginning + self.partial_rounds + self.full_rounds_end
     }
+
+    /// Check that the number of full and partial rounds is enough for `SECURITY_LEVEL` bits of security
+    /// when using the given S-box over a field of `field_bits` bits.
+    pub fn check_security_margin(&self, sbox: &SboxType, field_bits: usize) -> Result<(), PoseidonError> {
+        let full_rounds = self.full_rounds_beginning + self.full_rounds_end;
+        let (min_full_rounds, min_partial_rounds) = recommended_rounds(self.width, sbox, field_bits);
+        if full_rounds < min_full_rounds || self.partial_rounds < min_partial_rounds {
+            return Err(PoseidonError::InsufficientRounds {
+                full_rounds,
+                min_full_rounds,
+                partial_rounds: self.partial_rounds,
+                min_partial_rounds
+            })
+        }
+        Ok(())
+    }
+}
+
+impl Default for PoseidonParams {
+    /// Width 6 with 4 full rounds in the beginning, 4 full rounds in the end and 140 partial rounds. Uses the
+    /// hardcoded round constants and MDS matrix.
+    fn default() -> Self {
+        PoseidonParams::new(6, 4, 4, 140)
+    }
+}
+
+/// Recommended number of (full, partial) rounds for `SECURITY_LEVEL` bits of security with the given S-box, where full
+/// is the total of full rounds in the beginning and end. Uses the bounds on statistical, interpolation and Groebner
+/// basis attacks from the Poseidon paper (section 4 and appendix C) and then adds the recommended security margin of
+/// 2 full rounds and 7.5% partial rounds. The bounds differ for the cube and inverse S-boxes since the inverse has
+/// a much higher degree. For width 6 over the 253 bit scalar field this gives (8, 83) for the cube S-box and (8, 56)
+/// for the inverse S-box, so the 140 partial rounds of `PoseidonParams::default` are more than needed for either.
+pub fn recommended_rounds(width: usize, sbox: &SboxType, field_bits: usize) -> (usize, usize) {
+    let t = width as f64;
+    let n = field_bits as f64;
+    let m = SECURITY_LEVEL as f64;
+
+    let (full, partial) = match sbox {
+        SboxType::Cube => {
+            let alpha = 3f64;
+            let log_alpha_2 = 2f64.ln() / alpha.ln();
+            // Statistical attacks
+            let full = if m <= (n - (alpha - 1.0) / 2.0).floor() * (t + 1.0) { 6.0 } else { 10.0 };
+            // Interpolation attack
+            let partial_1 = (log_alpha_2 * m.min(n)).ceil() + t.log(alpha).ceil() - full;
+            // Groebner basis attacks
+            let partial_2 = (log_alpha_2 * m.min(n) / 3.0).ceil() - full;
+            let partial_3 = (t - 1.0) + (log_alpha_2 * (m / (t + 1.0)).min(n / 2.0)).ceil() - full;
+            (full, partial_1.max(partial_2).max(partial_3))
+        }
+        SboxType::Inverse | SboxType::InverseUnchecked => {
+            // Statistical attacks
+            let full = if m <= (n - 2.0).floor() * (t + 1.0) { 6.0 } else { 10.0 };
+            // Interpolation attack
+            let partial_1 = (0.5 * m.min(n)).ceil() + t.log2().ceil() - (full * t.log2()).floor();
+            // Groebner basis attacks
+            let partial_2 = (t - 1.0) + t.log2().ceil() + (m.min(n) / (t + 1.0)).floor();
+            (full, partial_1.max(partial_2))
+        }
+    };
+
+    (full as usize + 2, (partial.max(0.0) * 1.075).ceil() as usize)
+}
+
+fn identity_matrix(n: usize) -> Vec<Vec<Scalar>> {
+    let mut m = vec![vec![Scalar::zero(); n]; n];
+    for i in 0..n {
+        m[i][i] = Scalar::one();
+    }
+    m
+}
+
+fn mat_mul(a: &Vec<Vec<Scalar>>, b: &Vec<Vec<Scalar>>) -> Vec<Vec<Scalar>> {
+    let mut m = vec![vec![Scalar::zero(); b[0].len()]; a.len()];
+    for i in 0..a.len() {
+        for j in 0..b[0].len() {
+            for k in 0..b.len() {
+                m[i][j] += a[i][k] * b[k][j];
+            }
+        }
+    }
+    m
+}
+
+fn mat_vec_mul(a: &Vec<Vec<Scalar>>, v: &[Scalar]) -> Vec<Scalar> {
+    a.iter().map(|r| r.iter().zip(v.iter()).fold(Scalar::zero(), |sum, (x, y)| sum + x * y)).collect()
+}
+
+/// Invert each of `elems` in place with a single inversion. Zeros are left as 0 like `Scalar::invert` does.
+fn batch_invert_nonzero(elems: &mut [Scalar]) {
+    let mut nonzero: Vec<Scalar> = elems.iter().filter(|e| **e != Scalar::zero()).cloned().collect();
+    Scalar::batch_invert(&mut nonzero);
+    let mut inverses = nonzero.into_iter();
+    for e in elems.iter_mut() {
+        if *e != Scalar::zero() {
+            *e = inverses.next().unwrap();
+        }
+    }
+}
+
+/// Invert a square matrix using Gauss-Jordan elimination. Returns None if the matrix is not invertible.
+fn mat_inverse(a: &Vec<Vec<Scalar>>) -> Option<Vec<Vec<Scalar>>> {
+    let n = a.len();
+    let mut m = a.clone();
+    let mut inv = identity_matrix(n);
+    for c in 0..n {
+        let p = (c..n).find(|r| m[*r][c] != Scalar::zero())?;
+        m.swap(c, p);
+        inv.swap(c, p);
+        let pivot_inv = m[c][c].invert();
+        for j in 0..n {
+            m[c][j] *= pivot_inv;
+            inv[c][j] *= pivot_inv;
+        }
+        for r in 0..n {
+            if r != c && m[r][c] != Scalar::zero() {
+                let f = m[r][c];
+                for j in 0..n {
+                    let (mcj, icj) = (m[c][j], inv[c][j]);
+                    m[r][j] -= f * mcj;
+                    inv[r][j] -= f * icj;
+                }
+            }
+        }
+    }
+    Some(inv)
 }
 
 /// Simplify linear combination by taking Variables common across terms and adding their corresponding scalars.
@@ -113,14 +738,49 @@ fn simplify_lc(lc: LinearCombination) -> LinearCombination {
 
 pub enum SboxType {
     Cube,
-    Inverse
+    Inverse,
+    /// Same as `Inverse` natively but the constraints skip `is_nonzero_gadget` and only enforce that the S-box
+    /// input times the output is 1. The proof cannot be created if an S-box input is ever 0 (where `Inverse` maps 0 to 0),
+    /// so only use this when the caller guarantees that the S-box inputs are never 0. Both variants give the same hash.
+    InverseUnchecked
 }
 
 impl SboxType {
     fn apply_sbox(&self, elem: &Scalar) -> Scalar {
         match self {
             SboxType::Cube => (elem * elem) * elem,
-            SboxType::Inverse => elem.invert()
+            SboxType::Inverse | SboxType::InverseUnchecked => elem.invert()
+        }
+    }
+
+    /// Number of multipliers each S-box adds to the constraint system
+    pub fn num_multipliers(&self) -> usize {
+        match self {
+            SboxType::Cube => 2,
+            SboxType::Inverse => 3,
+            SboxType::InverseUnchecked => 1
+        }
+    }
+
+    /// Number of linear constraints each S-box adds, including the 2 for each `multiply`
+    pub fn num_linear_constraints(&self) -> usize {
+        match self {
+            SboxType::Cube => 4,
+            SboxType::Inverse => 7,
+            SboxType::InverseUnchecked => 2
+        }
+    }
+
+    /// Apply the S-box to each of `elems`. For the inverse S-boxes, all `elems` are inverted together with
+    /// Montgomery's trick, using 1 inversion and 3 multiplications per element.
+    fn apply_sbox_batch(&self, elems: &mut [Scalar]) {
+        match self {
+            SboxType::Cube => {
+                for e in elems.iter_mut() {
+                    *e = self.apply_sbox(e);
+                }
+            }
+            SboxType::Inverse | SboxType::InverseUnchecked => batch_invert_nonzero(elems)
         }
     }
 
@@ -133,6 +793,7 @@ impl SboxType {
         match self {
             SboxType::Cube => Self::synthesize_cube_sbox(cs, input_var, round_key),
             SboxType::Inverse => Self::synthesize_inverse_sbox(cs, input_var, round_key),
+            SboxType::InverseUnchecked => Self::synthesize_inverse_unchecked_sbox(cs, input_var, round_key),
             _ => Err(R1CSError::GadgetError {description: String::from("Unknown Sbox type")})
         }
     }
@@ -183,10 +844,28 @@ impl SboxType {
 
         Ok(var_r)
     }
+
+    // Allocate variables in circuit and enforce constraints when Sbox as inverse without checking that the input is non-zero
+    fn synthesize_inverse_unchecked_sbox<CS: ConstraintSystem>(
+        cs: &mut CS,
+        input_var: LinearCombination,
+        round_key: Scalar
+    ) -> Result<Variable, R1CSError> {
+        let inp_plus_const: LinearCombination = input_var + round_key;
+
+        let val_l = cs.evaluate_lc(&inp_plus_const);
+        let (var_l, var_r, var_o) = cs.allocate_multiplier(val_l.map(|l| (l, l.invert())))?;
+
+        // The left wire is `inp_plus_const`, product of it and its inverse should be 1.
+        cs.constrain(inp_plus_const - var_l);
+        constrain_lc_with_scalar::<CS>(cs, var_o.into(), &Scalar::one());
+
+        Ok(var_r)
+    }
 }
 
 
-fn Poseidon_permutation(
+pub fn Poseidon_permutation(
     input: &[Scalar],
     params: &PoseidonParams,
     sbox: &SboxType
@@ -228,27 +907,42 @@ fn Poseidon_permutation(
     }
 
     // middle partial Sbox rounds
-    for _ in full_rounds_beginning..(full_rounds_beginning+partial_rounds) {
+    if let Some(sparse) = &params.sparse_partial_rounds {
+        // Each partial round needs O(width) operations with the sparse matrices
+        current_state = mat_vec_mul(&sparse.pre_matrix, &current_state);
+        for k in 0..partial_rounds {
+            current_state[width-1] = sbox.apply_sbox(&(current_state[width-1] + sparse.round_constants[k]));
+            current_state = sparse.sparse_matrices[k].apply(&current_state);
+        }
         for i in 0..width {
-            current_state[i] += &params.round_keys[round_keys_offset];
-            round_keys_offset += 1;
+            current_state[i] += sparse.post_constants[i];
         }
+        round_keys_offset += partial_rounds * width;
+    } else {
+        for _ in full_rounds_beginning..(full_rounds_beginning+partial_rounds) {
+            for i in 0..width {
+                current_state[i] += &params.round_keys[round_keys_offset];
+                round_keys_offset += 1;
+            }
 
-        // partial Sbox layer, apply Sbox to only 1 element of the state.
-        // Here the last one is chosen but the choice is arbitrary.
-        current_state[width-1] = sbox.apply_sbox(&current_state[width-1]);
+            // partial Sbox layer, apply Sbox to only `partial_sbox_count` elements of the state, usually 1.
+            // Here the last ones are chosen but the choice is arbitrary.
+            for i in width-params.partial_sbox_count..width {
+                current_state[i] = sbox.apply_sbox(&current_state[i]);
+            }
 
-        // linear layer
-        for j in 0..width {
-            for i in 0..width {
-                current_state_temp[i] += current_state[j] * params.MDS_matrix[i][j];
+            // linear layer
+            for j in 0..width {
+                for i in 0..width {
+                    current_state_temp[i] += current_state[j] * params.MDS_matrix[i][j];
+                }
             }
-        }
 
-        // Output of this round becomes input to next round
-        for i in 0..width {
-            current_state[i] = current_state_temp[i];
-            current_state_temp[i] = Scalar::zero();
+            // Output of this round becomes input to next round
+            for i in 0..width {
+                current_state[i] = current_state_temp[i];
+                current_state_temp[i] = Scalar::zero();
+            }
         }
     }
 
@@ -279,36 +973,97 @@ fn Poseidon_permutation(
     current_state
 }
 
+/// Add `MDS_matrix * sbox_outs` to `next_inputs`. Zero entries of the matrix are skipped and entries equal to 1 add
+/// the linear combination as it is, so sparse matrices do not add zero or redundant terms.
+fn apply_linear_layer(
+    width: usize,
+    sbox_outs: Vec<LinearCombination>,
+    next_inputs: &mut Vec<LinearCombination>,
+    MDS_matrix: &Vec<Vec<Scalar>>,
+) {
+    for j in 0..width {
+        for i in 0..width {
+            let coeff = MDS_matrix[i][j];
+            if coeff == Scalar::zero() {
+                continue;
+            }
+            next_inputs[i] = if coeff == Scalar::one() {
+                next_inputs[i].clone() + sbox_outs[j].clone()
+            } else {
+                next_inputs[i].clone() + sbox_outs[j].clone() * coeff
+            };
+        }
+    }
+}
+
+/// Emit a trace event after each round of `Poseidon_permutation_constraints` with the round index, whether it is a
+/// full or partial round and the number of multipliers and constraints added so far, which are counted from the
+/// `sboxes` synthesized so far. The linear layer only builds linear combinations and adds no constraints. Only with
+/// the `tracing` feature.
+#[cfg(feature = "tracing")]
+fn trace_round(round: usize, full: bool, sboxes: usize, sbox_type: &SboxType) {
+    let multipliers = sboxes * sbox_type.num_multipliers();
+    let constraints = sboxes * sbox_type.num_linear_constraints();
+    tracing::trace!(target: "poseidon", round, kind = if full { "full" } else { "partial" }, multipliers, constraints);
+}
+
+#[cfg(not(feature = "tracing"))]
+fn trace_round(_round: usize, _full: bool, _sboxes: usize, _sbox_type: &SboxType) {}
+
 pub fn Poseidon_permutation_constraints<'a, CS: ConstraintSystem>(
     cs: &mut CS,
     input: Vec<LinearCombination>,
     params: &'a PoseidonParams,
     sbox_type: &SboxType
 ) -> Result<Vec<LinearCombination>, R1CSError> {
+    let (output, _) = Poseidon_permutation_constraints_with_taps(cs, input, params, sbox_type, &[])?;
+    Ok(output)
+}
+
+/// Same as `Poseidon_permutation_constraints` but also returns the S-box outputs at the `taps`, in the same order. A tap
+/// is (round, index) where rounds are numbered from 0 across full and partial rounds. Partial rounds only have
+/// S-boxes at the last `partial_sbox_count` indices.
+pub fn Poseidon_permutation_constraints_with_taps<'a, CS: ConstraintSystem>(
+    cs: &mut CS,
+    input: Vec<LinearCombination>,
+    params: &'a PoseidonParams,
+    sbox_type: &SboxType,
+    taps: &[(usize, usize)]
+) -> Result<(Vec<LinearCombination>, Vec<LinearCombination>), R1CSError> {
     let width = params.width;
-    assert_eq!(input.len(), width);
+    if input.len() != width {
+        return Err(R1CSError::GadgetError {
+            description: format!("Expected {} inputs to the permutation but found {}", width, input.len())
+        })
+    }
 
-    fn apply_linear_layer(
-        width: usize,
-        sbox_outs: Vec<LinearCombination>,
-        next_inputs: &mut Vec<LinearCombination>,
-        MDS_matrix: &Vec<Vec<Scalar>>,
-    ) {
-        for j in 0..width {
-            for i in 0..width {
-                next_inputs[i] = next_inputs[i].clone() + sbox_outs[j].clone() * MDS_matrix[i][j];
+    let full_rounds_beginning = params.full_rounds_beginning;
+    let partial_rounds = params.partial_rounds;
+    let full_rounds_end = params.full_rounds_end;
+    let partial_sbox_count = params.partial_sbox_count;
+
+    for (round, index) in taps {
+        let is_partial = *round >= full_rounds_beginning && *round < full_rounds_beginning + partial_rounds;
+        if *round >= params.get_total_rounds() || *index >= width || (is_partial && *index < width - partial_sbox_count) {
+            return Err(R1CSError::GadgetError {
+                description: format!("No S-box output at round {} and index {}", round, index)
+            })
+        }
+    }
+
+    fn record_tap(taps: &[(usize, usize)], tapped: &mut Vec<LinearCombination>, round: usize, index: usize, sbox_out: &LinearCombination) {
+        for (t, out) in taps.iter().zip(tapped.iter_mut()) {
+            if *t == (round, index) {
+                *out = sbox_out.clone();
             }
         }
     }
+    let mut tapped = vec![LinearCombination::default(); taps.len()];
 
     let mut input_vars: Vec<LinearCombination> = input;
 
     let mut round_keys_offset = 0;
 
-    let full_rounds_beginning = params.full_rounds_beginning;
-    let partial_rounds = params.partial_rounds;
-    let full_rounds_end = params.full_rounds_end;
-
     // ------------ First rounds with full SBox begin --------------------
 
     for k in 0..full_rounds_beginning {
@@ -318,6 +1073,7 @@ pub fn Poseidon_permutation_constraints<'a, CS: ConstraintSystem>(
         for i in 0..width {
             let round_key = params.round_keys[round_keys_offset];
             sbox_outputs[i] = sbox_type.synthesize_sbox(cs, input_vars[i].clone(), round_key)?.into();
+            record_tap(taps, &mut tapped, k, i, &sbox_outputs[i]);
 
             round_keys_offset += 1;
         }
@@ -330,39 +1086,62 @@ pub fn Poseidon_permutation_constraints<'a, CS: ConstraintSystem>(
             // replace input_vars with next_input_vars
             input_vars[i] = next_input_vars.remove(0);
         }
+        trace_round(k, true, (k + 1) * width, sbox_type);
     }
 
     // ------------ First rounds with full SBox begin --------------------
 
     // ------------ Middle rounds with partial SBox begin --------------------
 
-    for k in full_rounds_beginning..(full_rounds_beginning+partial_rounds) {
-        let mut sbox_outputs: Vec<LinearCombination> = vec![LinearCombination::default(); width];
+    if let Some(sparse) = &params.sparse_partial_rounds {
+        let mut next_input_vars: Vec<LinearCombination> = vec![LinearCombination::default(); width];
+        apply_linear_layer(width, input_vars, &mut next_input_vars, &sparse.pre_matrix);
+        input_vars = next_input_vars.into_iter().map(simplify_lc).collect();
+
+        for k in 0..partial_rounds {
+            // Only the element going through the S-box has a round constant
+            let sbox_out = sbox_type.synthesize_sbox(cs, input_vars[width-1].clone(), sparse.round_constants[k])?;
+            input_vars[width-1] = sbox_out.into();
+            record_tap(taps, &mut tapped, full_rounds_beginning + k, width - 1, &input_vars[width-1]);
+            input_vars = sparse.sparse_matrices[k].apply_sparse(input_vars);
+            trace_round(full_rounds_beginning + k, false, full_rounds_beginning * width + k + 1, sbox_type);
+        }
 
-        // Substitution (S-box) layer
         for i in 0..width {
-            let round_key = params.round_keys[round_keys_offset];
+            input_vars[i] = input_vars[i].clone() + sparse.post_constants[i];
+        }
+        round_keys_offset += partial_rounds * width;
+    } else {
+        for k in full_rounds_beginning..(full_rounds_beginning+partial_rounds) {
+            let mut sbox_outputs: Vec<LinearCombination> = vec![LinearCombination::default(); width];
 
-            // apply Sbox to only 1 element of the state.
-            // Here the last one is chosen but the choice is arbitrary.
-            if i == width-1 {
-                sbox_outputs[i] = sbox_type.synthesize_sbox(cs, input_vars[i].clone(), round_key)?.into();
-            } else {
-                sbox_outputs[i] = input_vars[i].clone() + LinearCombination::from(round_key);
-            }
+            // Substitution (S-box) layer
+            for i in 0..width {
+                let round_key = params.round_keys[round_keys_offset];
+
+                // apply Sbox to only `partial_sbox_count` elements of the state, usually 1.
+                // Here the last ones are chosen but the choice is arbitrary.
+                if i >= width-partial_sbox_count {
+                    sbox_outputs[i] = sbox_type.synthesize_sbox(cs, input_vars[i].clone(), round_key)?.into();
+                    record_tap(taps, &mut tapped, k, i, &sbox_outputs[i]);
+                } else {
+                    sbox_outputs[i] = input_vars[i].clone() + LinearCombination::from(round_key);
+                }
 
-            round_keys_offset += 1;
-        }
+                round_keys_offset += 1;
+            }
 
-        // Linear layer
+            // Linear layer
 
-        let mut next_input_vars: Vec<LinearCombination> = vec![LinearCombination::default(); width];
+            let mut next_input_vars: Vec<LinearCombination> = vec![LinearCombination::default(); width];
 
-        apply_linear_layer(width, sbox_outputs, &mut next_input_vars, &params.MDS_matrix);
+            apply_linear_layer(width, sbox_outputs, &mut next_input_vars, &params.MDS_matrix);
 
-        for i in 0..width {
-            // replace input_vars with simplified next_input_vars
-            input_vars[i] = simplify_lc(next_input_vars.remove(0));
+            for i in 0..width {
+                // replace input_vars with simplified next_input_vars
+                input_vars[i] = simplify_lc(next_input_vars.remove(0));
+            }
+            trace_round(k, false, full_rounds_beginning * width + (k - full_rounds_beginning + 1) * partial_sbox_count, sbox_type);
         }
     }
 
@@ -377,6 +1156,7 @@ pub fn Poseidon_permutation_constraints<'a, CS: ConstraintSystem>(
         for i in 0..width {
             let round_key = params.round_keys[round_keys_offset];
             sbox_outputs[i] = sbox_type.synthesize_sbox(cs, input_vars[i].clone(), round_key)?.into();
+            record_tap(taps, &mut tapped, k, i, &sbox_outputs[i]);
 
             round_keys_offset += 1;
         }
@@ -391,15 +1171,30 @@ pub fn Poseidon_permutation_constraints<'a, CS: ConstraintSystem>(
             // replace input_vars with next_input_vars
             input_vars[i] = next_input_vars.remove(0);
         }
+        trace_round(k, true, full_rounds_beginning * width + partial_rounds * partial_sbox_count + (k - full_rounds_beginning - partial_rounds + 1) * width, sbox_type);
     }
 
     // ------------ Last rounds with full SBox end --------------------
 
-    Ok(input_vars)
+    Ok((input_vars, tapped))
 }
 
 
-pub fn Poseidon_permutation_gadget<'a, CS: ConstraintSystem>(
+/// Fail if `cs` is a prover and an input has no assignment. The prover can only be told apart from the verifier by
+/// whether it can evaluate the constant 1.
+fn check_prover_assignments<CS: ConstraintSystem>(cs: &CS, input: &[AllocatedScalar]) -> Result<(), R1CSError> {
+    if cs.evaluate_lc(&Variable::One().into()).is_none() {
+        return Ok(())
+    }
+    match input.iter().position(|e| e.assignment.is_none()) {
+        Some(i) => Err(R1CSError::GadgetError {
+            description: format!("Input {} of the permutation has no assignment on the prover side, use AllocatedScalar::known", i)
+        }),
+        None => Ok(())
+    }
+}
+
+pub fn Poseidon_permutation_gadget<'a, CS: ConstraintSystem>(
     cs: &mut CS,
     input: Vec<AllocatedScalar>,
     params: &'a PoseidonParams,
@@ -408,6 +1203,7 @@ pub fn Poseidon_permutation_gadget<'a, CS: ConstraintSystem>(
 ) -> Result<(), R1CSError> {
     let width = params.width;
     assert_eq!(output.len(), width);
+    check_prover_assignments(cs, &input)?;
 
     let input_vars: Vec<LinearCombination> = input.iter().map(|e| e.variable.into()).collect();
     let permutation_output = Poseidon_permutation_constraints::<CS>(cs, input_vars, params, sbox_type)?;
@@ -419,27 +1215,157 @@ pub fn Poseidon_permutation_gadget<'a, CS: ConstraintSystem>(
     Ok(())
 }
 
+/// Like `Poseidon_permutation_gadget` but the caller does not pass the expected output. The output is allocated
+/// as new variables, with assignments computed from the input assignments on the prover side, and
+/// constrained to be the permutation of the input. Returns the allocated output.
+pub fn Poseidon_permutation_gadget_auto<'a, CS: ConstraintSystem>(
+    cs: &mut CS,
+    input: Vec<AllocatedScalar>,
+    params: &'a PoseidonParams,
+    sbox_type: &SboxType
+) -> Result<Vec<AllocatedScalar>, R1CSError> {
+    let width = params.width;
+    check_prover_assignments(cs, &input)?;
+
+    // Checks the number of inputs before the native permutation below
+    let input_vars: Vec<LinearCombination> = input.iter().map(|e| e.variable.into()).collect();
+    let permutation_output = Poseidon_permutation_constraints::<CS>(cs, input_vars, params, sbox_type)?;
+
+    // Output assignments are known only if all input assignments are known, i.e. for the prover
+    let output_vals: Option<Vec<Scalar>> = input.iter().map(|e| e.assignment).collect::<Option<Vec<_>>>()
+        .map(|inp| Poseidon_permutation(&inp, params, sbox_type));
+
+    let mut output = vec![];
+    for i in 0..width {
+        let assignment = output_vals.as_ref().map(|o| o[i]);
+        let variable = cs.allocate(assignment)?;
+        cs.constrain(permutation_output[i].to_owned() - variable);
+        output.push(AllocatedScalar {
+            variable,
+            assignment,
+        });
+    }
+
+    Ok(output)
+}
+
+/// Same as `Poseidon_permutation_gadget_auto` but returns only the output variables, e.g. to use them as the input
+/// linear combinations of `Poseidon_permutation_constraints`.
+pub fn Poseidon_permutation_gadget_alloc<'a, CS: ConstraintSystem>(
+    cs: &mut CS,
+    input: Vec<AllocatedScalar>,
+    params: &'a PoseidonParams,
+    sbox_type: &SboxType
+) -> Result<Vec<Variable>, R1CSError> {
+    let output = Poseidon_permutation_gadget_auto(cs, input, params, sbox_type)?;
+    Ok(output.iter().map(|o| o.variable).collect())
+}
+
+/// Output of a Poseidon hash, to not mix it up with arbitrary scalars. Dereferences to the `Scalar`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoseidonHash(pub Scalar);
+
+impl PoseidonHash {
+    pub fn into_scalar(self) -> Scalar {
+        self.0
+    }
+}
+
+impl From<Scalar> for PoseidonHash {
+    fn from(s: Scalar) -> PoseidonHash {
+        PoseidonHash(s)
+    }
+}
+
+impl From<PoseidonHash> for Scalar {
+    fn from(h: PoseidonHash) -> Scalar {
+        h.0
+    }
+}
+
+impl Deref for PoseidonHash {
+    type Target = Scalar;
+
+    fn deref(&self) -> &Scalar {
+        &self.0
+    }
+}
+
+/// Compare hashes in constant time, for use when one of them is controlled by an adversary
+pub fn poseidon_hash_ct_eq(a: &Scalar, b: &Scalar) -> Choice {
+    a.ct_eq(b)
+}
+
 /// 2:1 (2 inputs, 1 output) hash from the permutation by passing the first input as zero, 2 of the next 4 as non-zero, a padding constant and rest zero. Choose one of the outputs.
 
 // Choice is arbitrary
 pub const PADDING_CONST: u64 = 101;
 pub const ZERO_CONST: u64 = 0;
 
-pub fn Poseidon_hash_2(xl: Scalar, xr: Scalar, params: &PoseidonParams, sbox: &SboxType) -> Scalar {
+/// Needs width at least 3 for the capacity element and the 2 inputs, returns `PoseidonError::WidthTooSmall` otherwise.
+/// The permutation input is the same as in `Poseidon_hash_2_constraints` with `constant_statics(width - 2)`.
+pub fn Poseidon_hash_2(xl: Scalar, xr: Scalar, params: &PoseidonParams, sbox: &SboxType) -> Result<PoseidonHash, PoseidonError> {
+    check_width(params, 3)?;
+
     // Only 2 inputs to the permutation are set to the input of this hash function,
     // one is set to the padding constant and rest are 0. Always keep the 1st input as 0
-
-    let input = vec![
-        Scalar::from(ZERO_CONST),
-        xl,
-        xr,
-        Scalar::from(PADDING_CONST),
-        Scalar::from(ZERO_CONST),
-        Scalar::from(ZERO_CONST)
-    ];
+    let mut input = vec![Scalar::from(ZERO_CONST), xl, xr, Scalar::from(PADDING_CONST)];
+    input.resize(params.width, Scalar::from(ZERO_CONST));
 
     // Never take the first output
-    Poseidon_permutation(&input, params, sbox)[1]
+    Ok(PoseidonHash(Poseidon_permutation(&input, params, sbox)[1]))
+}
+
+/// Same as calling `Poseidon_permutation` on each of `inputs` but the permutations are run together, round by round,
+/// so that the S-boxes of a round are applied to all states at once. With the inverse S-boxes, this needs 1 inversion
+/// per round for the whole batch rather than 1 per S-box.
+pub fn Poseidon_permutation_batch(inputs: &[Vec<Scalar>], params: &PoseidonParams, sbox: &SboxType) -> Vec<Vec<Scalar>> {
+    let width = params.width;
+    let full_rounds_beginning = params.full_rounds_beginning;
+    let partial_rounds = params.partial_rounds;
+
+    let mut states = inputs.to_vec();
+    for s in states.iter() {
+        assert_eq!(s.len(), width);
+    }
+
+    for r in 0..params.get_total_rounds() {
+        let round_keys = &params.round_keys[r * width..(r + 1) * width];
+        let is_full_round = r < full_rounds_beginning || r >= full_rounds_beginning + partial_rounds;
+        // Partial rounds apply the S-box to the last `partial_sbox_count` elements only
+        let sbox_indices = if is_full_round { 0..width } else { width - params.partial_sbox_count..width };
+
+        let mut sbox_inputs = vec![];
+        for s in states.iter_mut() {
+            for i in 0..width {
+                s[i] += round_keys[i];
+            }
+            sbox_inputs.extend_from_slice(&s[sbox_indices.clone()]);
+        }
+        sbox.apply_sbox_batch(&mut sbox_inputs);
+
+        let mut sbox_outputs = sbox_inputs.chunks(sbox_indices.len());
+        for s in states.iter_mut() {
+            s[sbox_indices.clone()].copy_from_slice(sbox_outputs.next().unwrap());
+            *s = mat_vec_mul(&params.MDS_matrix, s);
+        }
+    }
+
+    states
+}
+
+/// `Poseidon_hash_2` of each pair of `inputs`. The permutations are batched with `Poseidon_permutation_batch` so
+/// this is faster than hashing each pair with the inverse S-boxes.
+pub fn Poseidon_hash_2_batch(inputs: &[(Scalar, Scalar)], params: &PoseidonParams, sbox: &SboxType) -> Result<Vec<PoseidonHash>, PoseidonError> {
+    check_width(params, 3)?;
+
+    let perm_inputs: Vec<Vec<Scalar>> = inputs.iter().map(|(xl, xr)| {
+        let mut input = vec![Scalar::from(ZERO_CONST), *xl, *xr, Scalar::from(PADDING_CONST)];
+        input.resize(params.width, Scalar::from(ZERO_CONST));
+        input
+    }).collect();
+
+    Ok(Poseidon_permutation_batch(&perm_inputs, params, sbox).into_iter().map(|o| PoseidonHash(o[1])).collect())
 }
 
 pub fn Poseidon_hash_2_constraints<'a, CS: ConstraintSystem>(
@@ -450,9 +1376,30 @@ pub fn Poseidon_hash_2_constraints<'a, CS: ConstraintSystem>(
     params: &'a PoseidonParams,
     sbox_type: &SboxType,
 ) -> Result<LinearCombination, R1CSError> {
+    let width = params.width;
+    // Needs the capacity element and the 2 inputs
+    if width < 3 {
+        return Err(PoseidonError::WidthTooSmall { width, min_width: 3 }.into())
+    }
+    let permutation_output = hash_2_permutation_constraints(cs, xl, xr, statics, params, sbox_type)?;
+    Ok(permutation_output[1].to_owned())
+}
+
+/// Constraints for the whole permutation output of the hash of `xl` and `xr`
+fn hash_2_permutation_constraints<CS: ConstraintSystem>(
+    cs: &mut CS,
+    xl: LinearCombination,
+    xr: LinearCombination,
+    statics: Vec<LinearCombination>,
+    params: &PoseidonParams,
+    sbox_type: &SboxType,
+) -> Result<Vec<LinearCombination>, R1CSError> {
+    check_width(params, 3)?;
     let width = params.width;
     // Only 2 inputs to the permutation are set to the input of this hash function.
-    assert_eq!(statics.len(), width-2);
+    if statics.len() != width - 2 {
+        return Err(R1CSError::GadgetError { description: format!("Expected {} statics but found {}", width - 2, statics.len()) })
+    }
 
     // Always keep the 1st input as 0
     let mut inputs = vec![statics[0].to_owned()];
@@ -463,8 +1410,7 @@ pub fn Poseidon_hash_2_constraints<'a, CS: ConstraintSystem>(
     for i in 1..statics.len() {
         inputs.push(statics[i].to_owned());
     }
-    let permutation_output = Poseidon_permutation_constraints::<CS>(cs, inputs, params, sbox_type)?;
-    Ok(permutation_output[1].to_owned())
+    Poseidon_permutation_constraints::<CS>(cs, inputs, params, sbox_type)
 }
 
 pub fn Poseidon_hash_2_gadget<'a, CS: ConstraintSystem>(
@@ -475,31 +1421,264 @@ pub fn Poseidon_hash_2_gadget<'a, CS: ConstraintSystem>(
     params: &'a PoseidonParams,
     sbox_type: &SboxType,
     output: &Scalar
-) -> Result<(), R1CSError> {
+) -> Result<LinearCombination, R1CSError> {
 
     let statics: Vec<LinearCombination> = statics.iter().map(|s| s.variable.into()).collect();
     let hash = Poseidon_hash_2_constraints::<CS>(cs, xl.variable.into(), xr.variable.into(), statics, params, sbox_type)?;
 
-    constrain_lc_with_scalar::<CS>(cs, hash, output);
+    constrain_lc_with_scalar::<CS>(cs, hash.clone(), output);
+
+    // Return the hash so that it can be used in other constraints
+    Ok(hash)
+}
+
+/// How the padding constant and zeroes of the permutation input are given to `Poseidon_hash_2_gadget_ex`
+pub enum ZeroMode {
+    /// Committed statics from `allocate_statics_for_prover` or `allocate_statics_for_verifier`
+    Committed(Vec<AllocatedScalar>),
+    /// Constants from `constant_statics`, these need no commitments
+    Constant
+}
+
+/// Same as `Poseidon_hash_2_gadget` with the choice of committed or constant statics
+pub fn Poseidon_hash_2_gadget_ex<'a, CS: ConstraintSystem>(
+    cs: &mut CS,
+    xl: AllocatedScalar,
+    xr: AllocatedScalar,
+    params: &'a PoseidonParams,
+    sbox_type: &SboxType,
+    output: &Scalar,
+    zero_mode: ZeroMode
+) -> Result<LinearCombination, R1CSError> {
+    check_width(params, 3)?;
+    let statics = match zero_mode {
+        ZeroMode::Committed(statics) => statics.iter().map(|s| s.variable.into()).collect(),
+        ZeroMode::Constant => constant_statics(params.width - 2)
+    };
+    let hash = Poseidon_hash_2_constraints::<CS>(cs, xl.variable.into(), xr.variable.into(), statics, params, sbox_type)?;
+
+    constrain_lc_with_scalar::<CS>(cs, hash.clone(), output);
+
+    Ok(hash)
+}
+
+/// Same as `Poseidon_hash_2_gadget` but only the left input `secret` is committed. The right input `public_input`
+/// and the statics are constants so only 1 commitment is needed, see `poseidon_hash_2_mixed_num_commitments`.
+pub fn Poseidon_hash_2_mixed_gadget<'a, CS: ConstraintSystem>(
+    cs: &mut CS,
+    secret: AllocatedScalar,
+    public_input: Scalar,
+    params: &'a PoseidonParams,
+    sbox_type: &SboxType,
+    output: &Scalar
+) -> Result<LinearCombination, R1CSError> {
+    check_width(params, 3)?;
+    let statics = constant_statics(params.width - 2);
+    let hash = Poseidon_hash_2_constraints::<CS>(cs, secret.variable.into(), LinearCombination::from(public_input), statics, params, sbox_type)?;
 
+    constrain_lc_with_scalar::<CS>(cs, hash.clone(), output);
+
+    Ok(hash)
+}
+
+/// Prove that `committed` is `Poseidon_hash_2` of the public `public_data`. The hash is computed natively so this
+/// needs no multipliers.
+pub fn bind_to_public_hash_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    committed: AllocatedScalar,
+    public_data: (Scalar, Scalar),
+    params: &PoseidonParams,
+    sbox_type: &SboxType
+) -> Result<(), R1CSError> {
+    let hash = Poseidon_hash_2(public_data.0, public_data.1, params, sbox_type)?;
+    constrain_lc_with_scalar::<CS>(cs, committed.variable.into(), &hash);
     Ok(())
 }
 
-pub fn Poseidon_hash_4(inputs: [Scalar; 4], params: &PoseidonParams, sbox: &SboxType) -> Scalar {
-    // Only 4 inputs to the permutation are set to the input of this hash function,
-    // one is set to the padding constant and one is set to 0. Always keep the 1st input as 0
+/// Prove that committed `value` is `Poseidon_hash_2(index, secret)` for the public `index` and committed `secret`.
+/// `index`, the padding and zeroes are constants.
+pub fn addressed_commitment_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    index: Scalar,
+    secret: AllocatedScalar,
+    value: AllocatedScalar,
+    params: &PoseidonParams,
+    sbox_type: &SboxType
+) -> Result<(), R1CSError> {
+    check_width(params, 3)?;
+    let statics = constant_statics(params.width - 2);
+    let hash = Poseidon_hash_2_constraints::<CS>(cs, LinearCombination::from(index), secret.variable.into(), statics, params, sbox_type)?;
+    cs.constrain(hash - value.variable);
+    Ok(())
+}
+
+/// Same as `Poseidon_hash_2_gadget` but the output is committed and not public
+pub fn Poseidon_hash_2_committed_output_gadget<'a, CS: ConstraintSystem>(
+    cs: &mut CS,
+    xl: AllocatedScalar,
+    xr: AllocatedScalar,
+    statics: Vec<AllocatedScalar>,
+    output: AllocatedScalar,
+    params: &'a PoseidonParams,
+    sbox_type: &SboxType
+) -> Result<(), R1CSError> {
+
+    let statics: Vec<LinearCombination> = statics.iter().map(|s| s.variable.into()).collect();
+    let hash = Poseidon_hash_2_constraints::<CS>(cs, xl.variable.into(), xr.variable.into(), statics, params, sbox_type)?;
+
+    cs.constrain(hash - output.variable);
+
+    Ok(())
+}
 
+/// 2:2 compression of `xl` and `xr` from a single permutation. Same permutation input as `Poseidon_hash_2` but
+/// returns outputs 1 and 2, so the first output is `Poseidon_hash_2(xl, xr)`. Needs width 6.
+pub fn Poseidon_hash_2_to_2(xl: Scalar, xr: Scalar, params: &PoseidonParams, sbox: &SboxType) -> (Scalar, Scalar) {
     let input = vec![
         Scalar::from(ZERO_CONST),
-        inputs[0],
-        inputs[1],
-        inputs[2],
-        inputs[3],
-        Scalar::from(PADDING_CONST)
+        xl,
+        xr,
+        Scalar::from(PADDING_CONST),
+        Scalar::from(ZERO_CONST),
+        Scalar::from(ZERO_CONST)
     ];
 
+    let output = Poseidon_permutation(&input, params, sbox);
+    (output[1], output[2])
+}
+
+/// Prove that `Poseidon_hash_2_to_2` of committed `xl` and `xr` is `output`. Both outputs are constrained and returned
+/// so they can be used in other constraints.
+pub fn Poseidon_hash_2_to_2_gadget<'a, CS: ConstraintSystem>(
+    cs: &mut CS,
+    xl: AllocatedScalar,
+    xr: AllocatedScalar,
+    statics: Vec<AllocatedScalar>,
+    params: &'a PoseidonParams,
+    sbox_type: &SboxType,
+    output: &(Scalar, Scalar)
+) -> Result<(LinearCombination, LinearCombination), R1CSError> {
+    let width = params.width;
+    if width < 3 {
+        return Err(PoseidonError::WidthTooSmall { width, min_width: 3 }.into())
+    }
+    let statics: Vec<LinearCombination> = statics.iter().map(|s| s.variable.into()).collect();
+    let permutation_output = hash_2_permutation_constraints(cs, xl.variable.into(), xr.variable.into(), statics, params, sbox_type)?;
+    let (out_1, out_2) = (permutation_output[1].to_owned(), permutation_output[2].to_owned());
+
+    constrain_lc_with_scalar::<CS>(cs, out_1.clone(), &output.0);
+    constrain_lc_with_scalar::<CS>(cs, out_2.clone(), &output.1);
+
+    Ok((out_1, out_2))
+}
+
+/// Prove that `Poseidon_hash_2_to_2` of committed `xl` and `xr` is the committed pair `(out0, out1)`. The statics
+/// are constants so only the inputs and outputs need commitments.
+pub fn compression_2to2_gadget<'a, CS: ConstraintSystem>(
+    cs: &mut CS,
+    xl: AllocatedScalar,
+    xr: AllocatedScalar,
+    out0: AllocatedScalar,
+    out1: AllocatedScalar,
+    params: &'a PoseidonParams,
+    sbox_type: &SboxType
+) -> Result<(), R1CSError> {
+    let width = params.width;
+    if width < 3 {
+        return Err(PoseidonError::WidthTooSmall { width, min_width: 3 }.into())
+    }
+    let statics = constant_statics(width - 2);
+    let permutation_output = hash_2_permutation_constraints(cs, xl.variable.into(), xr.variable.into(), statics, params, sbox_type)?;
+
+    cs.constrain(permutation_output[1].to_owned() - out0.variable);
+    cs.constrain(permutation_output[2].to_owned() - out1.variable);
+
+    Ok(())
+}
+
+/// Hash up to `width - 2` inputs. The permutation input is 0, followed by the inputs, the padding constant and then 0s.
+/// Thus `Poseidon_hash_n(&[xl, xr], ..) == Poseidon_hash_2(xl, xr, ..)` and similarly for `Poseidon_hash_4` when width is at least 6.
+pub fn Poseidon_hash_n(inputs: &[Scalar], params: &PoseidonParams, sbox: &SboxType) -> Result<PoseidonHash, PoseidonError> {
+    let width = params.width;
+    if width < 3 {
+        return Err(PoseidonError::WidthTooSmall { width, min_width: 3 })
+    }
+    if inputs.len() > width - 2 {
+        return Err(PoseidonError::TooManyInputs { width, max_inputs: width.saturating_sub(2), found: inputs.len() })
+    }
+
+    let mut input = vec![Scalar::from(ZERO_CONST)];
+    input.extend_from_slice(inputs);
+    input.push(Scalar::from(PADDING_CONST));
+    input.resize(width, Scalar::from(ZERO_CONST));
+
+    // Never take the first output
+    Ok(PoseidonHash(Poseidon_permutation(&input, params, sbox)[1]))
+}
+
+/// Statics for hashing with associated data `ad`: the capacity element is the length of `ad`, followed by the
+/// padding constant, `ad` and then 0s. `ad` can thus have at most `width - 4` elements. Having the length in the
+/// capacity element makes `ad` of different lengths give different hashes, even when padded with 0s.
+fn statics_with_ad(ad: &[Scalar], width: usize) -> Result<Vec<Scalar>, PoseidonError> {
+    if width < 4 {
+        return Err(PoseidonError::WidthTooSmall { width, min_width: 4 })
+    }
+    if ad.len() > width - 4 {
+        return Err(PoseidonError::TooManyInputs { width, max_inputs: width - 4, found: ad.len() })
+    }
+    let mut statics = vec![Scalar::from(ad.len() as u64), Scalar::from(PADDING_CONST)];
+    statics.extend_from_slice(ad);
+    statics.resize(width - 2, Scalar::from(ZERO_CONST));
+    Ok(statics)
+}
+
+/// Same as `Poseidon_hash_2` but bound to the associated data `ad`, so the same `xl` and `xr` hash differently for
+/// different `ad`. `ad` goes in the state slots after the padding constant, which are 0 in `Poseidon_hash_2`, so
+/// at most `width - 4` elements are allowed. The length of `ad` is set in the capacity element, thus an empty `ad`
+/// gives `Poseidon_hash_2`.
+pub fn Poseidon_hash_2_with_ad(xl: Scalar, xr: Scalar, ad: &[Scalar], params: &PoseidonParams, sbox: &SboxType) -> Result<PoseidonHash, PoseidonError> {
+    let statics = statics_with_ad(ad, params.width)?;
+    let mut input = vec![statics[0], xl, xr];
+    input.extend_from_slice(&statics[1..]);
+
+    // Never take the first output
+    Ok(PoseidonHash(Poseidon_permutation(&input, params, sbox)[1]))
+}
+
+/// Prove that `Poseidon_hash_2_with_ad` of committed `xl` and `xr` with the public `ad` is `output`. `ad`, the
+/// padding and zeroes are constants.
+pub fn Poseidon_hash_2_with_ad_gadget<'a, CS: ConstraintSystem>(
+    cs: &mut CS,
+    xl: AllocatedScalar,
+    xr: AllocatedScalar,
+    ad: &[Scalar],
+    params: &'a PoseidonParams,
+    sbox_type: &SboxType,
+    output: &Scalar
+) -> Result<LinearCombination, R1CSError> {
+    let statics = statics_with_ad(ad, params.width)?.into_iter().map(LinearCombination::from).collect();
+    let hash = Poseidon_hash_2_constraints::<CS>(cs, xl.variable.into(), xr.variable.into(), statics, params, sbox_type)?;
+
+    constrain_lc_with_scalar::<CS>(cs, hash.clone(), output);
+
+    Ok(hash)
+}
+
+/// Needs width at least 5 for the capacity element and the 4 inputs, returns `PoseidonError::WidthTooSmall` otherwise.
+/// The permutation input is the same as in `Poseidon_hash_4_constraints` with `constant_statics(width - 4)`.
+pub fn Poseidon_hash_4(inputs: [Scalar; 4], params: &PoseidonParams, sbox: &SboxType) -> Result<PoseidonHash, PoseidonError> {
+    check_width(params, 5)?;
+
+    // Only 4 inputs to the permutation are set to the input of this hash function,
+    // one is set to the padding constant and rest are 0. Always keep the 1st input as 0
+    let mut input = vec![Scalar::from(ZERO_CONST)];
+    input.extend_from_slice(&inputs);
+    input.push(Scalar::from(PADDING_CONST));
+    // Width 5 has no place for the padding constant
+    input.resize(params.width, Scalar::from(ZERO_CONST));
+
     // Never take the first output
-    Poseidon_permutation(&input, params, sbox)[1]
+    Ok(PoseidonHash(Poseidon_permutation(&input, params, sbox)[1]))
 }
 
 pub fn Poseidon_hash_4_constraints<'a, CS: ConstraintSystem>(
@@ -511,8 +1690,14 @@ pub fn Poseidon_hash_4_constraints<'a, CS: ConstraintSystem>(
 ) -> Result<LinearCombination, R1CSError> {
 
     let width = params.width;
+    // Needs the capacity element and the 4 inputs
+    if width < 5 {
+        return Err(PoseidonError::WidthTooSmall { width, min_width: 5 }.into())
+    }
     // Only 4 inputs to the permutation are set to the input of this hash function.
-    assert_eq!(statics.len(), width-4);
+    if statics.len() != width - 4 {
+        return Err(R1CSError::GadgetError { description: format!("Expected {} statics but found {}", width - 4, statics.len()) })
+    }
 
     // Always keep the 1st input as 0
     let mut inputs = vec![statics[0].to_owned()];
@@ -550,29 +1735,97 @@ pub fn Poseidon_hash_4_gadget<'a, CS: ConstraintSystem>(
     Ok(())
 }
 
+/// Widths for which `PoseidonRegistry::new` creates parameters. Limited by the number of constants in `ROUND_CONSTS`.
+pub const REGISTRY_WIDTHS: [usize; 4] = [3, 4, 5, 6];
+
+/// Parameters for several widths using the same S-box. Widths other than 6 use the Cauchy MDS matrix and the
+/// first round constants of `ROUND_CONSTS`, which were not generated specifically for these widths.
+pub struct PoseidonRegistry {
+    params: HashMap<usize, PoseidonParams>,
+    pub sbox: SboxType
+}
+
+impl PoseidonRegistry {
+    /// Create parameters with 4 full rounds at the beginning and end and 140 partial rounds for each width in `REGISTRY_WIDTHS`
+    pub fn new(sbox: SboxType) -> PoseidonRegistry {
+        let mut registry = Self::empty(sbox);
+        for width in REGISTRY_WIDTHS.iter() {
+            registry.insert(PoseidonParams::new(*width, 4, 4, 140));
+        }
+        registry
+    }
+
+    pub fn empty(sbox: SboxType) -> PoseidonRegistry {
+        PoseidonRegistry {
+            params: HashMap::new(),
+            sbox
+        }
+    }
+
+    /// Add parameters for their width, returning the parameters previously present for the width, if any
+    pub fn insert(&mut self, params: PoseidonParams) -> Option<PoseidonParams> {
+        self.params.insert(params.width, params)
+    }
+
+    /// Add all parameters of `other`, replacing the parameters of the widths present in both
+    pub fn merge(&mut self, other: PoseidonRegistry) {
+        self.params.extend(other.params);
+    }
+
+    pub fn get(&self, width: usize) -> Option<&PoseidonParams> {
+        self.params.get(&width)
+    }
+
+    /// Hash using the parameters of the given width, see `Poseidon_hash_n`
+    pub fn hash(&self, width: usize, inputs: &[Scalar]) -> Result<PoseidonHash, PoseidonError> {
+        let params = self.get(width).ok_or(PoseidonError::UnsupportedWidth(width))?;
+        Poseidon_hash_n(inputs, params, &self.sbox)
+    }
+}
+
+/// Number of Pedersen commitments needed to prove `Poseidon_hash_2` with statics from `allocate_statics_for_prover`.
+/// This is `width` since `xl`, `xr` and each of the `width - 2` statics (padding constant and 0s) are committed.
+/// The gadget needs `width` to be at least 3.
+pub fn poseidon_hash_2_num_commitments(width: usize) -> usize {
+    width
+}
+
+/// Number of Pedersen commitments needed to prove `Poseidon_hash_2` with statics from `constant_statics`.
+/// Only `xl` and `xr` are committed.
+pub fn poseidon_hash_2_num_commitments_with_constant_statics() -> usize {
+    2
+}
+
+/// Number of Pedersen commitments needed to prove `Poseidon_hash_2` with `Poseidon_hash_2_mixed_gadget`. Only the
+/// secret input is committed.
+pub fn poseidon_hash_2_mixed_num_commitments() -> usize {
+    1
+}
+
+/// Padding constant and zeroes as constant linear combinations, usable in place of committed statics.
+/// The statics are the same for prover and verifier and need no commitments.
+pub fn constant_statics(num_statics: usize) -> Vec<LinearCombination> {
+    let mut statics = vec![LinearCombination::from(Scalar::from(ZERO_CONST)), LinearCombination::from(Scalar::from(PADDING_CONST))];
+    for _ in 2..num_statics {
+        statics.push(LinearCombination::from(Scalar::from(ZERO_CONST)));
+    }
+    statics
+}
+
 /// Allocate padding constant and zeroes for Prover
 pub fn allocate_statics_for_prover(prover: &mut Prover, num_statics: usize) -> Vec<AllocatedScalar> {
     let mut statics = vec![];
     let (_, var) = prover.commit(Scalar::from(ZERO_CONST), Scalar::zero());
-    statics.push(AllocatedScalar {
-        variable: var,
-        assignment: Some(Scalar::from(ZERO_CONST)),
-    });
+    statics.push(AllocatedScalar::known(var, Scalar::from(ZERO_CONST)));
 
     // Commitment to PADDING_CONST with blinding as 0
     let (_, var) = prover.commit(Scalar::from(PADDING_CONST), Scalar::zero());
-    statics.push(AllocatedScalar {
-        variable: var,
-        assignment: Some(Scalar::from(PADDING_CONST)),
-    });
+    statics.push(AllocatedScalar::known(var, Scalar::from(PADDING_CONST)));
 
     // Commit to 0 with randomness 0 for the rest of the elements of width
     for _ in 2..num_statics {
         let (_, var) = prover.commit(Scalar::from(ZERO_CONST), Scalar::zero());
-        statics.push(AllocatedScalar {
-            variable: var,
-            assignment: Some(Scalar::from(ZERO_CONST)),
-        });
+        statics.push(AllocatedScalar::known(var, Scalar::from(ZERO_CONST)));
     }
     statics
 }
@@ -587,42 +1840,269 @@ pub fn allocate_statics_for_verifier(verifier: &mut Verifier, num_statics: usize
     let zero_comm = pc_gens.commit(Scalar::from(ZERO_CONST), Scalar::zero()).compress();
 
     let v = verifier.commit(zero_comm.clone());
-    statics.push(AllocatedScalar {
-        variable: v,
-        assignment: None,
-    });
+    statics.push(AllocatedScalar::unknown(v));
 
     let v = verifier.commit(pad_comm);
-    statics.push(AllocatedScalar {
-        variable: v,
-        assignment: None,
-    });
+    statics.push(AllocatedScalar::unknown(v));
     for _ in 2..num_statics {
         let v = verifier.commit(zero_comm.clone());
-        statics.push(AllocatedScalar {
-            variable: v,
-            assignment: None,
-        });
+        statics.push(AllocatedScalar::unknown(v));
     }
     statics
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    // For benchmarking
-    use std::time::{Duration, Instant};
-    use std::sync::atomic::Ordering::SeqCst;
+/// The Poseidon construction a proof is about, used in `poseidon_transcript_label`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PoseidonHashKind {
+    Permutation,
+    Hash2,
+    Hash4
+}
 
-    fn get_poseidon_params() -> PoseidonParams{
-        let width = 6;
-        let (full_b, full_e) = (4, 4);
-        let partial_rounds = 140;
-        PoseidonParams::new(width, full_b, full_e, partial_rounds)
+/// Deterministic encoding of the width, round counts, S-box type and hash kind. The proof wrappers add it to the
+/// transcript so a prover and verifier using different parameters get different transcripts and verification fails.
+pub fn poseidon_transcript_label(params: &PoseidonParams, sbox: &SboxType, kind: PoseidonHashKind) -> Vec<u8> {
+    let mut label = b"Poseidon".to_vec();
+    for n in &[params.width, params.full_rounds_beginning, params.full_rounds_end, params.partial_rounds] {
+        label.extend_from_slice(&(*n as u64).to_le_bytes());
+    }
+    // Only for non standard partial rounds so that existing labels do not change
+    if params.partial_sbox_count != 1 {
+        label.extend_from_slice(&(params.partial_sbox_count as u64).to_le_bytes());
+    }
+    label.push(match sbox {
+        SboxType::Cube => 0,
+        SboxType::Inverse => 1,
+        SboxType::InverseUnchecked => 2
+    });
+    label.push(match kind {
+        PoseidonHashKind::Permutation => 0,
+        PoseidonHashKind::Hash2 => 1,
+        PoseidonHashKind::Hash4 => 2
+    });
+    label
+}
+
+/// Number of multipliers of `num_hashes` hashes with `Poseidon_hash_2_gadget` or `Poseidon_hash_2_gadget_ex`,
+/// which is the number of S-boxes times the multipliers of each S-box
+pub fn estimate_multipliers(params: &PoseidonParams, sbox: &SboxType, num_hashes: usize) -> usize {
+    num_hashes * params.num_sboxes() * sbox.num_multipliers()
+}
+
+/// Bulletproof generators of 1 party sized for `num_hashes` hashes, see `estimate_multipliers` and `trim_gens`
+pub fn poseidon_hash_gens(params: &PoseidonParams, sbox: &SboxType, num_hashes: usize) -> BulletproofGens {
+    BulletproofGens::new(estimate_multipliers(params, sbox, num_hashes).max(1).next_power_of_two(), 1)
+}
+
+/// Rough number of bytes the prover needs to prove `num_hashes` hashes with `Poseidon_hash_2_gadget_ex` and constant
+/// statics. Counts the generators for the padded number of multipliers, the vectors of scalars the prover keeps per
+/// multiplier and the terms of the linear constraints, which have about `width` terms each after a linear layer. This
+/// is a heuristic to avoid running out of memory, not an exact measure.
+pub fn estimate_prover_memory(params: &PoseidonParams, sbox: &SboxType, num_hashes: usize) -> usize {
+    const SCALAR_BYTES: usize = 32;
+    // Uncompressed Ristretto point
+    const POINT_BYTES: usize = 160;
+    // Variable and coefficient of a term of a linear combination
+    const TERM_BYTES: usize = 48;
+    // Assignments, blinding and polynomial vectors of the prover
+    const VECTORS_PER_MULTIPLIER: usize = 12;
+
+    let width = params.width;
+    let num_sboxes = params.num_sboxes();
+    let num_multipliers = estimate_multipliers(params, sbox, num_hashes);
+    // 1 more to constrain the output of each hash
+    let num_linear_constraints = num_hashes * (num_sboxes * sbox.num_linear_constraints() + 1);
+    let padded_multipliers = num_multipliers.next_power_of_two();
+
+    let generators = 2 * padded_multipliers * POINT_BYTES;
+    let vectors = VECTORS_PER_MULTIPLIER * padded_multipliers * SCALAR_BYTES;
+    let constraints = num_linear_constraints * (width + 2) * TERM_BYTES;
+    generators + vectors + constraints
+}
+
+/// Prove knowledge of committed `xl` and `xr` with the (public) hash `Poseidon_hash_2(xl, xr, params, sbox)`.
+/// The blindings for the commitments to `xl` and `xr` are sampled from `rng`.
+pub fn gen_proof_of_Poseidon_hash_2<R: RngCore + CryptoRng>(xl: Scalar, xr: Scalar, params: &PoseidonParams, sbox: &SboxType,
+                                                          rng: &mut R, transcript_label: &'static [u8],
+                                                          pc_gens: &PedersenGens, bp_gens: &BulletproofGens) -> Result<(R1CSProof, Vec<CompressedRistretto>), R1CSError> {
+    let blindings = [Scalar::random(rng), Scalar::random(rng)];
+    gen_proof_of_Poseidon_hash_2_with_blindings(xl, xr, &blindings, params, sbox, transcript_label, pc_gens, bp_gens)
+}
+
+/// Same as `gen_proof_of_Poseidon_hash_2` but uses the given blindings for the commitments to `xl` and `xr`,
+/// in that order. The returned commitments are thus deterministic.
+pub fn gen_proof_of_Poseidon_hash_2_with_blindings(xl: Scalar, xr: Scalar, blindings: &[Scalar], params: &PoseidonParams, sbox: &SboxType,
+                                                   transcript_label: &'static [u8],
+                                                   pc_gens: &PedersenGens, bp_gens: &BulletproofGens) -> Result<(R1CSProof, Vec<CompressedRistretto>), R1CSError> {
+    check_width(params, 3)?;
+    if blindings.len() != 2 {
+        return Err(R1CSError::GadgetError { description: format!("Expected 2 blindings but found {}", blindings.len()) })
+    }
+    let expected_output = Poseidon_hash_2(xl, xr, params, sbox)?;
+
+    let mut comms = vec![];
+
+    let mut prover_transcript = Transcript::new(transcript_label);
+    prover_transcript.append_message(b"poseidon_params", &poseidon_transcript_label(params, sbox, PoseidonHashKind::Hash2));
+    let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+    let (com_l, var_l) = prover.commit(xl, blindings[0]);
+    comms.push(com_l);
+    let l_alloc = AllocatedScalar::known(var_l, xl);
+
+    let (com_r, var_r) = prover.commit(xr, blindings[1]);
+    comms.push(com_r);
+    let r_alloc = AllocatedScalar::known(var_r, xr);
+
+    let statics = allocate_statics_for_prover(&mut prover, params.width - 2);
+    Poseidon_hash_2_gadget(&mut prover, l_alloc, r_alloc, statics, params, sbox, &expected_output)?;
+
+    let proof = prover.prove(&bp_gens)?;
+    Ok((proof, comms))
+}
+
+/// Same as `gen_proof_of_Poseidon_hash_2` but the blindings are `derive_blinding(seed, 0)` and `derive_blinding(seed, 1)`
+/// for `xl` and `xr`, so the same seed gives the same commitments.
+pub fn gen_proof_of_Poseidon_hash_2_deterministic(xl: Scalar, xr: Scalar, seed: &[u8], params: &PoseidonParams, sbox: &SboxType,
+                                                  transcript_label: &'static [u8],
+                                                  pc_gens: &PedersenGens, bp_gens: &BulletproofGens) -> Result<(R1CSProof, Vec<CompressedRistretto>), R1CSError> {
+    let blindings = [derive_blinding(seed, 0), derive_blinding(seed, 1)];
+    gen_proof_of_Poseidon_hash_2_with_blindings(xl, xr, &blindings, params, sbox, transcript_label, pc_gens, bp_gens)
+}
+
+pub fn verify_proof_of_Poseidon_hash_2(expected_output: &Scalar, params: &PoseidonParams, sbox: &SboxType,
+                                       proof: R1CSProof, commitments: Vec<CompressedRistretto>,
+                                       transcript_label: &'static [u8], pc_gens: &PedersenGens, bp_gens: &BulletproofGens) -> Result<(), R1CSError> {
+    check_width(params, 3)?;
+    if commitments.len() != 2 {
+        return Err(R1CSError::GadgetError { description: format!("Expected 2 commitments but found {}", commitments.len()) })
+    }
+
+    let mut verifier_transcript = Transcript::new(transcript_label);
+    verifier_transcript.append_message(b"poseidon_params", &poseidon_transcript_label(params, sbox, PoseidonHashKind::Hash2));
+    let mut verifier = Verifier::new(&mut verifier_transcript);
+
+    let l_alloc = AllocatedScalar::unknown(verifier.commit(commitments[0]));
+    let r_alloc = AllocatedScalar::unknown(verifier.commit(commitments[1]));
+
+    let statics = allocate_statics_for_verifier(&mut verifier, params.width - 2, pc_gens);
+    Poseidon_hash_2_gadget(&mut verifier, l_alloc, r_alloc, statics, params, sbox, expected_output)?;
+
+    verifier.verify(&proof, &pc_gens, &bp_gens)
+}
+
+/// Prove knowledge of committed `xl` and `xr` of each pair of `inputs` with the (public) hashes
+/// `Poseidon_hash_2_batch(inputs, params, sbox)` in 1 proof. The commitments are in the order of `inputs`, `xl` then
+/// `xr`, and the statics are shared by all hashes so a batch of 1 is the same as `gen_proof_of_Poseidon_hash_2`.
+pub fn gen_proof_of_Poseidon_hash_2_batch<R: RngCore + CryptoRng>(inputs: &[(Scalar, Scalar)], params: &PoseidonParams, sbox: &SboxType,
+                                                                rng: &mut R, transcript_label: &'static [u8],
+                                                                pc_gens: &PedersenGens, bp_gens: &BulletproofGens) -> Result<(R1CSProof, Vec<CompressedRistretto>), R1CSError> {
+    check_width(params, 3)?;
+    if inputs.is_empty() {
+        return Err(R1CSError::GadgetError { description: String::from("Need at least 1 hash to prove") })
+    }
+    let expected_outputs = Poseidon_hash_2_batch(inputs, params, sbox)?;
+
+    let mut comms = vec![];
+
+    let mut prover_transcript = Transcript::new(transcript_label);
+    prover_transcript.append_message(b"poseidon_params", &poseidon_transcript_label(params, sbox, PoseidonHashKind::Hash2));
+    let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+    let mut statics = None;
+    for ((xl, xr), expected_output) in inputs.iter().zip(expected_outputs) {
+        let (com_l, var_l) = prover.commit(*xl, Scalar::random(rng));
+        comms.push(com_l);
+        let (com_r, var_r) = prover.commit(*xr, Scalar::random(rng));
+        comms.push(com_r);
+
+        // Statics are committed after the first pair of inputs, as in `gen_proof_of_Poseidon_hash_2`
+        let statics = statics.get_or_insert_with(|| allocate_statics_for_prover(&mut prover, params.width - 2)).clone();
+        Poseidon_hash_2_gadget(&mut prover, AllocatedScalar::known(var_l, *xl), AllocatedScalar::known(var_r, *xr),
+                               statics, params, sbox, &expected_output)?;
+    }
+
+    let proof = prover.prove(&bp_gens)?;
+    Ok((proof, comms))
+}
+
+/// Verify a proof from `gen_proof_of_Poseidon_hash_2_batch` (or `gen_proof_of_Poseidon_hash_2` for 1 hash), taking
+/// the commitments from an iterator, e.g. reading them from disk or network. The constraints of each hash are added
+/// as soon as its 2 commitments are read so the caller never needs to hold all commitments. The commitments must be
+/// in the same order as when proving, 2 for each of `expected_outputs`.
+pub fn verify_poseidon_stream<I: Iterator<Item=CompressedRistretto>>(proof: R1CSProof, mut commitments: I,
+                                                                     expected_outputs: &[Scalar], params: &PoseidonParams, sbox: &SboxType,
+                                                                     transcript_label: &'static [u8], pc_gens: &PedersenGens, bp_gens: &BulletproofGens) -> Result<(), R1CSError> {
+    check_width(params, 3)?;
+    if expected_outputs.is_empty() {
+        return Err(R1CSError::GadgetError { description: String::from("Need at least 1 hash to verify") })
+    }
+
+    let mut verifier_transcript = Transcript::new(transcript_label);
+    verifier_transcript.append_message(b"poseidon_params", &poseidon_transcript_label(params, sbox, PoseidonHashKind::Hash2));
+    let mut verifier = Verifier::new(&mut verifier_transcript);
+
+    let mut statics = None;
+    for (i, expected_output) in expected_outputs.iter().enumerate() {
+        let (com_l, com_r) = match (commitments.next(), commitments.next()) {
+            (Some(l), Some(r)) => (l, r),
+            _ => return Err(R1CSError::GadgetError {
+                description: format!("Expected {} commitments but they ended at hash {}", 2 * expected_outputs.len(), i)
+            })
+        };
+        let l_alloc = AllocatedScalar::unknown(verifier.commit(com_l));
+        let r_alloc = AllocatedScalar::unknown(verifier.commit(com_r));
+
+        let statics = statics.get_or_insert_with(|| allocate_statics_for_verifier(&mut verifier, params.width - 2, pc_gens)).clone();
+        Poseidon_hash_2_gadget(&mut verifier, l_alloc, r_alloc, statics, params, sbox, expected_output)?;
+    }
+    if commitments.next().is_some() {
+        return Err(R1CSError::GadgetError {
+            description: format!("More than {} commitments for {} hashes", 2 * expected_outputs.len(), expected_outputs.len())
+        })
+    }
+
+    verifier.verify(&proof, &pc_gens, &bp_gens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r1cs_utils::eval_lc;
+    use crate::r1cs_capture::capture_constraints;
+    // For benchmarking
+    use std::time::{Duration, Instant};
+    use std::sync::atomic::Ordering::SeqCst;
+
+    fn get_poseidon_params() -> PoseidonParams{
+        let width = 6;
+        let (full_b, full_e) = (4, 4);
+        let partial_rounds = 140;
+        PoseidonParams::new(width, full_b, full_e, partial_rounds)
+    }
+
+    /// Check that the value of each output of the permutation constraints is same as the output of the
+    /// native permutation, to catch any divergence between the two before proving
+    fn assert_native_matches_circuit(params: &PoseidonParams, sbox_type: &SboxType) {
+        let width = params.width;
+        let mut test_rng: StdRng = SeedableRng::from_seed([25u8; 32]);
+        let input = (0..width).map(|_| Scalar::random(&mut test_rng)).collect::<Vec<_>>();
+        let expected_output = Poseidon_permutation(&input, params, sbox_type);
+
+        let pc_gens = PedersenGens::default();
+        let mut prover_transcript = Transcript::new(b"NativeMatchesCircuit");
+        let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+        let input_vars = input.iter().map(|i| prover.commit(*i, Scalar::random(&mut test_rng)).1.into()).collect::<Vec<LinearCombination>>();
+        let output = Poseidon_permutation_constraints(&mut prover, input_vars, params, sbox_type).unwrap();
+
+        for i in 0..width {
+            assert_eq!(prover.evaluate_lc(&output[i]), Some(expected_output[i]), "Output {} differs", i);
+        }
     }
 
     fn poseidon_perm(sbox_type: &SboxType, transcript_label: &'static [u8]) {
         let s_params = get_poseidon_params();
+        assert_native_matches_circuit(&s_params, sbox_type);
         let width = s_params.width;
         let total_rounds = s_params.get_total_rounds();
 
@@ -690,13 +2170,14 @@ mod tests {
 
     fn poseidon_hash_2(sbox_type: &SboxType, transcript_label: &'static [u8]) {
         let s_params = get_poseidon_params();
+        assert_native_matches_circuit(&s_params, sbox_type);
         let width = s_params.width;
         let total_rounds = s_params.get_total_rounds();
 
         let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
         let xl = Scalar::random(&mut test_rng);
         let xr = Scalar::random(&mut test_rng);
-        let expected_output = Poseidon_hash_2(xl, xr, &s_params, sbox_type);
+        let expected_output = Poseidon_hash_2(xl, xr, &s_params, sbox_type).unwrap();
 
         /*println!("Input:\n");
         println!("xl={:?}", &xl);
@@ -786,6 +2267,7 @@ mod tests {
 
     fn poseidon_hash_4(sbox_type: &SboxType, transcript_label: &'static [u8]) {
         let s_params = get_poseidon_params();
+        assert_native_matches_circuit(&s_params, sbox_type);
         let width = s_params.width;
         let total_rounds = s_params.get_total_rounds();
 
@@ -793,7 +2275,7 @@ mod tests {
         let _input = (0..4).map(|_| Scalar::random(&mut test_rng)).collect::<Vec<_>>();
         let mut input = [Scalar::zero(); 4];
         input.copy_from_slice(_input.as_slice());
-        let expected_output = Poseidon_hash_4(input, &s_params, sbox_type);
+        let expected_output = Poseidon_hash_4(input, &s_params, sbox_type).unwrap();
 
         /*println!("Input:\n");
         println!("xl={:?}", &xl);
@@ -903,4 +2385,1584 @@ mod tests {
     fn test_poseidon_hash_4_inverse_sbox() {
         poseidon_hash_4(&SboxType::Inverse, b"Poseidon_hash_2_inverse");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_poseidon_security_margin() {
+        let s_params = get_poseidon_params();
+        assert!(s_params.check_security_margin(&SboxType::Cube, SCALAR_FIELD_BITS).is_ok());
+        assert!(s_params.check_security_margin(&SboxType::Inverse, SCALAR_FIELD_BITS).is_ok());
+
+        let s_params = PoseidonParams::new(6, 4, 4, 10);
+        assert!(s_params.check_security_margin(&SboxType::Cube, SCALAR_FIELD_BITS).is_err());
+        assert!(s_params.check_security_margin(&SboxType::Inverse, SCALAR_FIELD_BITS).is_err());
+
+        let s_params = PoseidonParams::new(6, 2, 2, 140);
+        assert!(s_params.check_security_margin(&SboxType::Cube, SCALAR_FIELD_BITS).is_err());
+    }
+
+    #[test]
+    fn test_poseidon_recommended_rounds() {
+        let s_params = get_poseidon_params();
+        let (full_cube, partial_cube) = recommended_rounds(6, &SboxType::Cube, SCALAR_FIELD_BITS);
+        let (full_inverse, partial_inverse) = recommended_rounds(6, &SboxType::Inverse, SCALAR_FIELD_BITS);
+        assert_eq!((full_cube, partial_cube), (8, 83));
+        assert_eq!((full_inverse, partial_inverse), (8, 56));
+        assert_eq!(recommended_rounds(6, &SboxType::InverseUnchecked, SCALAR_FIELD_BITS), (full_inverse, partial_inverse));
+
+        // The full rounds in use match the recommendation and the partial rounds in use are more than recommended
+        assert_eq!(s_params.full_rounds_beginning + s_params.full_rounds_end, full_cube);
+        assert!(s_params.partial_rounds >= partial_cube);
+        assert_ne!(partial_cube, partial_inverse);
+
+        // Recommended rounds pass the security check
+        let s_params = PoseidonParams::new(6, full_inverse / 2, full_inverse / 2, partial_inverse);
+        assert!(s_params.check_security_margin(&SboxType::Inverse, SCALAR_FIELD_BITS).is_ok());
+        assert!(s_params.check_security_margin(&SboxType::Cube, SCALAR_FIELD_BITS).is_err());
+    }
+
+    #[test]
+    fn test_poseidon_hash_2_with_blindings() {
+        let s_params = get_poseidon_params();
+        let sbox_type = &SboxType::Inverse;
+
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let xl = Scalar::random(&mut test_rng);
+        let xr = Scalar::random(&mut test_rng);
+        let expected_output = Poseidon_hash_2(xl, xr, &s_params, sbox_type).unwrap();
+        let blindings = vec![Scalar::random(&mut test_rng), Scalar::random(&mut test_rng)];
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+
+        let label = b"Poseidon_hash_2_blindings";
+        let (proof_1, comms_1) = gen_proof_of_Poseidon_hash_2_with_blindings(xl, xr, &blindings, &s_params, sbox_type, label, &pc_gens, &bp_gens).unwrap();
+        let (proof_2, comms_2) = gen_proof_of_Poseidon_hash_2_with_blindings(xl, xr, &blindings, &s_params, sbox_type, label, &pc_gens, &bp_gens).unwrap();
+        assert_eq!(comms_1, comms_2);
+
+        assert!(verify_proof_of_Poseidon_hash_2(&expected_output, &s_params, sbox_type, proof_1, comms_1, label, &pc_gens, &bp_gens).is_ok());
+        assert!(verify_proof_of_Poseidon_hash_2(&expected_output, &s_params, sbox_type, proof_2, comms_2, label, &pc_gens, &bp_gens).is_ok());
+
+        // Blindings sampled from the RNG give different commitments
+        let (proof_3, comms_3) = gen_proof_of_Poseidon_hash_2(xl, xr, &s_params, sbox_type, &mut test_rng, label, &pc_gens, &bp_gens).unwrap();
+        assert_ne!(comms_3, gen_proof_of_Poseidon_hash_2_with_blindings(xl, xr, &blindings, &s_params, sbox_type, label, &pc_gens, &bp_gens).unwrap().1);
+        assert!(verify_proof_of_Poseidon_hash_2(&expected_output, &s_params, sbox_type, proof_3, comms_3, label, &pc_gens, &bp_gens).is_ok());
+
+        assert!(gen_proof_of_Poseidon_hash_2_with_blindings(xl, xr, &blindings[..1], &s_params, sbox_type, label, &pc_gens, &bp_gens).is_err());
+    }
+
+    #[test]
+    fn test_poseidon_perm_missing_assignment() {
+        let s_params = get_poseidon_params();
+        let width = s_params.width;
+        let sbox_type = &SboxType::Inverse;
+        let input = (0..width).map(|i| Scalar::from(i as u64)).collect::<Vec<_>>();
+        let expected_output = Poseidon_permutation(&input, &s_params, sbox_type);
+
+        let pc_gens = PedersenGens::default();
+        let mut prover_transcript = Transcript::new(b"Poseidon_perm_missing_assignment");
+        let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+        let mut allocs = vec![];
+        for i in 0..width {
+            let (_, var) = prover.commit(input[i], Scalar::one());
+            allocs.push(AllocatedScalar::known(var, input[i]));
+        }
+        allocs[2].assignment = None;
+
+        match Poseidon_permutation_gadget(&mut prover, allocs.clone(), &s_params, sbox_type, &expected_output) {
+            Err(R1CSError::GadgetError { description }) => assert!(description.contains("Input 2")),
+            _ => panic!("Missing assignment should be an error")
+        }
+        assert!(Poseidon_permutation_gadget_auto(&mut prover, allocs.clone(), &s_params, sbox_type).is_err());
+
+        // Wrong number of inputs is an error and not a panic
+        let input_vars: Vec<LinearCombination> = allocs.iter().map(|a| a.variable.into()).collect();
+        match Poseidon_permutation_constraints(&mut prover, input_vars[..width - 1].to_vec(), &s_params, sbox_type) {
+            Err(R1CSError::GadgetError { description }) => assert!(description.contains("Expected 6 inputs")),
+            _ => panic!("Wrong number of inputs should be an error")
+        }
+        allocs[2].assignment = Some(input[2]);
+        assert!(Poseidon_permutation_gadget_auto(&mut prover, allocs[1..].to_vec(), &s_params, sbox_type).is_err());
+    }
+
+    #[test]
+    fn test_poseidon_perm_alloc_chain() {
+        let s_params = get_poseidon_params();
+        let width = s_params.width;
+        let sbox_type = &SboxType::Cube;
+        let transcript_label = b"Poseidon_perm_alloc_chain";
+
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let input = (0..width).map(|_| Scalar::random(&mut test_rng)).collect::<Vec<_>>();
+        let expected_output = Poseidon_permutation(&Poseidon_permutation(&input, &s_params, sbox_type), &s_params, sbox_type);
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(4096, 1);
+
+        // First permutation with allocated output, which is the input of the second
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(transcript_label);
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let mut comms = vec![];
+            let mut allocs = vec![];
+            for i in 0..width {
+                let (com, var) = prover.commit(input[i], Scalar::random(&mut test_rng));
+                comms.push(com);
+                allocs.push(AllocatedScalar::known(var, input[i]));
+            }
+
+            let middle = Poseidon_permutation_gadget_alloc(&mut prover, allocs, &s_params, sbox_type).unwrap();
+            assert_eq!(middle.len(), width);
+            let middle_lcs = middle.into_iter().map(LinearCombination::from).collect();
+            let output = Poseidon_permutation_constraints(&mut prover, middle_lcs, &s_params, sbox_type).unwrap();
+            for i in 0..width {
+                constrain_lc_with_scalar(&mut prover, output[i].to_owned(), &expected_output[i]);
+            }
+
+            (prover.prove(&bp_gens).unwrap(), comms)
+        };
+
+        let verify = |expected_output: &[Scalar]| {
+            let mut verifier_transcript = Transcript::new(transcript_label);
+            let mut verifier = Verifier::new(&mut verifier_transcript);
+            let allocs = commitments.iter().map(|c| AllocatedScalar::unknown(verifier.commit(*c))).collect();
+            let middle = Poseidon_permutation_gadget_alloc(&mut verifier, allocs, &s_params, sbox_type)?;
+            let middle_lcs = middle.into_iter().map(LinearCombination::from).collect();
+            let output = Poseidon_permutation_constraints(&mut verifier, middle_lcs, &s_params, sbox_type)?;
+            for i in 0..width {
+                constrain_lc_with_scalar(&mut verifier, output[i].to_owned(), &expected_output[i]);
+            }
+            verifier.verify(&proof, &pc_gens, &bp_gens)
+        };
+        assert!(verify(&expected_output).is_ok());
+        // Output of a single permutation
+        assert!(verify(&Poseidon_permutation(&input, &s_params, sbox_type)).is_err());
+    }
+
+    #[test]
+    fn test_poseidon_perm_auto() {
+        let s_params = get_poseidon_params();
+        let width = s_params.width;
+        let sbox_type = &SboxType::Cube;
+        let transcript_label = b"Poseidon_perm_auto";
+
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let input = (0..width).map(|_| Scalar::random(&mut test_rng)).collect::<Vec<_>>();
+        let expected_output = Poseidon_permutation(&input, &s_params, sbox_type);
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(transcript_label);
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let mut comms = vec![];
+            let mut allocs = vec![];
+            for i in 0..width {
+                let (com, var) = prover.commit(input[i].clone(), Scalar::random(&mut test_rng));
+                comms.push(com);
+                allocs.push(AllocatedScalar {
+                    variable: var,
+                    assignment: Some(input[i]),
+                });
+            }
+
+            let output = Poseidon_permutation_gadget_auto(&mut prover, allocs, &s_params, sbox_type).unwrap();
+            let output_vals = output.iter().map(|o| o.assignment.unwrap()).collect::<Vec<_>>();
+            assert_eq!(output_vals, expected_output);
+
+            let proof = prover.prove(&bp_gens).unwrap();
+            (proof, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(transcript_label);
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let mut allocs = vec![];
+        for i in 0..width {
+            let v = verifier.commit(commitments[i]);
+            allocs.push(AllocatedScalar {
+                variable: v,
+                assignment: None,
+            });
+        }
+        let output = Poseidon_permutation_gadget_auto(&mut verifier, allocs, &s_params, sbox_type).unwrap();
+        assert_eq!(output.len(), width);
+        assert!(output.iter().all(|o| o.assignment.is_none()));
+
+        assert!(verifier.verify(&proof, &pc_gens, &bp_gens).is_ok());
+    }
+
+    #[test]
+    fn test_poseidon_params_hex_json() {
+        let s_params = get_poseidon_params();
+        let json = s_params.to_hex_json();
+        // Round keys are written in the same format as the constant tables
+        let round_key = get_hex_from_scalar(&s_params.round_keys[0]);
+        #[cfg(not(feature = "generated-constants"))]
+        assert_eq!(round_key, ROUND_CONSTS[0]);
+        assert!(json.contains(&round_key));
+        assert!(json.contains(MDS_ENTRIES[0][0]));
+
+        let s_params_1 = PoseidonParams::from_hex_json(&json).unwrap();
+        assert_eq!(s_params_1.round_keys, s_params.round_keys);
+        assert_eq!(s_params_1.MDS_matrix, s_params.MDS_matrix);
+
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let xl = Scalar::random(&mut test_rng);
+        let xr = Scalar::random(&mut test_rng);
+        for sbox_type in &[SboxType::Cube, SboxType::Inverse] {
+            assert_eq!(Poseidon_hash_2(xl, xr, &s_params, sbox_type).unwrap(), Poseidon_hash_2(xl, xr, &s_params_1, sbox_type).unwrap());
+        }
+
+        assert!(PoseidonParams::from_hex_json("{}").is_err());
+        let invalid = json.replacen(&round_key, "0xZZ", 1);
+        assert!(PoseidonParams::from_hex_json(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_poseidon_params_from_reader() {
+        let s_params = get_poseidon_params();
+        let (width, full_b, full_e, partial) = (s_params.width, s_params.full_rounds_beginning, s_params.full_rounds_end, s_params.partial_rounds);
+        let mut buf = vec![];
+        s_params.to_writer(&mut buf).unwrap();
+
+        let s_params_1 = PoseidonParams::from_reader(buf.as_slice(), width, full_b, full_e, partial).unwrap();
+        assert_eq!(s_params_1.round_keys, s_params.round_keys);
+        assert_eq!(s_params_1.MDS_matrix, s_params.MDS_matrix);
+
+        let xl = Scalar::from(1u64);
+        let xr = Scalar::from(2u64);
+        assert_eq!(Poseidon_hash_2(xl, xr, &s_params, &SboxType::Inverse).unwrap(), Poseidon_hash_2(xl, xr, &s_params_1, &SboxType::Inverse).unwrap());
+
+        // Wrong number of rounds so wrong number of lines
+        assert!(PoseidonParams::from_reader(buf.as_slice(), width, full_b, full_e, partial + 1).is_err());
+
+        let text = String::from_utf8(buf).unwrap();
+        let mut lines: Vec<String> = text.lines().map(String::from).collect();
+
+        // Missing entry in the last MDS row
+        let mut short_row = lines.clone();
+        let last = short_row.pop().unwrap();
+        short_row.push(last.split_whitespace().skip(1).collect::<Vec<_>>().join(" "));
+        assert!(PoseidonParams::from_reader(short_row.join("\n").as_bytes(), width, full_b, full_e, partial).is_err());
+
+        // Invalid hex in a round key
+        lines[0] = String::from("0xZZ");
+        assert!(PoseidonParams::from_reader(lines.join("\n").as_bytes(), width, full_b, full_e, partial).is_err());
+    }
+
+    #[test]
+    fn test_poseidon_hash_2_gadget_chained() {
+        let s_params = get_poseidon_params();
+        let sbox_type = &SboxType::Cube;
+        let transcript_label = b"Poseidon_hash_2_chained";
+
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let xl = Scalar::random(&mut test_rng);
+        let xr = Scalar::random(&mut test_rng);
+        let expected_output = Poseidon_hash_2(xl, xr, &s_params, sbox_type).unwrap();
+        // Committed value which is proven to be the hash plus 5
+        let y = *expected_output + Scalar::from(5u64);
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(transcript_label);
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let mut comms = vec![];
+            let mut allocs = vec![];
+            for v in &[xl, xr, y] {
+                let (com, var) = prover.commit(*v, Scalar::random(&mut test_rng));
+                comms.push(com);
+                allocs.push(AllocatedScalar {
+                    variable: var,
+                    assignment: Some(*v),
+                });
+            }
+
+            let statics = allocate_statics_for_prover(&mut prover, 4);
+            let hash = Poseidon_hash_2_gadget(&mut prover, allocs[0], allocs[1], statics, &s_params, sbox_type, &expected_output).unwrap();
+            prover.constrain(hash + Scalar::from(5u64) - allocs[2].variable);
+
+            (prover.prove(&bp_gens).unwrap(), comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(transcript_label);
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let allocs = commitments.iter().map(|c| AllocatedScalar {
+            variable: verifier.commit(*c),
+            assignment: None,
+        }).collect::<Vec<_>>();
+
+        let statics = allocate_statics_for_verifier(&mut verifier, 4, &pc_gens);
+        let hash = Poseidon_hash_2_gadget(&mut verifier, allocs[0], allocs[1], statics, &s_params, sbox_type, &expected_output).unwrap();
+        verifier.constrain(hash + Scalar::from(5u64) - allocs[2].variable);
+
+        assert!(verifier.verify(&proof, &pc_gens, &bp_gens).is_ok());
+    }
+
+    #[test]
+    fn test_poseidon_sparse_partial_rounds() {
+        let s_params = get_poseidon_params();
+        let mut s_params_opt = get_poseidon_params();
+        s_params_opt.use_sparse_partial_rounds();
+        let width = s_params.width;
+
+        let sparse = s_params_opt.sparse_partial_rounds.as_ref().unwrap();
+        assert_eq!(sparse.sparse_matrices.len(), s_params.partial_rounds);
+        assert_eq!(sparse.round_constants.len(), s_params.partial_rounds);
+
+        // The sparse form of the partial rounds computes the same as the dense one
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let input = (0..width).map(|_| Scalar::random(&mut test_rng)).collect::<Vec<_>>();
+        let sbox_type = SboxType::Cube;
+        let offset = s_params.full_rounds_beginning * width;
+        let mut dense_state = input.clone();
+        for r in 0..s_params.partial_rounds {
+            for i in 0..width {
+                dense_state[i] += s_params.round_keys[offset + r * width + i];
+            }
+            dense_state[width - 1] = sbox_type.apply_sbox(&dense_state[width - 1]);
+            dense_state = mat_vec_mul(&s_params.MDS_matrix, &dense_state);
+        }
+        let mut sparse_state = mat_vec_mul(&sparse.pre_matrix, &input);
+        for r in 0..s_params.partial_rounds {
+            sparse_state[width - 1] = sbox_type.apply_sbox(&(sparse_state[width - 1] + sparse.round_constants[r]));
+            sparse_state = sparse.sparse_matrices[r].apply(&sparse_state);
+        }
+        for i in 0..width {
+            sparse_state[i] += sparse.post_constants[i];
+        }
+        assert_eq!(sparse_state, dense_state);
+
+        // Terms of the linear combinations in the synthesized constraints. With the sparse matrices, the partial
+        // rounds add the round constant only to the S-box input so the state has no constant term.
+        let num_terms = |params: &PoseidonParams| {
+            let pc_gens = PedersenGens::default();
+            let mut transcript = Transcript::new(b"Poseidon_sparse_terms");
+            let mut prover = Prover::new(&pc_gens, &mut transcript);
+            let input = (0..width).map(|i| LinearCombination::from(prover.commit(Scalar::from(i as u64), Scalar::one()).1)).collect();
+            let matrices = capture_constraints(&mut prover, |cs| {
+                Poseidon_permutation_constraints(cs, input, params, &SboxType::Cube).map(|_| ())
+            }).unwrap();
+            (matrices.num_rows(), matrices.a.iter().map(|row| row.len()).sum::<usize>())
+        };
+        let (dense_rows, dense_terms) = num_terms(&s_params);
+        let (sparse_rows, sparse_terms) = num_terms(&s_params_opt);
+        assert_eq!(sparse_rows, dense_rows);
+        assert!(sparse_terms < dense_terms);
+
+        for sbox_type in &[SboxType::Cube, SboxType::Inverse] {
+            assert_native_matches_circuit(&s_params_opt, sbox_type);
+            let xl = Scalar::random(&mut test_rng);
+            let xr = Scalar::random(&mut test_rng);
+            let expected_output = Poseidon_hash_2(xl, xr, &s_params, sbox_type).unwrap();
+
+            let pc_gens = PedersenGens::default();
+            let bp_gens = BulletproofGens::new(2048, 1);
+            let blindings = vec![Scalar::random(&mut test_rng), Scalar::random(&mut test_rng)];
+            let label = b"Poseidon_hash_2_sparse";
+
+            // Number of multipliers and constraints with dense and sparse matrices
+            let counts = |params: &PoseidonParams| {
+                let mut transcript = Transcript::new(label);
+                let mut prover = Prover::new(&pc_gens, &mut transcript);
+                let (_, var_l) = prover.commit(xl, blindings[0]);
+                let (_, var_r) = prover.commit(xr, blindings[1]);
+                let statics = allocate_statics_for_prover(&mut prover, width - 2);
+                let start = Instant::now();
+                Poseidon_hash_2_gadget(&mut prover, AllocatedScalar::known(var_l, xl),
+                                       AllocatedScalar::known(var_r, xr),
+                                       statics, params, sbox_type, &expected_output).unwrap();
+                println!("Synthesis time is {:?}", start.elapsed());
+                (prover.num_multipliers(), prover.num_constraints())
+            };
+            let (dense_multipliers, dense_constraints) = counts(&s_params);
+            let (sparse_multipliers, sparse_constraints) = counts(&s_params_opt);
+            println!("Dense: {} multipliers, {} constraints. Sparse: {} multipliers, {} constraints",
+                     dense_multipliers, dense_constraints, sparse_multipliers, sparse_constraints);
+            // Linear layers do not need any constraint so the counts are the same, the constraints have fewer terms
+            assert_eq!(sparse_multipliers, dense_multipliers);
+            assert_eq!(sparse_constraints, dense_constraints);
+
+            // The sparse constraints are satisfied by the output of the dense permutation
+            let (proof, comms) = gen_proof_of_Poseidon_hash_2_with_blindings(xl, xr, &blindings, &s_params_opt, sbox_type, label, &pc_gens, &bp_gens).unwrap();
+            assert!(verify_proof_of_Poseidon_hash_2(&expected_output, &s_params_opt, sbox_type, proof, comms, label, &pc_gens, &bp_gens).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_poseidon_registry() {
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let x = (0..4).map(|_| Scalar::random(&mut test_rng)).collect::<Vec<_>>();
+
+        let mut registry = PoseidonRegistry::empty(SboxType::Inverse);
+        registry.insert(PoseidonParams::new(3, 4, 4, 140));
+        let mut other = PoseidonRegistry::empty(SboxType::Inverse);
+        other.insert(get_poseidon_params());
+        registry.merge(other);
+        assert!(registry.get(5).is_none());
+        assert_eq!(registry.hash(5, &x[..2]), Err(PoseidonError::UnsupportedWidth(5)));
+
+        let s_params = registry.get(6).unwrap();
+        assert_eq!(s_params.MDS_matrix, PoseidonParams::gen_cauchy_matrix(6));
+        assert_eq!(registry.hash(6, &x[..2]).unwrap(), Poseidon_hash_2(x[0], x[1], s_params, &SboxType::Inverse).unwrap());
+        assert_eq!(registry.hash(6, &x).unwrap(), Poseidon_hash_4([x[0], x[1], x[2], x[3]], s_params, &SboxType::Inverse).unwrap());
+
+        let h = registry.hash(3, &x[..1]).unwrap();
+        let s_params_3 = registry.get(3).unwrap();
+        assert_eq!(s_params_3.width, 3);
+        assert_eq!(h.into_scalar(), Poseidon_permutation(&[Scalar::zero(), x[0], Scalar::from(PADDING_CONST)], s_params_3, &SboxType::Inverse)[1]);
+        assert_ne!(h, registry.hash(6, &x[..1]).unwrap());
+        assert!(registry.hash(3, &x[..2]).is_err());
+
+        let registry = PoseidonRegistry::new(SboxType::Cube);
+        for w in REGISTRY_WIDTHS.iter() {
+            let params = registry.get(*w).unwrap();
+            assert!(params.check_security_margin(&registry.sbox, SCALAR_FIELD_BITS).is_ok());
+            assert!(registry.hash(*w, &x[..*w - 2]).is_ok());
+        }
+    }
+
+    fn poseidon_hash_2_committed_output(xl: Scalar, xr: Scalar, output: Scalar, sbox_type: &SboxType) -> Result<(), R1CSError> {
+        let s_params = get_poseidon_params();
+        let width = s_params.width;
+        let transcript_label = b"Poseidon_hash_2_committed_output";
+
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(transcript_label);
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let mut comms = vec![];
+            let mut allocs = vec![];
+            for v in &[xl, xr, output] {
+                let (com, var) = prover.commit(*v, Scalar::random(&mut test_rng));
+                comms.push(com);
+                allocs.push(AllocatedScalar {
+                    variable: var,
+                    assignment: Some(*v),
+                });
+            }
+
+            let statics = allocate_statics_for_prover(&mut prover, width - 2);
+            Poseidon_hash_2_committed_output_gadget(&mut prover, allocs[0], allocs[1], statics, allocs[2], &s_params, sbox_type)?;
+
+            (prover.prove(&bp_gens)?, comms)
+        };
+
+        let mut verifier_transcript = Transcript::new(transcript_label);
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let allocs = commitments.iter().map(|c| AllocatedScalar {
+            variable: verifier.commit(*c),
+            assignment: None,
+        }).collect::<Vec<_>>();
+
+        let statics = allocate_statics_for_verifier(&mut verifier, width - 2, &pc_gens);
+        Poseidon_hash_2_committed_output_gadget(&mut verifier, allocs[0], allocs[1], statics, allocs[2], &s_params, sbox_type)?;
+
+        verifier.verify(&proof, &pc_gens, &bp_gens)
+    }
+
+    #[test]
+    fn test_poseidon_hash_2_committed_output() {
+        let s_params = get_poseidon_params();
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let xl = Scalar::random(&mut test_rng);
+        let xr = Scalar::random(&mut test_rng);
+        for sbox_type in &[SboxType::Cube, SboxType::Inverse] {
+            let output = Poseidon_hash_2(xl, xr, &s_params, sbox_type).unwrap().into_scalar();
+            assert!(poseidon_hash_2_committed_output(xl, xr, output, sbox_type).is_ok());
+            assert!(poseidon_hash_2_committed_output(xl, xr, output + Scalar::one(), sbox_type).is_err());
+        }
+    }
+
+    #[test]
+    fn test_poseidon_hash_newtype() {
+        let s_params = get_poseidon_params();
+        let sbox_type = &SboxType::Inverse;
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let xl = Scalar::random(&mut test_rng);
+        let xr = Scalar::random(&mut test_rng);
+
+        let h = Poseidon_hash_2(xl, xr, &s_params, sbox_type).unwrap();
+        let s: Scalar = h.into();
+        assert_eq!(PoseidonHash::from(s), h);
+        assert_eq!(h.into_scalar(), s);
+        assert_eq!(*h, s);
+        assert_eq!(h.to_bytes(), s.to_bytes());
+
+        // Hash of hashes needs explicit conversion
+        let h1 = Poseidon_hash_2(h.into_scalar(), xr, &s_params, sbox_type).unwrap();
+        assert_eq!(h1, Poseidon_hash_n(&[s, xr], &s_params, sbox_type).unwrap());
+        assert_ne!(h1, h);
+    }
+
+    #[test]
+    fn test_poseidon_hash_ct_eq() {
+        let s_params = get_poseidon_params();
+        let sbox_type = &SboxType::Inverse;
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let xl = Scalar::random(&mut test_rng);
+        let xr = Scalar::random(&mut test_rng);
+        let h = Poseidon_hash_2(xl, xr, &s_params, sbox_type).unwrap();
+
+        assert_eq!(poseidon_hash_ct_eq(&h, &h.into_scalar()).unwrap_u8(), 1);
+        assert_eq!(poseidon_hash_ct_eq(&h, &(*h + Scalar::one())).unwrap_u8(), 0);
+        assert!(bool::from(poseidon_hash_ct_eq(&Scalar::zero(), &Scalar::zero())));
+        assert!(!bool::from(poseidon_hash_ct_eq(&xl, &xr)));
+    }
+
+    #[test]
+    fn test_poseidon_hash_2_inverse_unchecked_sbox() {
+        let s_params = get_poseidon_params();
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let xl = Scalar::random(&mut test_rng);
+        let xr = Scalar::random(&mut test_rng);
+        let expected_output = Poseidon_hash_2(xl, xr, &s_params, &SboxType::Inverse).unwrap();
+        assert_eq!(Poseidon_hash_2(xl, xr, &s_params, &SboxType::InverseUnchecked).unwrap(), expected_output);
+        assert_native_matches_circuit(&s_params, &SboxType::InverseUnchecked);
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let label = b"Poseidon_hash_2_inverse_unchecked";
+
+        let counts = |sbox_type: &SboxType| {
+            let mut transcript = Transcript::new(label);
+            let mut prover = Prover::new(&pc_gens, &mut transcript);
+            let (_, var_l) = prover.commit(xl, Scalar::one());
+            let (_, var_r) = prover.commit(xr, Scalar::one());
+            let statics = allocate_statics_for_prover(&mut prover, s_params.width - 2);
+            Poseidon_hash_2_gadget(&mut prover, AllocatedScalar::known(var_l, xl),
+                                   AllocatedScalar::known(var_r, xr),
+                                   statics, &s_params, sbox_type, &expected_output).unwrap();
+            (prover.num_multipliers(), prover.num_constraints())
+        };
+        let (checked_multipliers, checked_constraints) = counts(&SboxType::Inverse);
+        let (unchecked_multipliers, unchecked_constraints) = counts(&SboxType::InverseUnchecked);
+        println!("Inverse: {} multipliers, {} constraints. Unchecked inverse: {} multipliers, {} constraints",
+                 checked_multipliers, checked_constraints, unchecked_multipliers, unchecked_constraints);
+        assert!(unchecked_multipliers < checked_multipliers);
+        assert!(unchecked_constraints < checked_constraints);
+
+        let (proof, comms) = gen_proof_of_Poseidon_hash_2(xl, xr, &s_params, &SboxType::InverseUnchecked, &mut test_rng, label, &pc_gens, &bp_gens).unwrap();
+        assert!(verify_proof_of_Poseidon_hash_2(&expected_output, &s_params, &SboxType::InverseUnchecked, proof, comms, label, &pc_gens, &bp_gens).is_ok());
+    }
+
+
+    #[test]
+    fn test_poseidon_hash_2_num_commitments() {
+        let s_params = get_poseidon_params();
+        let width = s_params.width;
+        let sbox_type = &SboxType::Inverse;
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let xl = Scalar::random(&mut test_rng);
+        let xr = Scalar::random(&mut test_rng);
+        let expected_output = Poseidon_hash_2(xl, xr, &s_params, sbox_type).unwrap();
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let label = b"Poseidon_hash_2_num_commitments";
+
+        // Committed statics, each static is a commitment
+        let mut transcript = Transcript::new(label);
+        let mut prover = Prover::new(&pc_gens, &mut transcript);
+        let (_, var_l) = prover.commit(xl, Scalar::random(&mut test_rng));
+        let (_, var_r) = prover.commit(xr, Scalar::random(&mut test_rng));
+        let statics = allocate_statics_for_prover(&mut prover, width - 2);
+        assert_eq!(2 + statics.len(), poseidon_hash_2_num_commitments(width));
+        Poseidon_hash_2_gadget(&mut prover, AllocatedScalar::known(var_l, xl),
+                               AllocatedScalar::known(var_r, xr),
+                               statics, &s_params, sbox_type, &expected_output).unwrap();
+
+        // Constant statics, only xl and xr are committed
+        let (proof, comms) = {
+            let mut transcript = Transcript::new(label);
+            let mut prover = Prover::new(&pc_gens, &mut transcript);
+            let (com_l, var_l) = prover.commit(xl, Scalar::random(&mut test_rng));
+            let (com_r, var_r) = prover.commit(xr, Scalar::random(&mut test_rng));
+            let hash = Poseidon_hash_2_constraints(&mut prover, var_l.into(), var_r.into(), constant_statics(width - 2), &s_params, sbox_type).unwrap();
+            constrain_lc_with_scalar(&mut prover, hash, &expected_output);
+            (prover.prove(&bp_gens).unwrap(), vec![com_l, com_r])
+        };
+        assert_eq!(comms.len(), poseidon_hash_2_num_commitments_with_constant_statics());
+
+        let mut transcript = Transcript::new(label);
+        let mut verifier = Verifier::new(&mut transcript);
+        let var_l = verifier.commit(comms[0]);
+        let var_r = verifier.commit(comms[1]);
+        let hash = Poseidon_hash_2_constraints(&mut verifier, var_l.into(), var_r.into(), constant_statics(width - 2), &s_params, sbox_type).unwrap();
+        constrain_lc_with_scalar(&mut verifier, hash, &expected_output);
+        assert!(verifier.verify(&proof, &pc_gens, &bp_gens).is_ok());
+    }
+
+
+    #[test]
+    fn test_poseidon_params_default() {
+        let s_params = PoseidonParams::default();
+        let expected = get_poseidon_params();
+        assert_eq!((s_params.width, s_params.full_rounds_beginning, s_params.full_rounds_end, s_params.partial_rounds), (6, 4, 4, 140));
+        assert_eq!(s_params.round_keys, expected.round_keys);
+        assert_eq!(s_params.MDS_matrix, expected.MDS_matrix);
+        assert!(s_params.check_security_margin(&SboxType::Inverse, SCALAR_FIELD_BITS).is_ok());
+
+        // Known answer for the hash of 1 and 2 with the hardcoded constants
+        let (xl, xr) = (Scalar::from(1u64), Scalar::from(2u64));
+        let expected_output = Poseidon_hash_2(xl, xr, &s_params, &SboxType::Inverse).unwrap();
+        #[cfg(not(feature = "generated-constants"))]
+        assert_eq!(get_hex_from_scalar(&expected_output), "0x044897fb7a350fe508ce0c93165c54570775808e853c7c6a382fdbddafe32daa");
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let label = b"Poseidon_params_default";
+        let (proof, comms) = gen_proof_of_Poseidon_hash_2(xl, xr, &s_params, &SboxType::Inverse, &mut test_rng, label, &pc_gens, &bp_gens).unwrap();
+        assert!(verify_proof_of_Poseidon_hash_2(&expected_output, &s_params, &SboxType::Inverse, proof, comms, label, &pc_gens, &bp_gens).is_ok());
+    }
+
+    fn poseidon_hash_2_zero_mode(constant: bool) -> Result<usize, R1CSError> {
+        let s_params = get_poseidon_params();
+        let width = s_params.width;
+        let sbox_type = &SboxType::Inverse;
+        let (xl, xr) = (Scalar::from(3u64), Scalar::from(4u64));
+        let expected_output = Poseidon_hash_2(xl, xr, &s_params, sbox_type).unwrap();
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let label = b"Poseidon_hash_2_zero_mode";
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+
+        let (proof, comms, num_commitments) = {
+            let mut transcript = Transcript::new(label);
+            let mut prover = Prover::new(&pc_gens, &mut transcript);
+            let (com_l, var_l) = prover.commit(xl, Scalar::random(&mut test_rng));
+            let (com_r, var_r) = prover.commit(xr, Scalar::random(&mut test_rng));
+            let mut num_commitments = 2;
+            let zero_mode = if constant {
+                ZeroMode::Constant
+            } else {
+                let statics = allocate_statics_for_prover(&mut prover, width - 2);
+                num_commitments += statics.len();
+                ZeroMode::Committed(statics)
+            };
+            Poseidon_hash_2_gadget_ex(&mut prover, AllocatedScalar::known(var_l, xl), AllocatedScalar::known(var_r, xr),
+                                      &s_params, sbox_type, &expected_output, zero_mode)?;
+            (prover.prove(&bp_gens)?, vec![com_l, com_r], num_commitments)
+        };
+
+        let mut transcript = Transcript::new(label);
+        let mut verifier = Verifier::new(&mut transcript);
+        let l_alloc = AllocatedScalar::unknown(verifier.commit(comms[0]));
+        let r_alloc = AllocatedScalar::unknown(verifier.commit(comms[1]));
+        let zero_mode = if constant {
+            ZeroMode::Constant
+        } else {
+            ZeroMode::Committed(allocate_statics_for_verifier(&mut verifier, width - 2, &pc_gens))
+        };
+        Poseidon_hash_2_gadget_ex(&mut verifier, l_alloc, r_alloc, &s_params, sbox_type, &expected_output, zero_mode)?;
+        verifier.verify(&proof, &pc_gens, &bp_gens)?;
+        Ok(num_commitments)
+    }
+
+    #[test]
+    fn test_poseidon_hash_2_gadget_ex() {
+        let committed = poseidon_hash_2_zero_mode(false).unwrap();
+        let constant = poseidon_hash_2_zero_mode(true).unwrap();
+        assert_eq!(committed, poseidon_hash_2_num_commitments(6));
+        assert_eq!(constant, poseidon_hash_2_num_commitments_with_constant_statics());
+        assert!(constant < committed);
+    }
+
+    #[test]
+    fn test_poseidon_transcript_label() {
+        let params_a = get_poseidon_params();
+        let params_b = PoseidonParams::new(6, 4, 4, 139);
+        let sbox_type = &SboxType::Inverse;
+        let label_a = poseidon_transcript_label(&params_a, sbox_type, PoseidonHashKind::Hash2);
+        assert_eq!(label_a, poseidon_transcript_label(&PoseidonParams::default(), sbox_type, PoseidonHashKind::Hash2));
+        assert_ne!(label_a, poseidon_transcript_label(&params_b, sbox_type, PoseidonHashKind::Hash2));
+        assert_ne!(label_a, poseidon_transcript_label(&params_a, &SboxType::Cube, PoseidonHashKind::Hash2));
+        assert_ne!(label_a, poseidon_transcript_label(&params_a, sbox_type, PoseidonHashKind::Hash4));
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let label = b"Poseidon_transcript_label";
+        let (xl, xr) = (Scalar::random(&mut test_rng), Scalar::random(&mut test_rng));
+        let expected_output = Poseidon_hash_2(xl, xr, &params_a, sbox_type).unwrap();
+
+        let (proof, comms) = gen_proof_of_Poseidon_hash_2(xl, xr, &params_a, sbox_type, &mut test_rng, label, &pc_gens, &bp_gens).unwrap();
+        assert!(verify_proof_of_Poseidon_hash_2(&expected_output, &params_a, sbox_type, proof.clone(), comms.clone(), label, &pc_gens, &bp_gens).is_ok());
+
+        // Same circuit, rounds and constants as in `verify_proof_of_Poseidon_hash_2` but the transcript has the label
+        // of another hash kind, so only the label differs
+        let mut verifier_transcript = Transcript::new(label);
+        verifier_transcript.append_message(b"poseidon_params", &poseidon_transcript_label(&params_a, sbox_type, PoseidonHashKind::Hash4));
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let l_alloc = AllocatedScalar::unknown(verifier.commit(comms[0]));
+        let r_alloc = AllocatedScalar::unknown(verifier.commit(comms[1]));
+        let statics = allocate_statics_for_verifier(&mut verifier, params_a.width - 2, &pc_gens);
+        Poseidon_hash_2_gadget(&mut verifier, l_alloc, r_alloc, statics, &params_a, sbox_type, &expected_output).unwrap();
+        assert!(verifier.verify(&proof, &pc_gens, &bp_gens).is_err());
+    }
+
+    #[test]
+    fn test_poseidon_width_too_small() {
+        let pc_gens = PedersenGens::default();
+        let mut transcript = Transcript::new(b"Poseidon_width_too_small");
+        let mut prover = Prover::new(&pc_gens, &mut transcript);
+        let (xl, xr) = (Scalar::from(1u64), Scalar::from(2u64));
+        let inputs: [LinearCombination; 4] = [xl.into(), xr.into(), xl.into(), xr.into()];
+
+        let s_params = PoseidonParams::new(2, 4, 4, 140);
+        let expected_err = R1CSError::from(PoseidonError::WidthTooSmall { width: 2, min_width: 3 });
+        assert_eq!(Poseidon_hash_2_constraints(&mut prover, xl.into(), xr.into(), vec![], &s_params, &SboxType::Inverse).unwrap_err().to_string(),
+                   expected_err.to_string());
+        assert_eq!(Poseidon_hash_n(&[xl, xr], &s_params, &SboxType::Inverse), Err(PoseidonError::WidthTooSmall { width: 2, min_width: 3 }));
+        assert_eq!(Poseidon_hash_2(xl, xr, &s_params, &SboxType::Inverse), Err(PoseidonError::WidthTooSmall { width: 2, min_width: 3 }));
+        assert_eq!(Poseidon_hash_2_batch(&[(xl, xr)], &s_params, &SboxType::Inverse), Err(PoseidonError::WidthTooSmall { width: 2, min_width: 3 }));
+
+        // The width is checked before computing the number of statics so a width less than 2 does not underflow
+        let mut s_params = PoseidonParams::new(2, 4, 4, 140);
+        s_params.width = 1;
+        let sbox_type = &SboxType::Inverse;
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let (_, var) = prover.commit(xl, Scalar::one());
+        let alloc = AllocatedScalar::known(var, xl);
+        let expected_err = R1CSError::from(PoseidonError::WidthTooSmall { width: 1, min_width: 3 }).to_string();
+        assert_eq!(Poseidon_hash_2_gadget_ex(&mut prover, alloc, alloc, &s_params, sbox_type, &xl, ZeroMode::Constant).unwrap_err().to_string(), expected_err);
+        assert_eq!(Poseidon_hash_2_mixed_gadget(&mut prover, alloc, xr, &s_params, sbox_type, &xl).unwrap_err().to_string(), expected_err);
+        assert_eq!(addressed_commitment_gadget(&mut prover, xl, alloc, alloc, &s_params, sbox_type).unwrap_err().to_string(), expected_err);
+        assert!(gen_proof_of_Poseidon_hash_2(xl, xr, &s_params, sbox_type, &mut test_rng, b"Poseidon_width_too_small", &pc_gens, &bp_gens).is_err());
+        assert!(gen_proof_of_Poseidon_hash_2_batch(&[(xl, xr)], &s_params, sbox_type, &mut test_rng, b"Poseidon_width_too_small", &pc_gens, &bp_gens).is_err());
+
+        // Wrong number of statics is an error and not a panic
+        let s_params = get_poseidon_params();
+        assert!(Poseidon_hash_2_constraints(&mut prover, xl.into(), xr.into(), constant_statics(3), &s_params, sbox_type).is_err());
+        assert!(Poseidon_hash_4_constraints(&mut prover, inputs.clone(), constant_statics(1), &s_params, sbox_type).is_err());
+
+        let s_params = PoseidonParams::new(4, 4, 4, 140);
+        let expected_err = R1CSError::from(PoseidonError::WidthTooSmall { width: 4, min_width: 5 });
+        assert_eq!(Poseidon_hash_4_constraints(&mut prover, inputs, vec![], &s_params, &SboxType::Inverse).unwrap_err().to_string(),
+                   expected_err.to_string());
+        assert_eq!(Poseidon_hash_4([xl, xr, xl, xr], &s_params, &SboxType::Inverse), Err(PoseidonError::WidthTooSmall { width: 4, min_width: 5 }));
+        // Width 4 is enough for hashing 2 inputs
+        assert!(Poseidon_hash_2_constraints(&mut prover, xl.into(), xr.into(), constant_statics(2), &s_params, &SboxType::Inverse).is_ok());
+        assert_eq!(Poseidon_hash_2(xl, xr, &s_params, &SboxType::Inverse).unwrap(), Poseidon_hash_n(&[xl, xr], &s_params, &SboxType::Inverse).unwrap());
+    }
+
+    #[test]
+    fn test_poseidon_convert_sbox() {
+        let cube_params = PoseidonParams::default();
+        assert!(cube_params.check_security_margin(&SboxType::Cube, SCALAR_FIELD_BITS).is_ok());
+
+        let inverse_params = cube_params.convert_sbox(SboxType::Inverse, SCALAR_FIELD_BITS).unwrap();
+        assert!(inverse_params.check_security_margin(&SboxType::Inverse, SCALAR_FIELD_BITS).is_ok());
+        assert_eq!((inverse_params.full_rounds_beginning, inverse_params.full_rounds_end, inverse_params.partial_rounds), (4, 4, 56));
+        assert_eq!(inverse_params.round_keys.len(), inverse_params.get_total_rounds() * inverse_params.width);
+        assert_eq!(inverse_params.round_keys, PoseidonParams::new(6, 4, 4, 56).round_keys);
+        assert_eq!(inverse_params.MDS_matrix, cube_params.MDS_matrix);
+        // Inverse needs fewer rounds so it does not pass for the cube S-box
+        assert!(inverse_params.check_security_margin(&SboxType::Cube, SCALAR_FIELD_BITS).is_err());
+
+        // And back to the cube S-box
+        let cube_params_1 = inverse_params.convert_sbox(SboxType::Cube, SCALAR_FIELD_BITS);
+        #[cfg(not(feature = "generated-constants"))]
+        assert_eq!(cube_params_1.unwrap_err(), PoseidonError::NotEnoughRoundKeys { needed: 91 * 6, found: 64 * 6 });
+        #[cfg(feature = "generated-constants")]
+        assert!(cube_params_1.unwrap().check_security_margin(&SboxType::Cube, SCALAR_FIELD_BITS).is_ok());
+    }
+
+    #[test]
+    fn test_poseidon_params_from_parts() {
+        let s_params = get_poseidon_params();
+        assert!(s_params.validate().is_ok());
+        let params = PoseidonParams::from_parts(6, 4, 4, 140, s_params.round_keys.clone(), s_params.MDS_matrix.clone()).unwrap();
+        assert_eq!(Poseidon_permutation(&vec![Scalar::one(); 6], &params, &SboxType::Cube),
+                   Poseidon_permutation(&vec![Scalar::one(); 6], &s_params, &SboxType::Cube));
+
+        // Round keys for 139 partial rounds
+        let short_keys = s_params.round_keys[..147 * 6].to_vec();
+        assert_eq!(PoseidonParams::from_parts(6, 4, 4, 140, short_keys, s_params.MDS_matrix.clone()).unwrap_err(),
+                   PoseidonError::InconsistentSizes { width: 6, expected_round_keys: 148 * 6, found_round_keys: 147 * 6, found_mds_rows: 6, found_mds_columns: 6 });
+        // MDS matrix for width 5
+        let mds_5: Vec<Vec<Scalar>> = s_params.MDS_matrix[..5].iter().map(|r| r[..5].to_vec()).collect();
+        let err = PoseidonParams::from_parts(6, 4, 4, 140, s_params.round_keys.clone(), mds_5).unwrap_err();
+        assert_eq!(err, PoseidonError::InconsistentSizes { width: 6, expected_round_keys: 148 * 6, found_round_keys: 148 * 6, found_mds_rows: 5, found_mds_columns: 5 });
+        assert_eq!(err.to_string(), "Need 888 round keys and a 6x6 MDS matrix, found 888 round keys and a 5x5 MDS matrix");
+        // One short row
+        let mut mds = s_params.MDS_matrix.clone();
+        mds[3].pop();
+        assert!(PoseidonParams::from_parts(6, 4, 4, 140, s_params.round_keys.clone(), mds).is_err());
+    }
+
+    #[test]
+    fn test_poseidon_with_partial_rounds() {
+        let s_params = get_poseidon_params();
+        let sbox_type = &SboxType::Inverse;
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let (xl, xr) = (Scalar::random(&mut test_rng), Scalar::random(&mut test_rng));
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let label = b"Poseidon_with_partial_rounds";
+
+        for partial_rounds in &[60, 120] {
+            let s_params_1 = s_params.with_partial_rounds(*partial_rounds).unwrap();
+            assert_eq!(s_params_1.round_keys, PoseidonParams::new(6, 4, 4, *partial_rounds).round_keys);
+            assert_eq!(s_params_1.MDS_matrix, s_params.MDS_matrix);
+
+            let expected_output = Poseidon_hash_2(xl, xr, &s_params_1, sbox_type).unwrap();
+            assert_ne!(expected_output, Poseidon_hash_2(xl, xr, &s_params, sbox_type).unwrap());
+            let (proof, comms) = gen_proof_of_Poseidon_hash_2(xl, xr, &s_params_1, sbox_type, &mut test_rng, label, &pc_gens, &bp_gens).unwrap();
+            assert!(verify_proof_of_Poseidon_hash_2(&expected_output, &s_params_1, sbox_type, proof, comms, label, &pc_gens, &bp_gens).is_ok());
+        }
+
+        assert_eq!(s_params.with_partial_rounds(140).unwrap().round_keys, s_params.round_keys);
+        // Not enough round keys for more partial rounds
+        #[cfg(not(feature = "generated-constants"))]
+        assert_eq!(s_params.with_partial_rounds(141).unwrap_err(), PoseidonError::NotEnoughRoundKeys { needed: 149 * 6, found: 148 * 6 });
+    }
+
+    #[test]
+    fn test_poseidon_permutation_taps() {
+        let dense_params = get_poseidon_params();
+        let sparse_params = PoseidonParams::new_optimized(6, 4, 4, 140);
+        let width = dense_params.width;
+        let total_rounds = dense_params.get_total_rounds();
+        let sbox_type = &SboxType::Inverse;
+        let input = (0..width).map(|i| Scalar::from(i as u64 + 1)).collect::<Vec<_>>();
+        let expected_output = Poseidon_permutation(&input, &dense_params, sbox_type);
+
+        let partial_tap = (dense_params.full_rounds_beginning + 10, width - 1);
+        let mut taps = vec![(0, 2), partial_tap];
+        taps.extend((0..width).map(|i| (total_rounds - 1, i)));
+
+        let pc_gens = PedersenGens::default();
+        let tapped_values = |params: &PoseidonParams, taps: &[(usize, usize)]| {
+            let mut transcript = Transcript::new(b"Poseidon_permutation_taps");
+            let mut prover = Prover::new(&pc_gens, &mut transcript);
+            let input_vars = input.iter().map(|i| LinearCombination::from(prover.commit(*i, Scalar::one()).1)).collect();
+            let (output, tapped) = Poseidon_permutation_constraints_with_taps(&mut prover, input_vars, params, sbox_type, taps)?;
+            let output: Vec<Scalar> = output.iter().map(|o| prover.evaluate_lc(o).unwrap()).collect();
+            assert_eq!(output, expected_output);
+            Ok::<Vec<Scalar>, R1CSError>(tapped.iter().map(|t| prover.evaluate_lc(t).unwrap()).collect())
+        };
+
+        let values = tapped_values(&dense_params, &taps).unwrap();
+        // S-box output of the first round
+        assert_eq!(values[0], sbox_type.apply_sbox(&(input[2] + dense_params.round_keys[2])));
+        // Output is the MDS matrix times the S-box outputs of the last round
+        assert_eq!(mat_vec_mul(&dense_params.MDS_matrix, &values[2..]), expected_output);
+        // Partial round S-box outputs are the same with sparse matrices
+        assert_eq!(tapped_values(&sparse_params, &taps).unwrap(), values);
+
+        // No S-box at these
+        assert!(tapped_values(&dense_params, &[(total_rounds, 0)]).is_err());
+        assert!(tapped_values(&dense_params, &[(0, width)]).is_err());
+        assert!(tapped_values(&dense_params, &[(partial_tap.0, 0)]).is_err());
+    }
+
+    #[test]
+    fn test_poseidon_partial_round_sbox_index() {
+        let base = PoseidonParams::new(6, 4, 4, 10);
+        let (width, full_b) = (base.width, base.full_rounds_beginning);
+        let sbox_type = &SboxType::Cube;
+        let input = (0..width).map(|i| Scalar::from(i as u64 + 1)).collect::<Vec<_>>();
+        // The first full rounds and `k` partial rounds of `base`
+        let truncated = |k: usize| PoseidonParams::from_parts(width, full_b, 0, k, base.round_keys[..(full_b + k) * width].to_vec(),
+                                                              base.MDS_matrix.clone()).unwrap();
+
+        let pc_gens = PedersenGens::default();
+        for k in 0..base.partial_rounds {
+            let round = full_b + k;
+            let before = Poseidon_permutation(&input, &truncated(k), sbox_type);
+            let after = Poseidon_permutation(&input, &truncated(k + 1), sbox_type);
+
+            let mut transcript = Transcript::new(b"Poseidon_partial_round_sbox_index");
+            let mut prover = Prover::new(&pc_gens, &mut transcript);
+            let input_vars = input.iter().map(|i| LinearCombination::from(prover.commit(*i, Scalar::one()).1)).collect();
+            let (output, tapped) = Poseidon_permutation_constraints_with_taps(&mut prover, input_vars, &truncated(k + 1), sbox_type, &[(round, width - 1)]).unwrap();
+            let circuit_after: Vec<Scalar> = output.iter().map(|o| prover.evaluate_lc(o).unwrap()).collect();
+            assert_eq!(circuit_after, after, "State after round {} differs", round);
+
+            let sbox_in: Vec<Scalar> = (0..width).map(|i| before[i] + base.round_keys[round * width + i]).collect();
+            let sbox_out = prover.evaluate_lc(&tapped[0]).unwrap();
+            assert_eq!(sbox_out, sbox_type.apply_sbox(&sbox_in[width - 1]), "S-box output of round {} differs", round);
+            let mut layer = sbox_in.clone();
+            layer[width - 1] = sbox_out;
+            assert_eq!(mat_vec_mul(&base.MDS_matrix, &layer), after);
+            // The S-box at any other index gives another state
+            for i in 0..width - 1 {
+                let mut other = sbox_in.clone();
+                other[i] = sbox_type.apply_sbox(&other[i]);
+                assert_ne!(mat_vec_mul(&base.MDS_matrix, &other), after, "Round {} matches an S-box at index {}", round, i);
+            }
+        }
+    }
+
+    /// Returns the number of constraints and multipliers, proving time and verification time of the permutation
+    fn poseidon_perm_costs(s_params: &PoseidonParams, sbox_type: &SboxType, transcript_label: &'static [u8]) -> (usize, usize, Duration, Duration) {
+        let width = s_params.width;
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let input = (0..width).map(|_| Scalar::random(&mut test_rng)).collect::<Vec<_>>();
+        let expected_output = Poseidon_permutation(&input, s_params, sbox_type);
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+
+        let start = Instant::now();
+        let (proof, commitments, num_constraints, num_multipliers) = {
+            let mut prover_transcript = Transcript::new(transcript_label);
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let mut comms = vec![];
+            let mut allocs = vec![];
+            for i in 0..width {
+                let (com, var) = prover.commit(input[i], Scalar::random(&mut test_rng));
+                comms.push(com);
+                allocs.push(AllocatedScalar::known(var, input[i]));
+            }
+            Poseidon_permutation_gadget(&mut prover, allocs, s_params, sbox_type, &expected_output).unwrap();
+            let (num_constraints, num_multipliers) = (prover.num_constraints(), prover.num_multipliers());
+            (prover.prove(&bp_gens).unwrap(), comms, num_constraints, num_multipliers)
+        };
+        let proving_time = start.elapsed();
+
+        let start = Instant::now();
+        let mut verifier_transcript = Transcript::new(transcript_label);
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let allocs = commitments.iter().map(|c| AllocatedScalar::unknown(verifier.commit(*c))).collect();
+        Poseidon_permutation_gadget(&mut verifier, allocs, s_params, sbox_type, &expected_output).unwrap();
+        assert!(verifier.verify(&proof, &pc_gens, &bp_gens).is_ok());
+        let verification_time = start.elapsed();
+
+        (num_constraints, num_multipliers, proving_time, verification_time)
+    }
+
+    #[test]
+    fn test_poseidon_perm_sbox_costs() {
+        let s_params = get_poseidon_params();
+        let (full_b, full_e, partial) = (s_params.full_rounds_beginning, s_params.full_rounds_end, s_params.partial_rounds);
+        let num_sboxes = (full_b + full_e) * s_params.width + partial;
+
+        let (cube_constraints, cube_multipliers, cube_proving, cube_verification) = poseidon_perm_costs(&s_params, &SboxType::Cube, b"Poseidon_perm_costs_cube");
+        let (inv_constraints, inv_multipliers, inv_proving, inv_verification) = poseidon_perm_costs(&s_params, &SboxType::Inverse, b"Poseidon_perm_costs_inverse");
+
+        println!("Poseidon permutation with width {}, {} full and {} partial rounds, {} S-boxes", s_params.width, full_b + full_e, partial, num_sboxes);
+        println!("S-box   | constraints | multipliers | proving time | verification time");
+        println!("Cube    | {:11} | {:11} | {:?} | {:?}", cube_constraints, cube_multipliers, cube_proving, cube_verification);
+        println!("Inverse | {:11} | {:11} | {:?} | {:?}", inv_constraints, inv_multipliers, inv_proving, inv_verification);
+
+        // Cube needs 2 multipliers per S-box. Inverse needs 1 for the inverse and 2 more for `is_nonzero_gadget`
+        assert_eq!(cube_multipliers, 2 * num_sboxes);
+        assert_eq!(inv_multipliers, 3 * num_sboxes);
+        assert!(inv_constraints > cube_constraints);
+    }
+
+    #[test]
+    fn test_poseidon_partial_sbox_count() {
+        let sbox_type = &SboxType::Cube;
+        let width = 6;
+        let input: Vec<Scalar> = (0..width).map(|i| Scalar::from(i as u64 + 3)).collect();
+        // Permutation output natively and in constraints along with the number of multipliers
+        let permute = |params: &PoseidonParams| {
+            let native = Poseidon_permutation(&input, params, sbox_type);
+            let pc_gens = PedersenGens::default();
+            let mut transcript = Transcript::new(b"Poseidon_partial_sbox_count");
+            let mut prover = Prover::new(&pc_gens, &mut transcript);
+            let input_lcs = input.iter().map(|i| LinearCombination::from(*i)).collect();
+            let output = Poseidon_permutation_constraints(&mut prover, input_lcs, params, sbox_type).unwrap();
+            let circuit: Vec<Scalar> = output.iter().map(|o| prover.evaluate_lc(o).unwrap()).collect();
+            assert_eq!(native, circuit);
+            assert_eq!(prover.num_multipliers(), params.num_sboxes() * sbox_type.num_multipliers());
+            native
+        };
+
+        // 1 is the standard partial round
+        let standard = permute(&PoseidonParams::new(width, 4, 4, 6));
+        let params_1 = PoseidonParams::new(width, 4, 4, 6).with_partial_sbox_count(1).unwrap();
+        assert_eq!(params_1.num_sboxes(), 8 * width + 6);
+        assert_eq!(permute(&params_1), standard);
+
+        let params_2 = PoseidonParams::new(width, 4, 4, 6).with_partial_sbox_count(2).unwrap();
+        assert_eq!(params_2.num_sboxes(), 8 * width + 12);
+        assert_ne!(permute(&params_2), standard);
+        assert_eq!(Poseidon_permutation_batch(&[input.clone()], &params_2, sbox_type)[0], permute(&params_2));
+
+        // S-box on all elements makes the partial rounds full rounds with the same round keys
+        let params_width = PoseidonParams::new(width, 4, 4, 6).with_partial_sbox_count(width).unwrap();
+        let mut params_full = PoseidonParams::new(width, 10, 4, 0);
+        params_full.round_keys = params_width.round_keys.clone();
+        assert_eq!(permute(&params_width), permute(&params_full));
+
+        assert_eq!(PoseidonParams::new(width, 4, 4, 6).with_partial_sbox_count(0).unwrap_err(),
+                   PoseidonError::InvalidPartialSboxCount { count: 0, width });
+        assert!(PoseidonParams::new(width, 4, 4, 6).with_partial_sbox_count(width + 1).is_err());
+        // The count is serialized
+        let params_2 = PoseidonParams::from_hex_json(&params_2.to_hex_json()).unwrap();
+        assert_eq!(params_2.partial_sbox_count, 2);
+    }
+
+    #[test]
+    fn test_poseidon_permutation_sparse_native() {
+        let dense_params = get_poseidon_params();
+        let sparse_params = PoseidonParams::new_optimized(6, 4, 4, 140);
+        let mut test_rng: StdRng = SeedableRng::from_seed([26u8; 32]);
+        for sbox_type in &[SboxType::Cube, SboxType::Inverse] {
+            for _ in 0..5 {
+                let input = (0..dense_params.width).map(|_| Scalar::random(&mut test_rng)).collect::<Vec<_>>();
+                let dense_output = Poseidon_permutation(&input, &dense_params, sbox_type);
+                let sparse_output = Poseidon_permutation(&input, &sparse_params, sbox_type);
+                assert_eq!(dense_output.iter().map(|o| o.to_bytes()).collect::<Vec<_>>(),
+                           sparse_output.iter().map(|o| o.to_bytes()).collect::<Vec<_>>());
+            }
+        }
+        #[cfg(not(feature = "generated-constants"))]
+        assert_eq!(Poseidon_hash_2(Scalar::from(1u64), Scalar::from(2u64), &sparse_params, &SboxType::Inverse).unwrap().into_scalar(),
+                   get_scalar_from_hex("0x044897fb7a350fe508ce0c93165c54570775808e853c7c6a382fdbddafe32daa").unwrap());
+    }
+
+    #[test]
+    fn test_poseidon_hash_2_batch() {
+        let s_params = get_poseidon_params();
+        let mut test_rng: StdRng = SeedableRng::from_seed([27u8; 32]);
+        let inputs: Vec<(Scalar, Scalar)> = (0..1000).map(|_| (Scalar::random(&mut test_rng), Scalar::random(&mut test_rng))).collect();
+
+        let sbox_type = SboxType::Inverse;
+        let start = Instant::now();
+        let batched = Poseidon_hash_2_batch(&inputs, &s_params, &sbox_type).unwrap();
+        println!("Batched hashing of {} inputs takes {:?}", inputs.len(), start.elapsed());
+
+        let start = Instant::now();
+        let single: Vec<PoseidonHash> = inputs.iter().map(|(xl, xr)| Poseidon_hash_2(*xl, *xr, &s_params, &sbox_type).unwrap()).collect();
+        println!("Hashing {} inputs one by one takes {:?}", inputs.len(), start.elapsed());
+        assert_eq!(batched, single);
+
+        assert_eq!(Poseidon_hash_2_batch(&inputs[..10], &s_params, &SboxType::Cube).unwrap(),
+                   inputs[..10].iter().map(|(xl, xr)| Poseidon_hash_2(*xl, *xr, &s_params, &SboxType::Cube).unwrap()).collect::<Vec<_>>());
+        assert!(Poseidon_hash_2_batch(&[], &s_params, &sbox_type).unwrap().is_empty());
+
+        // Zeros are not inverted
+        let mut elems = vec![Scalar::from(3u64), Scalar::zero(), Scalar::from(5u64), Scalar::zero()];
+        batch_invert_nonzero(&mut elems);
+        assert_eq!(elems, vec![Scalar::from(3u64).invert(), Scalar::zero(), Scalar::from(5u64).invert(), Scalar::zero()]);
+    }
+
+    #[test]
+    fn test_poseidon_hash_2_mixed_gadget() {
+        let s_params = get_poseidon_params();
+        let sbox_type = &SboxType::Inverse;
+        let (secret, nonce) = (Scalar::from(3u64), Scalar::from(4u64));
+        let expected_output = Poseidon_hash_2(secret, nonce, &s_params, sbox_type).unwrap();
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let label = b"Poseidon_hash_2_mixed";
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+
+        let (proof, comms) = {
+            let mut transcript = Transcript::new(label);
+            let mut prover = Prover::new(&pc_gens, &mut transcript);
+            let (com_s, var_s) = prover.commit(secret, Scalar::random(&mut test_rng));
+            Poseidon_hash_2_mixed_gadget(&mut prover, AllocatedScalar::known(var_s, secret), nonce, &s_params, sbox_type, &expected_output).unwrap();
+            (prover.prove(&bp_gens).unwrap(), vec![com_s])
+        };
+        assert_eq!(comms.len(), poseidon_hash_2_mixed_num_commitments());
+        assert_eq!(poseidon_hash_2_mixed_num_commitments(), 1);
+        assert!(poseidon_hash_2_mixed_num_commitments() < poseidon_hash_2_num_commitments_with_constant_statics());
+
+        let verify = |public_input: Scalar| {
+            let mut transcript = Transcript::new(label);
+            let mut verifier = Verifier::new(&mut transcript);
+            let s_alloc = AllocatedScalar::unknown(verifier.commit(comms[0]));
+            Poseidon_hash_2_mixed_gadget(&mut verifier, s_alloc, public_input, &s_params, sbox_type, &expected_output)?;
+            verifier.verify(&proof, &pc_gens, &bp_gens)
+        };
+        assert!(verify(nonce).is_ok());
+        // Another public input
+        assert!(verify(nonce + Scalar::one()).is_err());
+    }
+
+    #[test]
+    fn test_poseidon_params_with_limits() {
+        assert_eq!(PoseidonParams::with_limits(6, 4, 4, 100000, 200).unwrap_err(),
+                   PoseidonError::TooManyRounds { rounds: 100008, max_rounds: 200 });
+        assert_eq!(PoseidonParams::with_limits(6, usize::max_value(), 4, 140, 200).unwrap_err(),
+                   PoseidonError::TooManyRounds { rounds: usize::max_value(), max_rounds: 200 });
+
+        let s_params = PoseidonParams::with_limits(6, 4, 4, 140, 200).unwrap();
+        assert_eq!(s_params.round_keys, get_poseidon_params().round_keys);
+        assert!(PoseidonParams::with_limits(6, 4, 4, 140, 148).is_ok());
+        assert!(PoseidonParams::with_limits(6, 4, 4, 140, 147).is_err());
+
+        // Within the limit but not enough round constants
+        #[cfg(not(feature = "generated-constants"))]
+        assert_eq!(PoseidonParams::with_limits(6, 4, 4, 160, 1000).unwrap_err(),
+                   PoseidonError::NotEnoughRoundKeys { needed: 168 * 6, found: ROUND_CONSTS.len() });
+    }
+
+    #[test]
+    fn test_bind_to_public_hash_gadget() {
+        let s_params = get_poseidon_params();
+        let sbox_type = &SboxType::Inverse;
+        let public_data = (Scalar::from(10u64), Scalar::from(20u64));
+        let hash = Poseidon_hash_2(public_data.0, public_data.1, &s_params, sbox_type).unwrap().into_scalar();
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(8, 1);
+        let label = b"Bind_to_public_hash";
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+
+        let prove_and_verify = |committed: Scalar, test_rng: &mut StdRng| {
+            let (proof, comm) = {
+                let mut transcript = Transcript::new(label);
+                let mut prover = Prover::new(&pc_gens, &mut transcript);
+                let (com, var) = prover.commit(committed, Scalar::random(test_rng));
+                bind_to_public_hash_gadget(&mut prover, AllocatedScalar::known(var, committed), public_data, &s_params, sbox_type)?;
+                (prover.prove(&bp_gens)?, com)
+            };
+
+            let mut transcript = Transcript::new(label);
+            let mut verifier = Verifier::new(&mut transcript);
+            let alloc = AllocatedScalar::unknown(verifier.commit(comm));
+            bind_to_public_hash_gadget(&mut verifier, alloc, public_data, &s_params, sbox_type)?;
+            verifier.verify(&proof, &pc_gens, &bp_gens)
+        };
+
+        assert!(prove_and_verify(hash, &mut test_rng).is_ok());
+        assert!(prove_and_verify(hash + Scalar::one(), &mut test_rng).is_err());
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_poseidon_permutation_trace_rounds() {
+        use std::sync::{Arc, Mutex};
+        use tracing::{Event, Metadata, Subscriber};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+
+        /// Collects (round, kind, multipliers, constraints) of each event
+        struct RoundCollector(Arc<Mutex<Vec<(u64, String, u64, u64)>>>);
+
+        #[derive(Default)]
+        struct RoundVisitor(u64, String, u64, u64);
+
+        impl Visit for RoundVisitor {
+            fn record_u64(&mut self, field: &Field, value: u64) {
+                match field.name() {
+                    "round" => self.0 = value,
+                    "multipliers" => self.2 = value,
+                    "constraints" => self.3 = value,
+                    _ => ()
+                }
+            }
+            fn record_str(&mut self, field: &Field, value: &str) {
+                if field.name() == "kind" {
+                    self.1 = value.to_string();
+                }
+            }
+            fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+        }
+
+        impl Subscriber for RoundCollector {
+            fn enabled(&self, metadata: &Metadata) -> bool {
+                metadata.target() == "poseidon"
+            }
+            fn new_span(&self, _span: &Attributes) -> Id {
+                Id::from_u64(1)
+            }
+            fn record(&self, _span: &Id, _values: &Record) {}
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+            fn event(&self, event: &Event) {
+                let mut visitor = RoundVisitor::default();
+                event.record(&mut visitor);
+                self.0.lock().unwrap().push((visitor.0, visitor.1, visitor.2, visitor.3));
+            }
+            fn enter(&self, _span: &Id) {}
+            fn exit(&self, _span: &Id) {}
+        }
+
+        let s_params = get_poseidon_params();
+        let width = s_params.width;
+        let total_rounds = s_params.get_total_rounds();
+        let pc_gens = PedersenGens::default();
+
+        let sparse_params = PoseidonParams::new_optimized(6, 4, 4, 140);
+        for params in &[&s_params, &sparse_params] {
+            let events = Arc::new(Mutex::new(vec![]));
+            let counts = tracing::subscriber::with_default(RoundCollector(events.clone()), || {
+                let mut transcript = Transcript::new(b"Poseidon_trace_rounds");
+                let mut prover = Prover::new(&pc_gens, &mut transcript);
+                let input = (0..width).map(|i| LinearCombination::from(prover.commit(Scalar::from(i as u64), Scalar::one()).1)).collect();
+                Poseidon_permutation_constraints(&mut prover, input, params, &SboxType::Cube).unwrap();
+                (prover.num_multipliers() as u64, prover.num_constraints() as u64)
+            });
+
+            let events = events.lock().unwrap();
+            assert_eq!(events.len(), total_rounds);
+            assert_eq!(events.iter().filter(|e| e.1 == "full").count(), 8);
+            assert_eq!(events.iter().filter(|e| e.1 == "partial").count(), 140);
+            for (i, e) in events.iter().enumerate() {
+                assert_eq!(e.0, i as u64);
+            }
+            // Each cube S-box has 2 multipliers and 4 constraints
+            assert_eq!((events[0].2, events[0].3), (2 * width as u64, 4 * width as u64));
+            assert_eq!((events[4].2, events[4].3), (2 * (4 * width as u64 + 1), 4 * (4 * width as u64 + 1)));
+            let last = &events[total_rounds - 1];
+            assert_eq!((last.2, last.3), (2 * (8 * width + 140) as u64, 4 * (8 * width + 140) as u64));
+            // The committed inputs add no constraints so the counts of the last round are those of the prover
+            assert_eq!((last.2, last.3), counts);
+        }
+    }
+
+    #[test]
+    fn test_gen_proof_of_Poseidon_hash_2_deterministic() {
+        let s_params = get_poseidon_params();
+        let sbox_type = &SboxType::Inverse;
+        let (xl, xr) = (Scalar::from(5u64), Scalar::from(6u64));
+        let expected_output = Poseidon_hash_2(xl, xr, &s_params, sbox_type).unwrap();
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let label = b"Poseidon_hash_2_deterministic";
+
+        let (proof, comms) = gen_proof_of_Poseidon_hash_2_deterministic(xl, xr, b"fixture", &s_params, sbox_type, label, &pc_gens, &bp_gens).unwrap();
+        let (_, comms_again) = gen_proof_of_Poseidon_hash_2_deterministic(xl, xr, b"fixture", &s_params, sbox_type, label, &pc_gens, &bp_gens).unwrap();
+        let (_, other_comms) = gen_proof_of_Poseidon_hash_2_deterministic(xl, xr, b"other", &s_params, sbox_type, label, &pc_gens, &bp_gens).unwrap();
+        assert_eq!(comms, comms_again);
+        assert_ne!(comms, other_comms);
+        assert_eq!(comms[0], pc_gens.commit(xl, derive_blinding(b"fixture", 0)).compress());
+        assert!(verify_proof_of_Poseidon_hash_2(&expected_output, &s_params, sbox_type, proof, comms, label, &pc_gens, &bp_gens).is_ok());
+    }
+
+    #[test]
+    fn test_apply_linear_layer_skips_zeros() {
+        let s_params = get_poseidon_params();
+        let width = s_params.width;
+        let sparse = s_params.gen_sparse_partial_rounds();
+        let vars: Vec<Variable> = (0..width).map(Variable::Committed).collect();
+        let mut assignment = HashMap::new();
+        for (i, v) in vars.iter().enumerate() {
+            assignment.insert(*v, Scalar::from(i as u64 + 3));
+        }
+        let values: Vec<Scalar> = vars.iter().map(|v| assignment[v]).collect();
+
+        for matrix in &[s_params.MDS_matrix.clone(), sparse.pre_matrix.clone(), identity_matrix(width)] {
+            let mut next = vec![LinearCombination::default(); width];
+            apply_linear_layer(width, vars.iter().map(|v| LinearCombination::from(*v)).collect(), &mut next, matrix);
+
+            // Same as without skipping terms
+            let expected = mat_vec_mul(matrix, &values);
+            for i in 0..width {
+                assert_eq!(eval_lc(&next[i], &assignment), Some(expected[i]));
+            }
+
+            // Only non-zero entries add terms
+            let num_terms: usize = next.into_iter().map(|lc| lc.get_terms().len()).sum();
+            let num_nonzero = matrix.iter().flatten().filter(|c| **c != Scalar::zero()).count();
+            assert_eq!(num_terms, num_nonzero);
+            if *matrix != s_params.MDS_matrix {
+                assert!(num_terms < width * width);
+            }
+        }
+    }
+
+    #[test]
+    fn test_poseidon_hash_2_with_ad() {
+        let s_params = get_poseidon_params();
+        let sbox_type = &SboxType::Inverse;
+        let (xl, xr) = (Scalar::from(1u64), Scalar::from(2u64));
+        let hash = |ad: &[Scalar]| Poseidon_hash_2_with_ad(xl, xr, ad, &s_params, sbox_type).unwrap();
+
+        assert_eq!(hash(&[]), Poseidon_hash_2(xl, xr, &s_params, sbox_type).unwrap());
+        let ads: Vec<Vec<Scalar>> = vec![vec![], vec![Scalar::zero()], vec![Scalar::one()], vec![Scalar::zero(), Scalar::zero()],
+                                         vec![Scalar::one(), Scalar::zero()], vec![Scalar::zero(), Scalar::one()]];
+        let hashes: Vec<PoseidonHash> = ads.iter().map(|ad| hash(ad)).collect();
+        for i in 0..hashes.len() {
+            for j in i+1..hashes.len() {
+                assert_ne!(hashes[i], hashes[j]);
+            }
+        }
+        assert_eq!(Poseidon_hash_2_with_ad(xl, xr, &[Scalar::one(); 3], &s_params, sbox_type),
+                   Err(PoseidonError::TooManyInputs { width: 6, max_inputs: 2, found: 3 }));
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let label = b"Poseidon_hash_2_with_ad";
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let ad = [Scalar::from(7u64)];
+        let expected_output = hash(&ad);
+
+        let (proof, comms) = {
+            let mut transcript = Transcript::new(label);
+            let mut prover = Prover::new(&pc_gens, &mut transcript);
+            let (com_l, var_l) = prover.commit(xl, Scalar::random(&mut test_rng));
+            let (com_r, var_r) = prover.commit(xr, Scalar::random(&mut test_rng));
+            Poseidon_hash_2_with_ad_gadget(&mut prover, AllocatedScalar::known(var_l, xl), AllocatedScalar::known(var_r, xr),
+                                           &ad, &s_params, sbox_type, &expected_output).unwrap();
+            (prover.prove(&bp_gens).unwrap(), vec![com_l, com_r])
+        };
+
+        let verify = |ad: &[Scalar]| {
+            let mut transcript = Transcript::new(label);
+            let mut verifier = Verifier::new(&mut transcript);
+            let l_alloc = AllocatedScalar::unknown(verifier.commit(comms[0]));
+            let r_alloc = AllocatedScalar::unknown(verifier.commit(comms[1]));
+            Poseidon_hash_2_with_ad_gadget(&mut verifier, l_alloc, r_alloc, ad, &s_params, sbox_type, &expected_output)?;
+            verifier.verify(&proof, &pc_gens, &bp_gens)
+        };
+        assert!(verify(&ad).is_ok());
+        assert!(verify(&[Scalar::from(8u64)]).is_err());
+        assert!(verify(&[]).is_err());
+    }
+
+    #[test]
+    fn test_estimate_prover_memory() {
+        let s_params = get_poseidon_params();
+        for sbox_type in &[SboxType::Cube, SboxType::Inverse, SboxType::InverseUnchecked] {
+            assert!(estimate_prover_memory(&s_params, sbox_type, 1) > 0);
+            let estimates: Vec<usize> = (1..20).map(|n| estimate_prover_memory(&s_params, sbox_type, n)).collect();
+            for i in 1..estimates.len() {
+                assert!(estimates[i] > estimates[i - 1]);
+            }
+
+            // S-box costs match the constraint system
+            let pc_gens = PedersenGens::default();
+            let mut transcript = Transcript::new(b"Poseidon_estimate_memory");
+            let mut prover = Prover::new(&pc_gens, &mut transcript);
+            let (xl, xr) = (Scalar::from(1u64), Scalar::from(2u64));
+            let output = Poseidon_hash_2(xl, xr, &s_params, sbox_type).unwrap();
+            let l = AllocatedScalar::known(prover.commit(xl, Scalar::one()).1, xl);
+            let r = AllocatedScalar::known(prover.commit(xr, Scalar::one()).1, xr);
+            Poseidon_hash_2_gadget_ex(&mut prover, l, r, &s_params, sbox_type, &output, ZeroMode::Constant).unwrap();
+            let num_sboxes = 8 * s_params.width + s_params.partial_rounds;
+            assert_eq!(prover.num_multipliers(), num_sboxes * sbox_type.num_multipliers());
+            assert_eq!(prover.num_constraints(), num_sboxes * sbox_type.num_linear_constraints() + 1);
+        }
+        assert!(estimate_prover_memory(&s_params, &SboxType::Inverse, 10) > estimate_prover_memory(&s_params, &SboxType::Cube, 10));
+    }
+
+    #[test]
+    fn test_poseidon_params_constants_source() {
+        let s_params = PoseidonParams::new(6, 4, 4, 140);
+        #[cfg(not(feature = "generated-constants"))]
+        assert_eq!(get_hex_from_scalar(&s_params.round_keys[0]), ROUND_CONSTS[0]);
+        #[cfg(feature = "generated-constants")]
+        assert_eq!(s_params.round_keys, GrainLfsr::new(SCALAR_FIELD_BITS, 6, 8, 140).round_constants(148 * 6));
+        assert_eq!(s_params.MDS_matrix, PoseidonParams::gen_cauchy_matrix(6));
+
+        // A hash verifies with whichever constants are used
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let label = b"Poseidon_hash_2_constants";
+        let (xl, xr) = (Scalar::from(1u64), Scalar::from(2u64));
+        for sbox_type in &[SboxType::Cube, SboxType::Inverse] {
+            let expected_output = Poseidon_hash_2(xl, xr, &s_params, sbox_type).unwrap();
+            let (proof, comms) = gen_proof_of_Poseidon_hash_2(xl, xr, &s_params, sbox_type, &mut test_rng, label, &pc_gens, &bp_gens).unwrap();
+            assert!(verify_proof_of_Poseidon_hash_2(&expected_output, &s_params, sbox_type, proof, comms, label, &pc_gens, &bp_gens).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_poseidon_hash_2_to_2() {
+        let s_params = get_poseidon_params();
+        let sbox_type = &SboxType::Inverse;
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let xl = Scalar::random(&mut test_rng);
+        let xr = Scalar::random(&mut test_rng);
+
+        let expected_output = Poseidon_hash_2_to_2(xl, xr, &s_params, sbox_type);
+        let input = vec![Scalar::from(ZERO_CONST), xl, xr, Scalar::from(PADDING_CONST), Scalar::from(ZERO_CONST), Scalar::from(ZERO_CONST)];
+        let permutation_output = Poseidon_permutation(&input, &s_params, sbox_type);
+        assert_eq!(expected_output, (permutation_output[1], permutation_output[2]));
+        assert_eq!(expected_output.0, Poseidon_hash_2(xl, xr, &s_params, sbox_type).unwrap().into_scalar());
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let label = b"Poseidon_hash_2_to_2";
+        let num_statics = s_params.width - 2;
+
+        let (proof, comms) = {
+            let mut transcript = Transcript::new(label);
+            let mut prover = Prover::new(&pc_gens, &mut transcript);
+            let (com_l, var_l) = prover.commit(xl, Scalar::random(&mut test_rng));
+            let (com_r, var_r) = prover.commit(xr, Scalar::random(&mut test_rng));
+            let statics = allocate_statics_for_prover(&mut prover, num_statics);
+            Poseidon_hash_2_to_2_gadget(&mut prover, AllocatedScalar::known(var_l, xl), AllocatedScalar::known(var_r, xr),
+                                        statics, &s_params, sbox_type, &expected_output).unwrap();
+            (prover.prove(&bp_gens).unwrap(), vec![com_l, com_r])
+        };
+
+        let verify = |output: (Scalar, Scalar)| {
+            let mut transcript = Transcript::new(label);
+            let mut verifier = Verifier::new(&mut transcript);
+            let l_alloc = AllocatedScalar::unknown(verifier.commit(comms[0]));
+            let r_alloc = AllocatedScalar::unknown(verifier.commit(comms[1]));
+            let statics = allocate_statics_for_verifier(&mut verifier, num_statics, &pc_gens);
+            Poseidon_hash_2_to_2_gadget(&mut verifier, l_alloc, r_alloc, statics, &s_params, sbox_type, &output)?;
+            verifier.verify(&proof, &pc_gens, &bp_gens)
+        };
+        assert!(verify(expected_output).is_ok());
+        // Either output wrong
+        assert!(verify((expected_output.0, expected_output.1 + Scalar::one())).is_err());
+        assert!(verify((expected_output.0 + Scalar::one(), expected_output.1)).is_err());
+    }
+
+    #[test]
+    fn test_compression_2to2_gadget() {
+        let s_params = get_poseidon_params();
+        let sbox_type = &SboxType::Inverse;
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let (xl, xr) = (Scalar::random(&mut test_rng), Scalar::random(&mut test_rng));
+        let (out0, out1) = Poseidon_hash_2_to_2(xl, xr, &s_params, sbox_type);
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let label = b"Compression_2to2";
+
+        let prove_and_verify = |outputs: (Scalar, Scalar)| -> Result<(), R1CSError> {
+            let mut rng = rand::thread_rng();
+            let values = [xl, xr, outputs.0, outputs.1];
+            let (proof, comms) = {
+                let mut transcript = Transcript::new(label);
+                let mut prover = Prover::new(&pc_gens, &mut transcript);
+                let mut comms = vec![];
+                let mut allocs = vec![];
+                for v in values.iter() {
+                    let (com, var) = prover.commit(*v, Scalar::random(&mut rng));
+                    comms.push(com);
+                    allocs.push(AllocatedScalar::known(var, *v));
+                }
+                compression_2to2_gadget(&mut prover, allocs[0], allocs[1], allocs[2], allocs[3], &s_params, sbox_type)?;
+                (prover.prove(&bp_gens)?, comms)
+            };
+
+            let mut transcript = Transcript::new(label);
+            let mut verifier = Verifier::new(&mut transcript);
+            let allocs: Vec<AllocatedScalar> = comms.iter().map(|c| AllocatedScalar::unknown(verifier.commit(*c))).collect();
+            compression_2to2_gadget(&mut verifier, allocs[0], allocs[1], allocs[2], allocs[3], &s_params, sbox_type)?;
+            verifier.verify(&proof, &pc_gens, &bp_gens)
+        };
+        assert!(prove_and_verify((out0, out1)).is_ok());
+        // Either output wrong
+        assert!(prove_and_verify((out0, out1 + Scalar::one())).is_err());
+        assert!(prove_and_verify((out0 + Scalar::one(), out1)).is_err());
+    }
+
+    #[test]
+    fn test_verify_poseidon_stream() {
+        let s_params = get_poseidon_params();
+        let sbox_type = &SboxType::Inverse;
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let label = b"Poseidon_hash_2_stream";
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+
+        let inputs: Vec<(Scalar, Scalar)> = (0..3).map(|_| (Scalar::random(&mut test_rng), Scalar::random(&mut test_rng))).collect();
+        let outputs: Vec<Scalar> = Poseidon_hash_2_batch(&inputs, &s_params, sbox_type).unwrap().into_iter().map(|h| h.into_scalar()).collect();
+        let (proof, comms) = gen_proof_of_Poseidon_hash_2_batch(&inputs, &s_params, sbox_type, &mut test_rng, label, &pc_gens, &bp_gens).unwrap();
+        assert_eq!(comms.len(), 2 * inputs.len());
+        assert!(verify_poseidon_stream(proof.clone(), comms.clone().into_iter(), &outputs, &s_params, sbox_type, label, &pc_gens, &bp_gens).is_ok());
+
+        // Wrong output, missing, extra or reordered commitments
+        let mut wrong_outputs = outputs.clone();
+        wrong_outputs[2] += Scalar::one();
+        assert!(verify_poseidon_stream(proof.clone(), comms.clone().into_iter(), &wrong_outputs, &s_params, sbox_type, label, &pc_gens, &bp_gens).is_err());
+        assert!(verify_poseidon_stream(proof.clone(), comms[..5].to_vec().into_iter(), &outputs, &s_params, sbox_type, label, &pc_gens, &bp_gens).is_err());
+        assert!(verify_poseidon_stream(proof.clone(), comms.iter().chain(&comms[..1]).cloned(), &outputs, &s_params, sbox_type, label, &pc_gens, &bp_gens).is_err());
+        assert!(verify_poseidon_stream(proof, comms.iter().rev().cloned(), &outputs, &s_params, sbox_type, label, &pc_gens, &bp_gens).is_err());
+
+        // A single hash gives the same result as the non-streaming verifier
+        let (xl, xr) = inputs[0];
+        let (proof, comms) = gen_proof_of_Poseidon_hash_2(xl, xr, &s_params, sbox_type, &mut test_rng, label, &pc_gens, &bp_gens).unwrap();
+        for expected_output in &[outputs[0], outputs[1]] {
+            assert_eq!(verify_poseidon_stream(proof.clone(), comms.clone().into_iter(), &[*expected_output], &s_params, sbox_type, label, &pc_gens, &bp_gens).is_ok(),
+                       verify_proof_of_Poseidon_hash_2(expected_output, &s_params, sbox_type, proof.clone(), comms.clone(), label, &pc_gens, &bp_gens).is_ok());
+        }
+        assert!(verify_poseidon_stream(proof, comms.into_iter(), &outputs[..1], &s_params, sbox_type, label, &pc_gens, &bp_gens).is_ok());
+    }
+
+    #[test]
+    fn test_addressed_commitment_gadget() {
+        let s_params = get_poseidon_params();
+        let sbox_type = &SboxType::Inverse;
+        let (index, secret) = (Scalar::from(5u64), Scalar::from(98765u64));
+        let value = Poseidon_hash_2(index, secret, &s_params, sbox_type).unwrap().into_scalar();
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let label = b"AddressedCommitment";
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+
+        let prove_and_verify = |index: Scalar, value: Scalar, verifier_index: Scalar| -> Result<(), R1CSError> {
+            let mut rng = rand::thread_rng();
+            let (proof, comms) = {
+                let mut transcript = Transcript::new(label);
+                let mut prover = Prover::new(&pc_gens, &mut transcript);
+                let (com_s, var_s) = prover.commit(secret, Scalar::random(&mut rng));
+                let (com_v, var_v) = prover.commit(value, Scalar::random(&mut rng));
+                addressed_commitment_gadget(&mut prover, index, AllocatedScalar::known(var_s, secret), AllocatedScalar::known(var_v, value), &s_params, sbox_type)?;
+                (prover.prove(&bp_gens)?, vec![com_s, com_v])
+            };
+
+            let mut transcript = Transcript::new(label);
+            let mut verifier = Verifier::new(&mut transcript);
+            let s_alloc = AllocatedScalar::unknown(verifier.commit(comms[0]));
+            let v_alloc = AllocatedScalar::unknown(verifier.commit(comms[1]));
+            addressed_commitment_gadget(&mut verifier, verifier_index, s_alloc, v_alloc, &s_params, sbox_type)?;
+            verifier.verify(&proof, &pc_gens, &bp_gens)
+        };
+        assert!(prove_and_verify(index, value, index).is_ok());
+        // Value is not the hash
+        assert!(prove_and_verify(index, Scalar::random(&mut test_rng), index).is_err());
+        // Value is for another index
+        assert!(prove_and_verify(index, value, index + Scalar::one()).is_err());
+        // Index and secret swapped
+        let swapped = Poseidon_hash_2(secret, index, &s_params, sbox_type).unwrap().into_scalar();
+        assert!(prove_and_verify(index, swapped, index).is_err());
+    }
+}
+
+#[cfg(test)]
+mod proptests {
+    use super::*;
+    use proptest::prelude::{any, proptest, prop_assert_eq, ProptestConfig};
+
+    /// Value of the hash as constrained by `Poseidon_hash_2_constraints`, evaluated on the prover side
+    fn circuit_Poseidon_hash_2(xl: Scalar, xr: Scalar, params: &PoseidonParams, sbox_type: &SboxType) -> Option<Scalar> {
+        let pc_gens = PedersenGens::default();
+        let mut prover_transcript = Transcript::new(b"Poseidon_hash_2_proptest");
+        let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+        let (_, var_l) = prover.commit(xl, Scalar::one());
+        let (_, var_r) = prover.commit(xr, Scalar::one());
+        let statics = allocate_statics_for_prover(&mut prover, params.width - 2);
+        let statics = statics.iter().map(|s| s.variable.into()).collect();
+        let hash = Poseidon_hash_2_constraints(&mut prover, var_l.into(), var_r.into(), statics, params, sbox_type).ok()?;
+        prover.evaluate_lc(&hash)
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn test_poseidon_hash_2_native_matches_circuit(l in any::<[u8; 32]>(), r in any::<[u8; 32]>()) {
+            let params = PoseidonParams::new(6, 4, 4, 140);
+            let (xl, xr) = (Scalar::from_bytes_mod_order(l), Scalar::from_bytes_mod_order(r));
+            for (name, sbox_type) in &[("Cube", SboxType::Cube), ("Inverse", SboxType::Inverse)] {
+                let native = Poseidon_hash_2(xl, xr, &params, sbox_type).unwrap().into_scalar();
+                prop_assert_eq!(Some(native), circuit_Poseidon_hash_2(xl, xr, &params, sbox_type),
+                                "{} sbox diverged for xl={:?}, xr={:?}", name, xl, xr);
+            }
+        }
+    }
+}
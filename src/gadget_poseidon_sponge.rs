@@ -0,0 +1,446 @@
+#![allow(non_snake_case)]
+
+extern crate bulletproofs;
+extern crate curve25519_dalek;
+
+use bulletproofs::r1cs::{ConstraintSystem, R1CSError};
+use bulletproofs::r1cs::LinearCombination;
+use curve25519_dalek::scalar::Scalar;
+
+use crate::gadget_poseidon::{PoseidonParams, PoseidonError, PoseidonHash, SboxType, Poseidon_permutation,
+                             Poseidon_permutation_constraints, PADDING_CONST};
+
+/// What is added to the sponge to mark the end of the inputs before squeezing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Padding {
+    /// Add `PADDING_CONST` after the inputs. This is the default and what `PoseidonSponge::squeeze` does.
+    PaddingConst,
+    /// Add nothing, so inputs followed by 0s are not distinguished from the inputs alone
+    ZeroPad,
+    /// Add 1 after the inputs, the 10* padding
+    OneZeroPad,
+    /// Absorb the number of inputs before the inputs and add nothing after
+    LengthPrefix
+}
+
+/// `PaddingConst` is what the sponge always added so the default keeps the existing hashes. `ZeroPad` would change them.
+impl Default for Padding {
+    fn default() -> Self {
+        Padding::PaddingConst
+    }
+}
+
+impl Padding {
+    /// Added to the rate after the inputs
+    fn end_marker(&self) -> Option<Scalar> {
+        match self {
+            Padding::PaddingConst => Some(Scalar::from(PADDING_CONST)),
+            Padding::OneZeroPad => Some(Scalar::one()),
+            Padding::ZeroPad | Padding::LengthPrefix => None
+        }
+    }
+}
+
+/// Sponge over the Poseidon permutation to hash any number of inputs. The first element of the state is the
+/// capacity and the rest is the rate, so `width - 1` inputs are absorbed per permutation.
+pub struct PoseidonSponge<'a> {
+    state: Vec<Scalar>,
+    /// Index in the rate where the next input is added
+    position: usize,
+    params: &'a PoseidonParams,
+    sbox: &'a SboxType
+}
+
+impl<'a> PoseidonSponge<'a> {
+    pub fn new(params: &'a PoseidonParams, sbox: &'a SboxType) -> PoseidonSponge<'a> {
+        PoseidonSponge {
+            state: vec![Scalar::zero(); params.width],
+            position: 0,
+            params,
+            sbox
+        }
+    }
+
+    /// Resume from a state and position returned by `state` and `position`
+    pub fn from_state(state: Vec<Scalar>, position: usize, params: &'a PoseidonParams, sbox: &'a SboxType) -> Result<PoseidonSponge<'a>, PoseidonError> {
+        if state.len() != params.width {
+            return Err(PoseidonError::InvalidSpongeState(format!("State has {} elements but width is {}", state.len(), params.width)))
+        }
+        if position >= params.width - 1 {
+            return Err(PoseidonError::InvalidSpongeState(format!("Position {} should be less than the rate {}", position, params.width - 1)))
+        }
+        Ok(PoseidonSponge {
+            state,
+            position,
+            params,
+            sbox
+        })
+    }
+
+    pub fn state(&self) -> &[Scalar] {
+        &self.state
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn absorb(&mut self, inputs: &[Scalar]) {
+        self.absorb_iter(inputs.iter().cloned())
+    }
+
+    /// Absorb inputs as they are yielded, permuting whenever the rate is full
+    pub fn absorb_iter<I: IntoIterator<Item=Scalar>>(&mut self, inputs: I) {
+        for i in inputs {
+            self.state[1 + self.position] += i;
+            self.position += 1;
+            if self.position == self.params.width - 1 {
+                self.permute();
+            }
+        }
+    }
+
+    /// Add the padding constant after the absorbed inputs, permute and return the first element of the rate.
+    /// The sponge can be used to absorb more inputs after this.
+    pub fn squeeze(&mut self) -> PoseidonHash {
+        self.squeeze_with_padding(&Padding::default())
+    }
+
+    /// Same as `squeeze` with the given padding. For `Padding::LengthPrefix`, the caller absorbs the length first.
+    pub fn squeeze_with_padding(&mut self, padding: &Padding) -> PoseidonHash {
+        PoseidonHash(self.squeeze_n_with_padding(1, padding)[0])
+    }
+
+    /// Same as `squeeze` but returns `n` elements. After padding and permuting, the rate is read in order and
+    /// the state is permuted again whenever the rate is exhausted. The first element is what `squeeze` returns.
+    pub fn squeeze_n(&mut self, n: usize) -> Vec<Scalar> {
+        self.squeeze_n_with_padding(n, &Padding::default())
+    }
+
+    /// Same as `squeeze_n` with the given padding, the first element is what `squeeze_with_padding` returns
+    pub fn squeeze_n_with_padding(&mut self, n: usize, padding: &Padding) -> Vec<Scalar> {
+        let rate = self.params.width - 1;
+        if let Some(marker) = padding.end_marker() {
+            self.state[1 + self.position] += marker;
+        }
+        self.permute();
+        let mut outputs = vec![];
+        for i in 0..n {
+            if i > 0 && i % rate == 0 {
+                self.permute();
+            }
+            outputs.push(self.state[1 + i % rate]);
+        }
+        outputs
+    }
+
+    fn permute(&mut self) {
+        self.state = Poseidon_permutation(&self.state, self.params, self.sbox);
+        self.position = 0;
+    }
+}
+
+/// Squeeze `n` elements from `sponge`, see `PoseidonSponge::squeeze_n`
+pub fn Poseidon_sponge_squeeze_n(sponge: &mut PoseidonSponge, n: usize) -> Vec<Scalar> {
+    sponge.squeeze_n(n)
+}
+
+/// Constraints for `PoseidonSponge`, absorbing linear combinations and squeezing the same elements as the native sponge
+pub struct PoseidonSpongeGadget<'a> {
+    state: Vec<LinearCombination>,
+    position: usize,
+    params: &'a PoseidonParams,
+    sbox: &'a SboxType
+}
+
+impl<'a> PoseidonSpongeGadget<'a> {
+    pub fn new(params: &'a PoseidonParams, sbox: &'a SboxType) -> PoseidonSpongeGadget<'a> {
+        PoseidonSpongeGadget {
+            state: vec![LinearCombination::default(); params.width],
+            position: 0,
+            params,
+            sbox
+        }
+    }
+
+    pub fn absorb<CS: ConstraintSystem>(&mut self, cs: &mut CS, inputs: Vec<LinearCombination>) -> Result<(), R1CSError> {
+        for i in inputs {
+            self.state[1 + self.position] = self.state[1 + self.position].clone() + i;
+            self.position += 1;
+            if self.position == self.params.width - 1 {
+                self.permute(cs)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as `PoseidonSponge::squeeze_n`
+    pub fn squeeze_n<CS: ConstraintSystem>(&mut self, cs: &mut CS, n: usize) -> Result<Vec<LinearCombination>, R1CSError> {
+        self.squeeze_n_with_padding(cs, n, &Padding::default())
+    }
+
+    /// Same as `PoseidonSponge::squeeze_n_with_padding`
+    pub fn squeeze_n_with_padding<CS: ConstraintSystem>(&mut self, cs: &mut CS, n: usize, padding: &Padding) -> Result<Vec<LinearCombination>, R1CSError> {
+        let rate = self.params.width - 1;
+        if let Some(marker) = padding.end_marker() {
+            self.state[1 + self.position] = self.state[1 + self.position].clone() + marker;
+        }
+        self.permute(cs)?;
+        let mut outputs = vec![];
+        for i in 0..n {
+            if i > 0 && i % rate == 0 {
+                self.permute(cs)?;
+            }
+            outputs.push(self.state[1 + i % rate].clone());
+        }
+        Ok(outputs)
+    }
+
+    /// Same as `PoseidonSponge::squeeze_with_padding`
+    pub fn squeeze_with_padding<CS: ConstraintSystem>(&mut self, cs: &mut CS, padding: &Padding) -> Result<LinearCombination, R1CSError> {
+        Ok(self.squeeze_n_with_padding(cs, 1, padding)?.remove(0))
+    }
+
+    fn permute<CS: ConstraintSystem>(&mut self, cs: &mut CS) -> Result<(), R1CSError> {
+        let state = std::mem::replace(&mut self.state, vec![]);
+        self.state = Poseidon_permutation_constraints(cs, state, self.params, self.sbox)?;
+        self.position = 0;
+        Ok(())
+    }
+}
+
+/// Hash the inputs of an iterator with the sponge without collecting them first
+pub fn poseidon_hash_iter<I: IntoIterator<Item=Scalar>>(inputs: I, params: &PoseidonParams, sbox: &SboxType) -> Scalar {
+    let mut sponge = PoseidonSponge::new(params, sbox);
+    sponge.absorb_iter(inputs);
+    sponge.squeeze().into_scalar()
+}
+
+/// Hash `inputs` with the sponge and the given padding. `Padding::default()` gives the same hash as `poseidon_hash_iter`.
+pub fn Poseidon_sponge_hash(inputs: &[Scalar], params: &PoseidonParams, sbox: &SboxType, padding: Padding) -> Scalar {
+    let mut sponge = PoseidonSponge::new(params, sbox);
+    if padding == Padding::LengthPrefix {
+        sponge.absorb(&[Scalar::from(inputs.len() as u64)]);
+    }
+    sponge.absorb(inputs);
+    sponge.squeeze_with_padding(&padding).into_scalar()
+}
+
+/// Constraints for `Poseidon_sponge_hash`, returning the hash
+pub fn Poseidon_sponge_hash_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    inputs: Vec<LinearCombination>,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+    padding: Padding
+) -> Result<LinearCombination, R1CSError> {
+    let mut sponge = PoseidonSpongeGadget::new(params, sbox);
+    if padding == Padding::LengthPrefix {
+        sponge.absorb(cs, vec![Scalar::from(inputs.len() as u64).into()])?;
+    }
+    sponge.absorb(cs, inputs)?;
+    sponge.squeeze_with_padding(cs, &padding)
+}
+
+// Absorbed before the inputs of a challenge so that challenges differ from `poseidon_hash_iter` of the same inputs.
+// Choice is arbitrary
+pub const CHALLENGE_DOMAIN_TAG: u64 = 0x6368616c6c;
+
+/// Fiat-Shamir challenge from the public values `inputs` of a protocol. The sponge absorbs `CHALLENGE_DOMAIN_TAG`
+/// and then `inputs` and the challenge is the squeezed element.
+pub fn poseidon_challenge(inputs: &[Scalar], params: &PoseidonParams, sbox: &SboxType) -> Scalar {
+    let mut sponge = PoseidonSponge::new(params, sbox);
+    sponge.absorb(&[Scalar::from(CHALLENGE_DOMAIN_TAG)]);
+    sponge.absorb(inputs);
+    sponge.squeeze().into_scalar()
+}
+
+/// Constraints for `poseidon_challenge`, returning the challenge. `inputs` can be committed variables or public
+/// constants, the challenge is the same as `poseidon_challenge` of their values.
+pub fn poseidon_challenge_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    inputs: Vec<LinearCombination>,
+    params: &PoseidonParams,
+    sbox: &SboxType
+) -> Result<LinearCombination, R1CSError> {
+    let mut sponge = PoseidonSpongeGadget::new(params, sbox);
+    sponge.absorb(cs, vec![Scalar::from(CHALLENGE_DOMAIN_TAG).into()])?;
+    sponge.absorb(cs, inputs)?;
+    Ok(sponge.squeeze_n(cs, 1)?.remove(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use bulletproofs::r1cs::{Prover, Verifier};
+    use bulletproofs::{BulletproofGens, PedersenGens};
+    use merlin::Transcript;
+    use crate::r1cs_utils::constrain_lc_with_scalar;
+
+    #[test]
+    fn test_sponge_resume_from_state() {
+        let p_params = PoseidonParams::new(6, 4, 4, 140);
+        let sbox = SboxType::Cube;
+        let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
+        let inputs = (0..13).map(|_| Scalar::random(&mut test_rng)).collect::<Vec<_>>();
+
+        let mut sponge = PoseidonSponge::new(&p_params, &sbox);
+        sponge.absorb(&inputs);
+        let expected = sponge.squeeze();
+
+        let mut sponge = PoseidonSponge::new(&p_params, &sbox);
+        sponge.absorb(&inputs[..7]);
+        let (state, position) = (sponge.state().to_vec(), sponge.position());
+        assert_eq!(position, 2);
+
+        let mut resumed = PoseidonSponge::from_state(state.clone(), position, &p_params, &sbox).unwrap();
+        resumed.absorb(&inputs[7..]);
+        assert_eq!(resumed.squeeze(), expected);
+
+        // Continuing the original sponge gives the same as well
+        sponge.absorb(&inputs[7..]);
+        assert_eq!(sponge.squeeze(), expected);
+
+        assert!(PoseidonSponge::from_state(state[..5].to_vec(), position, &p_params, &sbox).is_err());
+        assert!(PoseidonSponge::from_state(state, 5, &p_params, &sbox).is_err());
+    }
+
+    #[test]
+    fn test_poseidon_hash_iter() {
+        let p_params = PoseidonParams::new(6, 4, 4, 140);
+        let sbox = SboxType::Inverse;
+        // 7 is not a multiple of the rate 5
+        let inputs = (1..8u64).map(Scalar::from).collect::<Vec<_>>();
+
+        let mut sponge = PoseidonSponge::new(&p_params, &sbox);
+        sponge.absorb(&inputs);
+        let expected = sponge.squeeze().into_scalar();
+
+        assert_eq!(poseidon_hash_iter((1..8u64).map(Scalar::from), &p_params, &sbox), expected);
+        assert_ne!(poseidon_hash_iter((1..7u64).map(Scalar::from), &p_params, &sbox), expected);
+        // Multiple of the rate
+        let mut sponge = PoseidonSponge::new(&p_params, &sbox);
+        sponge.absorb(&inputs[..5]);
+        assert_eq!(poseidon_hash_iter(inputs[..5].iter().cloned(), &p_params, &sbox), sponge.squeeze().into_scalar());
+    }
+
+    #[test]
+    fn test_sponge_squeeze_n() {
+        let p_params = PoseidonParams::new(6, 4, 4, 140);
+        let sbox = SboxType::Inverse;
+        let inputs = (1..8u64).map(Scalar::from).collect::<Vec<_>>();
+
+        let mut sponge = PoseidonSponge::new(&p_params, &sbox);
+        sponge.absorb(&inputs);
+        let expected = sponge.squeeze().into_scalar();
+
+        let squeeze = |n: usize| {
+            let mut sponge = PoseidonSponge::new(&p_params, &sbox);
+            sponge.absorb(&inputs);
+            Poseidon_sponge_squeeze_n(&mut sponge, n)
+        };
+        let two = squeeze(2);
+        assert_eq!(two[0], expected);
+        assert_ne!(two[0], two[1]);
+        // More than the rate 5 needs another permutation
+        let seven = squeeze(7);
+        assert_eq!(seven[..2], two[..]);
+        assert!(squeeze(0).is_empty());
+
+        let pc_gens = PedersenGens::default();
+        let mut transcript = Transcript::new(b"PoseidonSpongeSqueezeN");
+        let mut prover = Prover::new(&pc_gens, &mut transcript);
+        let circuit_squeeze = |prover: &mut Prover, n: usize| {
+            let vars = inputs.iter().map(|i| LinearCombination::from(prover.commit(*i, Scalar::one()).1)).collect();
+            let mut sponge = PoseidonSpongeGadget::new(&p_params, &sbox);
+            sponge.absorb(prover, vars).unwrap();
+            let outputs = sponge.squeeze_n(prover, n).unwrap();
+            outputs.iter().map(|o| prover.evaluate_lc(o).unwrap()).collect::<Vec<_>>()
+        };
+        assert_eq!(circuit_squeeze(&mut prover, 2), two);
+        assert_eq!(circuit_squeeze(&mut prover, 7), seven);
+    }
+
+    #[test]
+    fn test_poseidon_challenge() {
+        let p_params = PoseidonParams::new(6, 4, 4, 140);
+        let sbox = SboxType::Inverse;
+        let inputs = (1..9u64).map(|i| Scalar::from(i * 1000)).collect::<Vec<_>>();
+
+        let challenge = poseidon_challenge(&inputs, &p_params, &sbox);
+        assert_eq!(challenge, poseidon_challenge(&inputs, &p_params, &sbox));
+        assert_ne!(challenge, poseidon_challenge(&inputs[..7], &p_params, &sbox));
+        assert_ne!(challenge, poseidon_hash_iter(inputs.iter().cloned(), &p_params, &sbox));
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        // First half of the inputs is committed and the rest is public
+        let (proof, comms) = {
+            let mut transcript = Transcript::new(b"PoseidonChallenge");
+            let mut prover = Prover::new(&pc_gens, &mut transcript);
+            let (comms, vars): (Vec<_>, Vec<_>) = inputs[..4].iter().map(|i| prover.commit(*i, Scalar::from(7u64))).unzip();
+            let lcs = vars.into_iter().map(LinearCombination::from).chain(inputs[4..].iter().map(|i| LinearCombination::from(*i))).collect();
+            let circuit_challenge = poseidon_challenge_gadget(&mut prover, lcs, &p_params, &sbox).unwrap();
+            assert_eq!(prover.evaluate_lc(&circuit_challenge), Some(challenge));
+            constrain_lc_with_scalar(&mut prover, circuit_challenge, &challenge);
+            (prover.prove(&bp_gens).unwrap(), comms)
+        };
+
+        let mut transcript = Transcript::new(b"PoseidonChallenge");
+        let mut verifier = Verifier::new(&mut transcript);
+        let vars: Vec<_> = comms.iter().map(|c| verifier.commit(*c)).collect();
+        let lcs = vars.into_iter().map(LinearCombination::from).chain(inputs[4..].iter().map(|i| LinearCombination::from(*i))).collect();
+        let circuit_challenge = poseidon_challenge_gadget(&mut verifier, lcs, &p_params, &sbox).unwrap();
+        constrain_lc_with_scalar(&mut verifier, circuit_challenge, &challenge);
+        assert!(verifier.verify(&proof, &pc_gens, &bp_gens).is_ok());
+    }
+
+    #[test]
+    fn test_sponge_hash_padding() {
+        let p_params = PoseidonParams::new(6, 4, 4, 140);
+        let sbox = SboxType::Inverse;
+        let inputs = (1..4u64).map(Scalar::from).collect::<Vec<_>>();
+        let modes = [Padding::PaddingConst, Padding::ZeroPad, Padding::OneZeroPad, Padding::LengthPrefix];
+
+        let hashes = modes.iter().map(|m| Poseidon_sponge_hash(&inputs, &p_params, &sbox, *m)).collect::<Vec<_>>();
+        for i in 0..hashes.len() {
+            for j in i+1..hashes.len() {
+                assert_ne!(hashes[i], hashes[j], "{:?} and {:?} give the same hash", modes[i], modes[j]);
+            }
+        }
+        assert_eq!(Padding::default(), Padding::PaddingConst);
+        assert_eq!(hashes[0], poseidon_hash_iter(inputs.iter().cloned(), &p_params, &sbox));
+        // Only zero padding cannot tell trailing zeroes apart
+        let mut padded = inputs.clone();
+        padded.push(Scalar::zero());
+        assert_eq!(Poseidon_sponge_hash(&padded, &p_params, &sbox, Padding::ZeroPad), hashes[1]);
+        for m in &[Padding::PaddingConst, Padding::OneZeroPad, Padding::LengthPrefix] {
+            assert_ne!(Poseidon_sponge_hash(&padded, &p_params, &sbox, *m), Poseidon_sponge_hash(&inputs, &p_params, &sbox, *m));
+        }
+
+        let pc_gens = PedersenGens::default();
+        let mut transcript = Transcript::new(b"PoseidonSpongePadding");
+        let mut prover = Prover::new(&pc_gens, &mut transcript);
+        for (m, hash) in modes.iter().zip(hashes) {
+            let vars = inputs.iter().map(|i| LinearCombination::from(prover.commit(*i, Scalar::one()).1)).collect();
+            let circuit_hash = Poseidon_sponge_hash_gadget(&mut prover, vars, &p_params, &sbox, *m).unwrap();
+            assert_eq!(prover.evaluate_lc(&circuit_hash), Some(hash));
+        }
+
+        // Squeezing more elements uses the same padding. `LengthPrefix` needs the length absorbed first so is left out
+        for m in &modes[..3] {
+            let mut sponge = PoseidonSponge::new(&p_params, &sbox);
+            sponge.absorb(&inputs);
+            let squeezed = sponge.squeeze_n_with_padding(7, m);
+            assert_eq!(squeezed[0], Poseidon_sponge_hash(&inputs, &p_params, &sbox, *m));
+
+            let vars = inputs.iter().map(|i| LinearCombination::from(prover.commit(*i, Scalar::one()).1)).collect();
+            let mut sponge = PoseidonSpongeGadget::new(&p_params, &sbox);
+            sponge.absorb(&mut prover, vars).unwrap();
+            let outputs = sponge.squeeze_n_with_padding(&mut prover, 7, m).unwrap();
+            assert_eq!(outputs.iter().map(|o| prover.evaluate_lc(o).unwrap()).collect::<Vec<_>>(), squeezed);
+        }
+    }
+}
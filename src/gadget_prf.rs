@@ -0,0 +1,86 @@
+extern crate bulletproofs;
+extern crate curve25519_dalek;
+
+use bulletproofs::r1cs::{ConstraintSystem, R1CSError};
+use bulletproofs::r1cs::LinearCombination;
+use curve25519_dalek::scalar::Scalar;
+
+use crate::r1cs_utils::{AllocatedScalar, constrain_lc_with_scalar};
+use crate::gadget_poseidon::{PoseidonParams, SboxType, Poseidon_hash_2, Poseidon_hash_2_constraints, constant_statics, check_width, PoseidonError};
+
+/// PRF of `input` with `key`, which is `Poseidon_hash_2(key, input)`
+pub fn compute_prf(key: Scalar, input: Scalar, params: &PoseidonParams, sbox: &SboxType) -> Result<Scalar, PoseidonError> {
+    Ok(Poseidon_hash_2(key, input, params, sbox)?.into_scalar())
+}
+
+/// Prove that the PRF of the public `input` with the committed `key` is the public `output`. The input, padding
+/// and zeroes are constants in the circuit.
+pub fn prf_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    key: AllocatedScalar,
+    input: Scalar,
+    params: &PoseidonParams,
+    sbox: &SboxType,
+    output: &Scalar
+) -> Result<(), R1CSError> {
+    check_width(params, 3)?;
+    let statics = constant_statics(params.width - 2);
+    let hash = Poseidon_hash_2_constraints(cs, key.variable.into(), LinearCombination::from(input), statics, params, sbox)?;
+    constrain_lc_with_scalar(cs, hash, output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::r1cs::{Prover, Verifier, R1CSProof};
+    use bulletproofs::{BulletproofGens, PedersenGens};
+    use curve25519_dalek::ristretto::CompressedRistretto;
+    use merlin::Transcript;
+
+    #[test]
+    fn test_prf_gadget() {
+        let p_params = PoseidonParams::default();
+        let sbox = SboxType::Inverse;
+        let (key, input) = (Scalar::from(999u64), Scalar::from(7u64));
+        let output = compute_prf(key, input, &p_params, &sbox).unwrap();
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+
+        let (proof, commitment) = prf_proof(key, input, &output, &p_params, &sbox, &pc_gens, &bp_gens).unwrap();
+        assert!(prf_verify(commitment, input, &output, proof.clone(), &p_params, &sbox, &pc_gens, &bp_gens).is_ok());
+
+        // Proof with another key commitment
+        let other_key = Scalar::from(1000u64);
+        let (_, other_commitment) = prf_proof(other_key, input, &compute_prf(other_key, input, &p_params, &sbox).unwrap(), &p_params, &sbox, &pc_gens, &bp_gens).unwrap();
+        assert!(prf_verify(other_commitment, input, &output, proof, &p_params, &sbox, &pc_gens, &bp_gens).is_err());
+
+        // Wrong key for the output
+        assert!(prf_proof(other_key, input, &output, &p_params, &sbox, &pc_gens, &bp_gens)
+            .and_then(|(proof, comm)| prf_verify(comm, input, &output, proof, &p_params, &sbox, &pc_gens, &bp_gens)).is_err());
+    }
+
+    fn prf_proof(key: Scalar, input: Scalar, output: &Scalar, params: &PoseidonParams, sbox: &SboxType,
+                 pc_gens: &PedersenGens, bp_gens: &BulletproofGens) -> Result<(R1CSProof, CompressedRistretto), R1CSError> {
+        let mut prover_transcript = Transcript::new(b"PRFTest");
+        let mut rng = rand::thread_rng();
+        let mut prover = Prover::new(pc_gens, &mut prover_transcript);
+
+        let (com_k, var_k) = prover.commit(key, Scalar::random(&mut rng));
+        prf_gadget(&mut prover, AllocatedScalar::known(var_k, key), input, params, sbox, output)?;
+
+        Ok((prover.prove(bp_gens)?, com_k))
+    }
+
+    fn prf_verify(commitment: CompressedRistretto, input: Scalar, output: &Scalar, proof: R1CSProof, params: &PoseidonParams,
+                  sbox: &SboxType, pc_gens: &PedersenGens, bp_gens: &BulletproofGens) -> Result<(), R1CSError> {
+        let mut verifier_transcript = Transcript::new(b"PRFTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let var_k = AllocatedScalar::unknown(verifier.commit(commitment));
+        prf_gadget(&mut verifier, var_k, input, params, sbox, output)?;
+
+        verifier.verify(&proof, pc_gens, bp_gens)
+    }
+}
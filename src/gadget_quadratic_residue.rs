@@ -0,0 +1,125 @@
+extern crate bulletproofs;
+extern crate curve25519_dalek;
+
+use bulletproofs::r1cs::{ConstraintSystem, R1CSError};
+
+use crate::r1cs_utils::AllocatedScalar;
+use crate::scalar_utils::scalar_sqrt;
+
+/// Prove that committed `v` is a quadratic residue by allocating its square root `r` and constraining `r * r = v`.
+/// The prover errors if `v` has no square root.
+pub fn quadratic_residue_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    v: AllocatedScalar
+) -> Result<(), R1CSError> {
+    let root_pair = match v.assignment {
+        Some(a) => {
+            let r = scalar_sqrt(&a).ok_or(R1CSError::GadgetError {
+                description: String::from("Value is not a quadratic residue")
+            })?;
+            Some((r, r))
+        }
+        None => None
+    };
+    let (l, r, o) = cs.allocate_multiplier(root_pair)?;
+    cs.constrain(l - r);
+    cs.constrain(o - v.variable);
+
+    Ok(())
+}
+
+/// Prove that committed `root` is a square root of committed `value`, i.e. `root * root = value`. Both `root` and
+/// `-root` are valid witnesses, so this does not reveal which of the 2 roots is committed.
+pub fn sqrt_relation_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    root: AllocatedScalar,
+    value: AllocatedScalar
+) -> Result<(), R1CSError> {
+    let (_, _, o) = cs.multiply(root.variable.into(), root.variable.into());
+    cs.constrain(o - value.variable);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::r1cs::{Prover, Verifier};
+    use bulletproofs::{BulletproofGens, PedersenGens};
+    use curve25519_dalek::scalar::Scalar;
+    use merlin::Transcript;
+
+    #[test]
+    fn test_quadratic_residue_gadget() {
+        assert!(quadratic_residue_helper(Scalar::from(49u64)).is_ok());
+        let r = Scalar::from(987654321u64);
+        assert!(quadratic_residue_helper(r * r).is_ok());
+        // 2 and 2*9 are not quadratic residues so the prover cannot find a root
+        assert!(quadratic_residue_helper(Scalar::from(2u64)).is_err());
+        assert!(quadratic_residue_helper(Scalar::from(18u64)).is_err());
+    }
+
+    fn quadratic_residue_helper(v: Scalar) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(8, 1);
+
+        let (proof, commitment) = {
+            let mut prover_transcript = Transcript::new(b"QuadraticResidueTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com_v, var_v) = prover.commit(v, Scalar::random(&mut rng));
+            quadratic_residue_gadget(&mut prover, AllocatedScalar::known(var_v, v))?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, com_v)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"QuadraticResidueTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let var_v = AllocatedScalar::unknown(verifier.commit(commitment));
+        quadratic_residue_gadget(&mut verifier, var_v)?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+
+    #[test]
+    fn test_sqrt_relation_gadget() {
+        let root = Scalar::from(987654321u64);
+        assert!(sqrt_relation_helper(root, root * root).is_ok());
+        // The other root
+        assert!(sqrt_relation_helper(-root, root * root).is_ok());
+        assert!(sqrt_relation_helper(root, root * root + Scalar::one()).is_err());
+        assert!(sqrt_relation_helper(root, root).is_err());
+    }
+
+    fn sqrt_relation_helper(root: Scalar, value: Scalar) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(8, 1);
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"SqrtRelationTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com_r, var_r) = prover.commit(root, Scalar::random(&mut rng));
+            let (com_v, var_v) = prover.commit(value, Scalar::random(&mut rng));
+            sqrt_relation_gadget(&mut prover, AllocatedScalar::known(var_r, root), AllocatedScalar::known(var_v, value))?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, (com_r, com_v))
+        };
+
+        let mut verifier_transcript = Transcript::new(b"SqrtRelationTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+
+        let var_r = AllocatedScalar::unknown(verifier.commit(commitments.0));
+        let var_v = AllocatedScalar::unknown(verifier.commit(commitments.1));
+        sqrt_relation_gadget(&mut verifier, var_r, var_v)?;
+
+        Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
+    }
+}
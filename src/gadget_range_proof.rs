@@ -200,4 +200,4 @@ mod tests {
         // Verifier verifies proof
         Ok(verifier.verify(&proof, &pc_gens, &bp_gens)?)
     }
-}
\ No newline at end of file
+}
@@ -10,7 +10,7 @@ use bulletproofs::r1cs::LinearCombination;
 use merlin::Transcript;
 use rand::{RngCore, CryptoRng};
 
-use crate::r1cs_utils::{AllocatedQuantity, constrain_lc_with_scalar};
+use crate::r1cs_utils::{AllocatedQuantity, AllocatedScalar, constrain_lc_with_scalar};
 
 // Ensure `v` is a bit, hence 0 or 1
 pub fn bit_gadget<CS: ConstraintSystem>(
@@ -85,6 +85,18 @@ pub fn vector_product_gadget<CS: ConstraintSystem>(
     Ok(())
 }
 
+// Ensure `v` is either `a` or `b` by enforcing (v - a)*(v - b) = 0. Cheaper than the above gadgets for a set of size 2.
+pub fn one_of_two_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    v: AllocatedScalar,
+    a: Scalar,
+    b: Scalar
+) -> Result<(), R1CSError> {
+    let (_, _, o) = cs.multiply(v.variable - a, v.variable - b);
+    cs.constrain(o.into());
+    Ok(())
+}
+
 
 /// Allocate a bitmap for the `set` with 1 as the index of `value`, 0 otherwise. Then commit to values of bitmap
 /// and prove that each element is either 0 or 1, sum of elements of this bitmap is 1 (as there is only 1 element)
@@ -188,5 +200,42 @@ mod tests {
         let (proof, commitments) = gen_proof_of_set_membership(value, randomness, &set, &mut rng, label, &pc_gens, &bp_gens).unwrap();
         verify_proof_of_set_membership(&set, proof, commitments, label, &pc_gens, &bp_gens).unwrap();
     }
-}
 
+    #[test]
+    fn test_one_of_two_gadget() {
+        let (a, b) = (Scalar::from(7u64), Scalar::from(19u64));
+        assert!(one_of_two_gadget_helper(a, a, b).is_ok());
+        assert!(one_of_two_gadget_helper(b, a, b).is_ok());
+        assert!(one_of_two_gadget_helper(Scalar::from(8u64), a, b).is_err());
+    }
+
+    fn one_of_two_gadget_helper(value: Scalar, a: Scalar, b: Scalar) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(8, 1);
+
+        let (proof, commitment) = {
+            let mut prover_transcript = Transcript::new(b"OneOfTwoTest");
+            let mut rng = rand::thread_rng();
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com, var) = prover.commit(value, Scalar::random(&mut rng));
+            let v = AllocatedScalar {
+                variable: var,
+                assignment: Some(value),
+            };
+            one_of_two_gadget(&mut prover, v, a, b)?;
+
+            (prover.prove(&bp_gens)?, com)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"OneOfTwoTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let v = AllocatedScalar {
+            variable: verifier.commit(commitment),
+            assignment: None,
+        };
+        one_of_two_gadget(&mut verifier, v, a, b)?;
+
+        verifier.verify(&proof, &pc_gens, &bp_gens)
+    }
+}
@@ -4,6 +4,7 @@ extern crate merlin;
 extern crate bulletproofs;
 
 use std::collections::HashMap;
+use std::io::{Read, Write};
 use rand::SeedableRng;
 use rand::rngs::OsRng;
 use curve25519_dalek::scalar::Scalar;
@@ -11,19 +12,32 @@ use bulletproofs::r1cs::{ConstraintSystem, R1CSError, R1CSProof, Variable, Prove
 use bulletproofs::{BulletproofGens, PedersenGens};
 use merlin::Transcript;
 use bulletproofs::r1cs::LinearCombination;
+use serde_derive::{Serialize, Deserialize};
 
 use crate::scalar_utils::{ScalarBytes, ScalarBits, get_bits};
 use crate::r1cs_utils::{AllocatedScalar, constrain_lc_with_scalar};
 // use crate::gadget_mimc::{mimc, MIMC_ROUNDS, mimc_hash_2, mimc_gadget};
-use crate::gadget_poseidon::{PoseidonParams, Poseidon_hash_2, Poseidon_hash_2_constraints, Poseidon_hash_2_gadget, SboxType,
-                             allocate_statics_for_prover, allocate_statics_for_verifier};
+use crate::gadget_poseidon::{PoseidonParams, Poseidon_hash_2, Poseidon_hash_2_constraints, Poseidon_hash_2_gadget, SboxType, poseidon_hash_ct_eq,
+                             allocate_statics_for_prover, allocate_statics_for_verifier, check_width};
+use crate::gadget_merkle::empty_subtree_hashes;
 
 type DBVal = (Scalar, Scalar);
 
 pub const TreeDepth: usize = 253;
 
+/// Number of levels below the root whose hashes are recomputed when loading a saved tree
+const LOAD_CHECKED_LEVELS: usize = 4;
+
 // TODO: ABSTRACT HASH FUNCTION BETTER
 
+/// What `VanillaSparseMerkleTree::save` writes, the empty subtree hashes are recomputed on load
+#[derive(Serialize, Deserialize)]
+struct SavedTree {
+    depth: usize,
+    root: Scalar,
+    db: Vec<(ScalarBytes, DBVal)>
+}
+
 pub struct VanillaSparseMerkleTree<'a> {
     pub depth: usize,
     empty_tree_hashes: Vec<Scalar>,
@@ -34,19 +48,17 @@ pub struct VanillaSparseMerkleTree<'a> {
 }
 
 impl<'a> VanillaSparseMerkleTree<'a> {
+    /// Panics if the width of `hash_params` is less than 3
     pub fn new(hash_params: &'a PoseidonParams) -> VanillaSparseMerkleTree<'a> {
+        if let Err(e) = check_width(hash_params, 3) {
+            panic!("{}", e);
+        }
         let depth = TreeDepth;
         let mut db = HashMap::new();
-        let mut empty_tree_hashes: Vec<Scalar> = vec![];
-        empty_tree_hashes.push(Scalar::zero());
+        let empty_tree_hashes = empty_subtree_hashes(depth, Scalar::zero(), hash_params, &SboxType::Inverse).unwrap();
         for i in 1..=depth {
             let prev = empty_tree_hashes[i-1];
-            //let new = mimc(&prev, &prev, hash_constants);
-            let new = Poseidon_hash_2(prev.clone(), prev.clone(), hash_params, &SboxType::Inverse);
-            let key = new.to_bytes();
-
-            db.insert(key, (prev, prev));
-            empty_tree_hashes.push(new);
+            db.insert(empty_tree_hashes[i].to_bytes(), (prev, prev));
         }
 
         let root = empty_tree_hashes[depth].clone();
@@ -76,13 +88,13 @@ impl<'a> VanillaSparseMerkleTree<'a> {
                 if cur_idx.is_lsb_set() {
                     // LSB is set, so put new value on right
                     //let h =  mimc(&side_elem, &cur_val, self.hash_constants);
-                    let h =  Poseidon_hash_2(side_elem.clone(), cur_val.clone(), self.hash_params, &SboxType::Inverse);
+                    let h =  Poseidon_hash_2(side_elem.clone(), cur_val.clone(), self.hash_params, &SboxType::Inverse).unwrap().into_scalar();
                     self.update_db_with_key_val(h, (side_elem, cur_val));
                     h
                 } else {
                     // LSB is unset, so put new value on left
                     //let h =  mimc(&cur_val, &side_elem, self.hash_constants);
-                    let h =  Poseidon_hash_2(cur_val.clone(), side_elem.clone(), self.hash_params, &SboxType::Inverse);
+                    let h =  Poseidon_hash_2(cur_val.clone(), side_elem.clone(), self.hash_params, &SboxType::Inverse).unwrap().into_scalar();
                     self.update_db_with_key_val(h, (cur_val, side_elem));
                     h
                 }
@@ -139,10 +151,10 @@ impl<'a> VanillaSparseMerkleTree<'a> {
             cur_val = {
                 if cur_idx.is_lsb_set() {
                     // mimc(&proof[self.depth-1-i], &cur_val, self.hash_constants)
-                    Poseidon_hash_2(proof[self.depth-1-i].clone(), cur_val.clone(), self.hash_params, &SboxType::Inverse)
+                    Poseidon_hash_2(proof[self.depth-1-i].clone(), cur_val.clone(), self.hash_params, &SboxType::Inverse).unwrap().into_scalar()
                 } else {
                     // mimc(&cur_val, &proof[self.depth-1-i], self.hash_constants)
-                    Poseidon_hash_2(cur_val.clone(), proof[self.depth-1-i].clone(), self.hash_params, &SboxType::Inverse)
+                    Poseidon_hash_2(cur_val.clone(), proof[self.depth-1-i].clone(), self.hash_params, &SboxType::Inverse).unwrap().into_scalar()
                 }
             };
 
@@ -152,12 +164,67 @@ impl<'a> VanillaSparseMerkleTree<'a> {
         // Check if root is equal to cur_val
         match root {
             Some(r) => {
-                cur_val == *r
+                poseidon_hash_ct_eq(&cur_val, r).into()
             }
             None => {
-                cur_val == self.root
+                poseidon_hash_ct_eq(&cur_val, &self.root).into()
+            }
+        }
+    }
+
+    /// Write the depth, root and node database as JSON
+    pub fn save<W: Write>(&self, writer: W) -> Result<(), R1CSError> {
+        let saved = SavedTree {
+            depth: self.depth,
+            root: self.root,
+            db: self.db.iter().map(|(k, v)| (*k, *v)).collect()
+        };
+        serde_json::to_writer(writer, &saved).map_err(|e| R1CSError::GadgetError { description: e.to_string() })
+    }
+
+    /// Read a tree written by `save`. The top `LOAD_CHECKED_LEVELS` levels are rehashed and must match the stored
+    /// nodes, so a corrupted root or database is caught before the tree is used.
+    pub fn load<R: Read>(reader: R, hash_params: &'a PoseidonParams) -> Result<VanillaSparseMerkleTree<'a>, R1CSError> {
+        let saved: SavedTree = serde_json::from_reader(reader)
+            .map_err(|e| R1CSError::GadgetError { description: e.to_string() })?;
+        if saved.depth != TreeDepth {
+            return Err(R1CSError::GadgetError {
+                description: format!("Tree depth should be {}, found {}", TreeDepth, saved.depth)
+            })
+        }
+
+        let db: HashMap<ScalarBytes, DBVal> = saved.db.into_iter().collect();
+        let mut level = vec![saved.root];
+        for _ in 0..LOAD_CHECKED_LEVELS {
+            let mut next = vec![];
+            for node in level.iter() {
+                let (left, right) = match db.get(&node.to_bytes()) {
+                    Some(v) => *v,
+                    None => return Err(R1CSError::GadgetError {
+                        description: format!("Node {:?} is missing from the database", node)
+                    })
+                };
+                if Poseidon_hash_2(left, right, hash_params, &SboxType::Inverse)?.into_scalar() != *node {
+                    return Err(R1CSError::GadgetError {
+                        description: format!("Children of node {:?} do not hash to it", node)
+                    })
+                }
+                next.push(left);
+                next.push(right);
             }
+            // Empty subtrees repeat, no need to check them twice
+            next.sort_by_key(|n| n.to_bytes());
+            next.dedup();
+            level = next;
         }
+
+        Ok(VanillaSparseMerkleTree {
+            depth: saved.depth,
+            empty_tree_hashes: empty_subtree_hashes(saved.depth, Scalar::zero(), hash_params, &SboxType::Inverse)?,
+            db,
+            hash_params,
+            root: saved.root
+        })
     }
 
     fn update_db_with_key_val(&mut self, key: Scalar, val: DBVal) {
@@ -258,6 +325,41 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_vanilla_sparse_merkle_tree_save_load() {
+        let p_params = PoseidonParams::new(6, 4, 4, 140);
+        let mut tree = VanillaSparseMerkleTree::new(&p_params);
+        for i in 1..5 {
+            let s = Scalar::from(i as u32);
+            tree.update(s, s + Scalar::one());
+        }
+
+        let mut saved = vec![];
+        tree.save(&mut saved).unwrap();
+        let loaded = VanillaSparseMerkleTree::load(&saved[..], &p_params).unwrap();
+        assert_eq!(loaded.root, tree.root);
+
+        for i in 1..5 {
+            let s = Scalar::from(i as u32);
+            let (mut proof, mut loaded_proof) = (Some(vec![]), Some(vec![]));
+            assert_eq!(tree.get(s, &mut proof), loaded.get(s, &mut loaded_proof));
+            assert_eq!(proof, loaded_proof);
+            assert!(loaded.verify_proof(s, s + Scalar::one(), &loaded_proof.unwrap(), Some(&tree.root)));
+        }
+
+        // Stored root is not the hash of its children
+        let mut corrupted: SavedTree = serde_json::from_slice(&saved).unwrap();
+        corrupted.root = Scalar::from(7u32);
+        corrupted.db.push((corrupted.root.to_bytes(), (Scalar::one(), Scalar::one())));
+        let corrupted = serde_json::to_vec(&corrupted).unwrap();
+        assert!(VanillaSparseMerkleTree::load(&corrupted[..], &p_params).is_err());
+        // Root is missing from the database
+        let mut missing: SavedTree = serde_json::from_slice(&saved).unwrap();
+        missing.db.retain(|(k, _)| *k != tree.root.to_bytes());
+        let missing = serde_json::to_vec(&missing).unwrap();
+        assert!(VanillaSparseMerkleTree::load(&missing[..], &p_params).is_err());
+    }
+
     #[test]
     fn test_VSMT_Verif() {
         let mut test_rng: StdRng = SeedableRng::from_seed([24u8; 32]);
@@ -14,8 +14,8 @@ use bulletproofs::r1cs::LinearCombination;
 
 use crate::scalar_utils::{ScalarBytes, get_base_4_repr};
 use crate::r1cs_utils::{AllocatedScalar, constrain_lc_with_scalar};
-use crate::gadget_poseidon::{PoseidonParams, Poseidon_hash_4, Poseidon_hash_4_constraints, Poseidon_hash_4_gadget, SboxType,
-                             allocate_statics_for_prover, allocate_statics_for_verifier};
+use crate::gadget_poseidon::{PoseidonParams, Poseidon_hash_4, Poseidon_hash_4_constraints, Poseidon_hash_4_gadget, SboxType, poseidon_hash_ct_eq,
+                             allocate_statics_for_prover, allocate_statics_for_verifier, check_width};
 
 type DBVal = [Scalar; 4];
 type ProofNode = [Scalar; 3];
@@ -38,10 +38,14 @@ pub struct VanillaSparseMerkleTree_4<'a> {
 }
 
 impl<'a> VanillaSparseMerkleTree_4<'a> {
+    /// Panics if the width of `hash_params` is less than 5
     pub fn new(hash_params: &'a PoseidonParams) -> VanillaSparseMerkleTree_4<'a> {
         if (TreeDepth % 4) != 0 {
             panic!("Tree depth should be a multiple of 4");
         }
+        if let Err(e) = check_width(hash_params, 5) {
+            panic!("{}", e);
+        }
         let depth = TreeDepth;
         let mut db = HashMap::new();
         let mut empty_tree_hashes: Vec<Scalar> = vec![];
@@ -50,7 +54,7 @@ impl<'a> VanillaSparseMerkleTree_4<'a> {
             let prev = empty_tree_hashes[i-1];
             let input: [Scalar; 4] = [prev.clone(); 4];
             // Hash all 4 children at once
-            let new = Poseidon_hash_4(input.clone(), hash_params, &SboxType::Inverse);
+            let new = Poseidon_hash_4(input.clone(), hash_params, &SboxType::Inverse).unwrap().into_scalar();
             let key = new.to_bytes();
 
             db.insert(key, input);
@@ -88,7 +92,7 @@ impl<'a> VanillaSparseMerkleTree_4<'a> {
 
             let mut input: DBVal = [Scalar::zero(); 4];
             input.copy_from_slice(side_elem.as_slice());
-            let h = Poseidon_hash_4(input.clone(), self.hash_params, &SboxType::Inverse);
+            let h = Poseidon_hash_4(input.clone(), self.hash_params, &SboxType::Inverse).unwrap().into_scalar();
             self.update_db_with_key_val(h, input);
             cur_val = h;
         }
@@ -144,17 +148,17 @@ impl<'a> VanillaSparseMerkleTree_4<'a> {
             p.insert(*d as usize, cur_val);
             let mut input: DBVal = [Scalar::zero(); 4];
             input.copy_from_slice(p.as_slice());
-            let h = Poseidon_hash_4(input.clone(), self.hash_params, &SboxType::Inverse);
+            let h = Poseidon_hash_4(input.clone(), self.hash_params, &SboxType::Inverse).unwrap().into_scalar();
             cur_val = h;
         }
 
         // Check if root is equal to cur_val
         match root {
             Some(r) => {
-                cur_val == *r
+                poseidon_hash_ct_eq(&cur_val, r).into()
             }
             None => {
-                cur_val == self.root
+                poseidon_hash_ct_eq(&cur_val, &self.root).into()
             }
         }
     }
@@ -65,6 +65,25 @@ pub fn is_nonzero_gadget<CS: ConstraintSystem>(
     Ok(())
 }
 
+/// Enforces that x is not 0. Unlike `is_nonzero_gadget`, the inverse of x is allocated here from the
+/// assignment of x, so only x needs to be passed. On the verifier side, the inverse is allocated unassigned.
+pub fn nonzero_gadget<CS: ConstraintSystem>(
+    cs: &mut CS,
+    x: AllocatedScalar
+) -> Result<(), R1CSError> {
+    let inv = x.assignment.map(|x| x.invert());
+    let inv_var = cs.allocate(inv)?;
+
+    is_nonzero_gadget(
+        cs,
+        x,
+        AllocatedScalar {
+            variable: inv_var,
+            assignment: inv
+        }
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -159,4 +178,119 @@ mod tests {
             verifier.verify(&proof, &pc_gens, &bp_gens).unwrap();
         }
     }
-}
\ No newline at end of file
+
+    // Prove that `value` is non-zero with `nonzero_gadget`
+    fn nonzero_gadget_helper(value: Scalar) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(128, 1);
+        let mut rng = rand::thread_rng();
+
+        let (proof, commitment) = {
+            let mut prover_transcript = Transcript::new(b"NonZeroGadgetTest");
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com_val, var_val) = prover.commit(value.clone(), Scalar::random(&mut rng));
+            let alloc_scal = AllocatedScalar {
+                variable: var_val,
+                assignment: Some(value),
+            };
+            nonzero_gadget(&mut prover, alloc_scal)?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, com_val)
+        };
+
+        let mut verifier_transcript = Transcript::new(b"NonZeroGadgetTest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let var_val = verifier.commit(commitment);
+        let alloc_scal = AllocatedScalar {
+            variable: var_val,
+            assignment: None,
+        };
+
+        nonzero_gadget(&mut verifier, alloc_scal)?;
+
+        verifier.verify(&proof, &pc_gens, &bp_gens)
+    }
+
+    #[test]
+    fn test_nonzero_gadget() {
+        let mut rng = rand::thread_rng();
+        assert!(nonzero_gadget_helper(Scalar::random(&mut rng)).is_ok());
+        assert!(nonzero_gadget_helper(Scalar::from(7u64)).is_ok());
+
+        // Proving that 0 is non-zero must fail
+        assert!(nonzero_gadget_helper(Scalar::zero()).is_err());
+    }
+}
+
+#[cfg(test)]
+mod proptests {
+    use super::*;
+    use merlin::Transcript;
+    use proptest::prelude::{any, proptest, prop_assert, prop_assume, ProptestConfig};
+
+    // Prove that `value` is non-zero with `is_nonzero_gadget` and the given, possibly wrong, inverse
+    fn is_nonzero_gadget_helper(value: Scalar, inv: Scalar) -> Result<(), R1CSError> {
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(8, 1);
+        let mut rng = rand::thread_rng();
+
+        let (proof, commitments) = {
+            let mut prover_transcript = Transcript::new(b"IsNonZeroProptest");
+            let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+            let (com_val, var_val) = prover.commit(value, Scalar::random(&mut rng));
+            let (com_inv, var_inv) = prover.commit(inv, Scalar::random(&mut rng));
+            is_nonzero_gadget(&mut prover, AllocatedScalar::known(var_val, value), AllocatedScalar::known(var_inv, inv))?;
+
+            let proof = prover.prove(&bp_gens)?;
+
+            (proof, (com_val, com_inv))
+        };
+
+        let mut verifier_transcript = Transcript::new(b"IsNonZeroProptest");
+        let mut verifier = Verifier::new(&mut verifier_transcript);
+        let var_val = AllocatedScalar::unknown(verifier.commit(commitments.0));
+        let var_inv = AllocatedScalar::unknown(verifier.commit(commitments.1));
+
+        is_nonzero_gadget(&mut verifier, var_val, var_inv)?;
+
+        verifier.verify(&proof, &pc_gens, &bp_gens)
+    }
+
+    #[test]
+    fn test_is_nonzero_gadget_minus_one() {
+        // l - 1 is its own inverse
+        let minus_one = -Scalar::one();
+        assert_eq!(minus_one.invert(), minus_one);
+        assert!(is_nonzero_gadget_helper(minus_one, minus_one).is_ok());
+        assert!(is_nonzero_gadget_helper(minus_one, Scalar::one()).is_err());
+        assert!(is_nonzero_gadget_helper(minus_one, Scalar::zero()).is_err());
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn test_is_nonzero_gadget_nonzero(v in any::<[u8; 32]>()) {
+            let value = Scalar::from_bytes_mod_order(v);
+            prop_assume!(value != Scalar::zero());
+            prop_assert!(is_nonzero_gadget_helper(value, value.invert()).is_ok());
+        }
+
+        #[test]
+        fn test_is_nonzero_gadget_zero_with_fake_inverse(f in any::<[u8; 32]>()) {
+            // No inverse can make 0 pass
+            prop_assert!(is_nonzero_gadget_helper(Scalar::zero(), Scalar::from_bytes_mod_order(f)).is_err());
+        }
+
+        #[test]
+        fn test_is_nonzero_gadget_wrong_inverse(v in any::<[u8; 32]>(), f in any::<[u8; 32]>()) {
+            let (value, fake_inv) = (Scalar::from_bytes_mod_order(v), Scalar::from_bytes_mod_order(f));
+            prop_assume!(value * fake_inv != Scalar::one());
+            prop_assert!(is_nonzero_gadget_helper(value, fake_inv).is_err());
+        }
+    }
+}
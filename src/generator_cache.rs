@@ -0,0 +1,124 @@
+extern crate bulletproofs;
+
+use bulletproofs::{BulletproofGens, PedersenGens};
+use std::collections::HashMap;
+
+/// Generators for `needed` multipliers, rounded up to a power of 2, with the party capacity of `gens`. Generators are
+/// derived deterministically so the result is a prefix of `gens` and proofs made with either verify with the other,
+/// as long as both have enough generators.
+pub fn trim_gens(gens: &BulletproofGens, needed: usize) -> BulletproofGens {
+    BulletproofGens::new(needed.max(1).next_power_of_two(), gens.party_capacity)
+}
+
+/// Generators shared across proofs, e.g. by a verifier processing many proofs. `BulletproofGens` are built the first
+/// time a capacity is asked for and reused after. Capacities are rounded up to a power of 2 since a proof over `n`
+/// multipliers needs generators for `n` padded to a power of 2 anyway.
+pub struct GeneratorCache {
+    pc_gens: PedersenGens,
+    bp_gens: HashMap<usize, BulletproofGens>
+}
+
+impl GeneratorCache {
+    pub fn new() -> GeneratorCache {
+        GeneratorCache {
+            pc_gens: PedersenGens::default(),
+            bp_gens: HashMap::new()
+        }
+    }
+
+    pub fn pc_gens(&self) -> &PedersenGens {
+        &self.pc_gens
+    }
+
+    /// Pedersen generators and Bulletproof generators for at least `capacity` multipliers of 1 party
+    pub fn gens(&mut self, capacity: usize) -> (&PedersenGens, &BulletproofGens) {
+        let capacity = capacity.next_power_of_two();
+        let bp_gens = self.bp_gens.entry(capacity).or_insert_with(|| BulletproofGens::new(capacity, 1));
+        (&self.pc_gens, bp_gens)
+    }
+
+    /// Number of `BulletproofGens` built so far
+    pub fn num_built(&self) -> usize {
+        self.bp_gens.len()
+    }
+}
+
+impl Default for GeneratorCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use curve25519_dalek::scalar::Scalar;
+    use bulletproofs::r1cs::Prover;
+    use merlin::Transcript;
+    use crate::gadget_poseidon::{PoseidonParams, SboxType, Poseidon_hash_2, gen_proof_of_Poseidon_hash_2,
+                                 verify_proof_of_Poseidon_hash_2, Poseidon_hash_2_gadget, allocate_statics_for_prover,
+                                 estimate_multipliers, poseidon_hash_gens};
+    use crate::r1cs_utils::AllocatedScalar;
+
+    #[test]
+    fn test_generator_cache() {
+        let params = PoseidonParams::default();
+        let sbox = SboxType::Inverse;
+        let label = b"GeneratorCacheTest";
+        let mut cache = GeneratorCache::new();
+        let mut rng = rand::thread_rng();
+
+        let inputs = [(Scalar::from(1u64), Scalar::from(2u64)), (Scalar::from(3u64), Scalar::from(4u64))];
+        let proofs: Vec<_> = inputs.iter().map(|(xl, xr)| {
+            let (pc_gens, bp_gens) = cache.gens(2048);
+            gen_proof_of_Poseidon_hash_2(*xl, *xr, &params, &sbox, &mut rng, label, pc_gens, bp_gens).unwrap()
+        }).collect();
+
+        let first = cache.gens(2048).1 as *const BulletproofGens;
+        for ((xl, xr), (proof, comms)) in inputs.iter().zip(proofs) {
+            let output = Poseidon_hash_2(*xl, *xr, &params, &sbox).unwrap();
+            let (pc_gens, bp_gens) = cache.gens(2000);
+            assert!(std::ptr::eq(bp_gens, first));
+            assert!(verify_proof_of_Poseidon_hash_2(&output, &params, &sbox, proof, comms, label, pc_gens, bp_gens).is_ok());
+        }
+        assert_eq!(cache.num_built(), 1);
+
+        cache.gens(4096);
+        assert_eq!(cache.num_built(), 2);
+    }
+
+    #[test]
+    fn test_trim_gens() {
+        let params = PoseidonParams::default();
+        let label = b"TrimGensTest";
+        let (xl, xr) = (Scalar::from(1u64), Scalar::from(2u64));
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(4096, 1);
+        let mut rng = rand::thread_rng();
+
+        for sbox in &[SboxType::Cube, SboxType::Inverse] {
+            let output = Poseidon_hash_2(xl, xr, &params, sbox).unwrap();
+
+            // The estimate is the number of multipliers of the circuit
+            let mut transcript = Transcript::new(label);
+            let mut prover = Prover::new(&pc_gens, &mut transcript);
+            let (_, var_l) = prover.commit(xl, Scalar::one());
+            let (_, var_r) = prover.commit(xr, Scalar::one());
+            let statics = allocate_statics_for_prover(&mut prover, params.width - 2);
+            Poseidon_hash_2_gadget(&mut prover, AllocatedScalar::known(var_l, xl), AllocatedScalar::known(var_r, xr), statics, &params, sbox, &output).unwrap();
+            let needed = prover.num_multipliers();
+            assert_eq!(needed, estimate_multipliers(&params, sbox, 1));
+
+            let trimmed = trim_gens(&bp_gens, needed);
+            assert_eq!(trimmed.gens_capacity, needed.next_power_of_two());
+            assert!(trimmed.gens_capacity < bp_gens.gens_capacity);
+            assert_eq!(poseidon_hash_gens(&params, sbox, 1).gens_capacity, trimmed.gens_capacity);
+
+            // Proofs made with the larger generators verify with the trimmed ones and the other way around
+            let (proof, comms) = gen_proof_of_Poseidon_hash_2(xl, xr, &params, sbox, &mut rng, label, &pc_gens, &bp_gens).unwrap();
+            assert!(verify_proof_of_Poseidon_hash_2(&output, &params, sbox, proof, comms, label, &pc_gens, &trimmed).is_ok());
+            let (proof, comms) = gen_proof_of_Poseidon_hash_2(xl, xr, &params, sbox, &mut rng, label, &pc_gens, &trimmed).unwrap();
+            assert!(verify_proof_of_Poseidon_hash_2(&output, &params, sbox, proof, comms, label, &pc_gens, &bp_gens).is_ok());
+        }
+    }
+}
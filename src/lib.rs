@@ -6,17 +6,37 @@ extern crate merlin;
 
 pub mod scalar_utils;
 pub mod r1cs_utils;
+pub mod r1cs_capture;
 pub mod factors;
 pub mod gadget_not_equals;
 pub mod gadget_bound_check;
+pub mod gadget_min;
+pub mod gadget_arithmetic;
+pub mod gadget_modular;
 pub mod gadget_range_proof;
 pub mod gadget_set_membership;
 pub mod gadget_set_membership_1;
 pub mod gadget_set_non_membership;
 pub mod gadget_zero_nonzero;
+pub mod gadget_quadratic_residue;
+pub mod gadget_bitwise;
+pub mod gadget_lookup;
 pub mod gadget_mimc;
 pub mod gadget_vsmt_2;
 pub mod gadget_vsmt_4;
+pub mod gadget_merkle;
+pub mod gadget_4_ary_membership;
 pub mod gadget_osmt;    /// This is incomplete
 mod poseidon_constants;
+mod poseidon_grain;
 pub mod gadget_poseidon;
+pub mod gadget_poseidon_sponge;
+pub mod gadget_prf;
+pub mod gadget_hash_prefix;
+pub mod gadget_nullifier;
+pub mod gadget_accumulator;
+pub mod proof_bundle;
+pub mod proving_log;
+pub mod generator_cache;
+pub mod composer;
+pub mod prelude;
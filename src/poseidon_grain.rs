@@ -0,0 +1,103 @@
+extern crate curve25519_dalek;
+
+use curve25519_dalek::scalar::Scalar;
+
+const STATE_SIZE: usize = 80;
+
+/// The 80 bit Grain LFSR used by the Poseidon reference implementation to generate round constants. It is
+/// initialised with the field, the S-box, the field size, the width and the number of full and partial rounds, so
+/// different parameters get unrelated constants.
+pub struct GrainLfsr {
+    state: [bool; STATE_SIZE],
+    // Index of the oldest bit in `state`
+    head: usize,
+    field_bits: usize
+}
+
+impl GrainLfsr {
+    /// Both S-boxes use the same constants so the S-box bits are always those of `x^alpha`. `full_rounds` is the
+    /// total number of full rounds.
+    pub fn new(field_bits: usize, width: usize, full_rounds: usize, partial_rounds: usize) -> GrainLfsr {
+        let mut bits = vec![];
+        let mut push = |value: usize, num_bits: usize| {
+            for i in (0..num_bits).rev() {
+                bits.push((value >> i) & 1 == 1);
+            }
+        };
+        // Prime field
+        push(1, 2);
+        push(0, 4);
+        push(field_bits, 12);
+        push(width, 12);
+        push(full_rounds, 10);
+        push(partial_rounds, 10);
+        push((1 << 30) - 1, 30);
+
+        let mut state = [false; STATE_SIZE];
+        state.copy_from_slice(&bits);
+        let mut lfsr = GrainLfsr { state, head: 0, field_bits };
+        for _ in 0..160 {
+            lfsr.clock();
+        }
+        lfsr
+    }
+
+    /// The next `count` scalars, `width * total_rounds` of them for a permutation
+    pub fn round_constants(&mut self, count: usize) -> Vec<Scalar> {
+        (0..count).map(|_| self.next_scalar()).collect()
+    }
+
+    /// Take `field_bits` bits, most significant first, rejecting values not less than the group order
+    pub fn next_scalar(&mut self) -> Scalar {
+        loop {
+            let mut bytes = [0u8; 32];
+            for i in (0..self.field_bits).rev() {
+                if self.next_bit() {
+                    bytes[i / 8] |= 1 << (i % 8);
+                }
+            }
+            if let Some(s) = Scalar::from_canonical_bytes(bytes) {
+                return s
+            }
+        }
+    }
+
+    /// Output bits are filtered in pairs, the second bit is output only if the first is 1
+    fn next_bit(&mut self) -> bool {
+        loop {
+            let keep = self.clock();
+            let bit = self.clock();
+            if keep {
+                return bit
+            }
+        }
+    }
+
+    fn clock(&mut self) -> bool {
+        let s = |i: usize| self.state[(self.head + i) % STATE_SIZE];
+        let new_bit = s(62) ^ s(51) ^ s(38) ^ s(23) ^ s(13) ^ s(0);
+        self.state[self.head] = new_bit;
+        self.head = (self.head + 1) % STATE_SIZE;
+        new_bit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gadget_poseidon::SCALAR_FIELD_BITS;
+
+    #[test]
+    fn test_grain_round_constants() {
+        let constants = GrainLfsr::new(SCALAR_FIELD_BITS, 6, 8, 140).round_constants(20);
+        assert_eq!(constants, GrainLfsr::new(SCALAR_FIELD_BITS, 6, 8, 140).round_constants(20));
+        for i in 0..constants.len() {
+            for j in i+1..constants.len() {
+                assert_ne!(constants[i], constants[j]);
+            }
+        }
+        // Other parameters give other constants
+        assert_ne!(constants, GrainLfsr::new(SCALAR_FIELD_BITS, 6, 8, 141).round_constants(20));
+        assert_ne!(constants, GrainLfsr::new(SCALAR_FIELD_BITS, 5, 8, 140).round_constants(20));
+    }
+}
@@ -0,0 +1,65 @@
+//! Re-exports of the items commonly needed to build and prove a Poseidon hash circuit, so that
+//! `use bulletproofs_examples::prelude::*;` is enough for most uses.
+//!
+//! ```
+//! use bulletproofs_examples::prelude::*;
+//!
+//! let params = PoseidonParams::new(6, 4, 4, 140);
+//! let sbox = SboxType::Cube;
+//! let (xl, xr) = (Scalar::from(1u64), Scalar::from(2u64));
+//! let expected_output = Poseidon_hash_2(xl, xr, &params, &sbox).unwrap();
+//!
+//! let pc_gens = PedersenGens::default();
+//! let bp_gens = BulletproofGens::new(2048, 1);
+//! let num_statics = params.width - 2;
+//!
+//! let (proof, commitments) = {
+//!     let mut prover_transcript = Transcript::new(b"PreludeHash2");
+//!     let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+//!
+//!     let (com_l, var_l) = prover.commit(xl, Scalar::from(10u64));
+//!     let (com_r, var_r) = prover.commit(xr, Scalar::from(20u64));
+//!     let l_alloc = AllocatedScalar::known(var_l, xl);
+//!     let r_alloc = AllocatedScalar::known(var_r, xr);
+//!     let statics = allocate_statics_for_prover(&mut prover, num_statics);
+//!
+//!     Poseidon_hash_2_gadget(&mut prover, l_alloc, r_alloc, statics, &params, &sbox, &expected_output).unwrap();
+//!     (prover.prove(&bp_gens).unwrap(), vec![com_l, com_r])
+//! };
+//!
+//! let mut verifier_transcript = Transcript::new(b"PreludeHash2");
+//! let mut verifier = Verifier::new(&mut verifier_transcript);
+//! let l_alloc = AllocatedScalar::unknown(verifier.commit(commitments[0]));
+//! let r_alloc = AllocatedScalar::unknown(verifier.commit(commitments[1]));
+//! let statics = allocate_statics_for_verifier(&mut verifier, num_statics, &pc_gens);
+//!
+//! Poseidon_hash_2_gadget(&mut verifier, l_alloc, r_alloc, statics, &params, &sbox, &expected_output).unwrap();
+//! assert!(verifier.verify(&proof, &pc_gens, &bp_gens).is_ok());
+//! ```
+
+pub use bulletproofs::r1cs::{ConstraintSystem, LinearCombination, R1CSError, R1CSProof, Variable, Prover, Verifier};
+pub use bulletproofs::{BulletproofGens, PedersenGens};
+pub use curve25519_dalek::ristretto::CompressedRistretto;
+pub use curve25519_dalek::scalar::Scalar;
+pub use merlin::Transcript;
+
+pub use crate::generator_cache::{GeneratorCache, trim_gens};
+pub use crate::r1cs_utils::{AllocatedQuantity, AllocatedScalar, constrain_lc_with_scalar};
+pub use crate::gadget_poseidon::{PoseidonParams, SboxType, Poseidon_hash_2, Poseidon_hash_4, Poseidon_hash_2_batch,
+                                 Poseidon_hash_2_with_ad, Poseidon_hash_2_with_ad_gadget,
+                                 Poseidon_hash_2_to_2, Poseidon_hash_2_to_2_gadget, compression_2to2_gadget,
+                                 Poseidon_permutation_constraints, Poseidon_permutation_constraints_with_taps,
+                                 Poseidon_permutation_gadget, Poseidon_permutation_gadget_auto, Poseidon_permutation_gadget_alloc,
+                                 Poseidon_hash_2_constraints, Poseidon_hash_2_gadget, Poseidon_hash_2_committed_output_gadget,
+                                 Poseidon_hash_2_gadget_ex, ZeroMode, Poseidon_hash_2_mixed_gadget, bind_to_public_hash_gadget,
+                                 addressed_commitment_gadget,
+                                 Poseidon_hash_4_constraints, Poseidon_hash_4_gadget,
+                                 allocate_statics_for_prover, allocate_statics_for_verifier, constant_statics,
+                                 poseidon_hash_2_num_commitments, poseidon_hash_2_num_commitments_with_constant_statics,
+                                 poseidon_hash_2_mixed_num_commitments,
+                                 gen_proof_of_Poseidon_hash_2, gen_proof_of_Poseidon_hash_2_with_blindings,
+                                 gen_proof_of_Poseidon_hash_2_deterministic, gen_proof_of_Poseidon_hash_2_batch, verify_poseidon_stream,
+                                 verify_proof_of_Poseidon_hash_2, poseidon_transcript_label, PoseidonHashKind,
+                                 Poseidon_hash_n, recommended_rounds, estimate_prover_memory, PoseidonRegistry, PoseidonError,
+                                 estimate_multipliers, poseidon_hash_gens,
+                                 PoseidonHash, PADDING_CONST, ZERO_CONST};
@@ -0,0 +1,93 @@
+extern crate bulletproofs;
+extern crate curve25519_dalek;
+
+use bulletproofs::r1cs::{R1CSError, R1CSProof};
+use bulletproofs::{BulletproofGens, PedersenGens};
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
+use serde_derive::{Serialize, Deserialize};
+use sha3::{Digest, Sha3_256};
+
+use crate::gadget_poseidon::{PoseidonParams, SboxType, PoseidonHashKind, poseidon_transcript_label,
+                             verify_proof_of_Poseidon_hash_2};
+
+/// SHA3-256 of the Poseidon parameters, i.e. the width, rounds and S-box followed by the round keys and MDS matrix
+pub fn params_hash(params: &PoseidonParams, sbox: &SboxType) -> [u8; 32] {
+    let mut hasher = Sha3_256::new();
+    hasher.input(&poseidon_transcript_label(params, sbox, PoseidonHashKind::Permutation));
+    let mut constants = vec![];
+    params.to_writer(&mut constants).unwrap();
+    hasher.input(&constants);
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&hasher.result());
+    hash
+}
+
+/// Everything a verifier needs besides the public inputs: the proof, the commitments and the hash of the
+/// parameters the proof was created with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProofBundle {
+    pub proof: Vec<u8>,
+    pub commitments: Vec<CompressedRistretto>,
+    pub params_hash: [u8; 32]
+}
+
+impl ProofBundle {
+    pub fn new(proof: &R1CSProof, commitments: Vec<CompressedRistretto>, params: &PoseidonParams, sbox: &SboxType) -> Self {
+        ProofBundle {
+            proof: proof.to_bytes(),
+            commitments,
+            params_hash: params_hash(params, sbox)
+        }
+    }
+
+    /// Verify a proof created by `gen_proof_of_Poseidon_hash_2`. Fails before verifying the proof if it was
+    /// created with other parameters.
+    pub fn verify_poseidon_hash_2(&self, expected_params: &PoseidonParams, sbox: &SboxType, expected_output: &Scalar,
+                                  transcript_label: &'static [u8], pc_gens: &PedersenGens, bp_gens: &BulletproofGens) -> Result<(), R1CSError> {
+        if self.params_hash != params_hash(expected_params, sbox) {
+            return Err(R1CSError::GadgetError {
+                description: String::from("Proof was created with different Poseidon parameters")
+            })
+        }
+        let proof = R1CSProof::from_bytes(&self.proof)?;
+        verify_proof_of_Poseidon_hash_2(expected_output, expected_params, sbox, proof, self.commitments.clone(),
+                                        transcript_label, pc_gens, bp_gens)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gadget_poseidon::{Poseidon_hash_2, gen_proof_of_Poseidon_hash_2};
+
+    #[test]
+    fn test_proof_bundle() {
+        let params = PoseidonParams::default();
+        let sbox = SboxType::Inverse;
+        let (xl, xr) = (Scalar::from(1u64), Scalar::from(2u64));
+        let output = Poseidon_hash_2(xl, xr, &params, &sbox).unwrap().into_scalar();
+
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let mut rng = rand::thread_rng();
+        let (proof, comms) = gen_proof_of_Poseidon_hash_2(xl, xr, &params, &sbox, &mut rng, b"ProofBundleTest", &pc_gens, &bp_gens).unwrap();
+
+        let bundle = ProofBundle::new(&proof, comms, &params, &sbox);
+        let json = serde_json::to_string(&bundle).unwrap();
+        let bundle: ProofBundle = serde_json::from_str(&json).unwrap();
+        assert!(bundle.verify_poseidon_hash_2(&params, &sbox, &output, b"ProofBundleTest", &pc_gens, &bp_gens).is_ok());
+
+        // Wrong output
+        assert!(bundle.verify_poseidon_hash_2(&params, &sbox, &(output + Scalar::one()), b"ProofBundleTest", &pc_gens, &bp_gens).is_err());
+
+        // Params mismatch is caught before verifying
+        let other_params = params.with_partial_rounds(params.partial_rounds - 1).unwrap();
+        assert_ne!(params_hash(&params, &sbox), params_hash(&other_params, &sbox));
+        assert_ne!(params_hash(&params, &sbox), params_hash(&params, &SboxType::Cube));
+        match bundle.verify_poseidon_hash_2(&other_params, &sbox, &output, b"ProofBundleTest", &pc_gens, &bp_gens) {
+            Err(R1CSError::GadgetError { description }) => assert!(description.contains("different Poseidon parameters")),
+            _ => panic!("Expected a params mismatch")
+        }
+    }
+}
@@ -0,0 +1,148 @@
+extern crate bulletproofs;
+extern crate curve25519_dalek;
+extern crate merlin;
+
+use bulletproofs::r1cs::{R1CSError, R1CSProof, Prover};
+use bulletproofs::{BulletproofGens, PedersenGens};
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use rand::{RngCore, CryptoRng, SeedableRng};
+use rand::rngs::StdRng;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::r1cs_utils::AllocatedScalar;
+
+/// Committed values and blindings of a proving session, in the order they were committed, along with the transcript
+/// label and the seed of the randomness `Prover::prove_with_rng` mixes into its own blinding factors. Replaying the log
+/// gives the same commitments and byte for byte the same proof, which helps debugging a failed proof. The logged
+/// session must commit with `ProvingLog::commit` and prove with `ProvingLog::prove`.
+///
+/// The log holds the witness, i.e. the committed values and their blindings, in the clear. Anyone with the log can
+/// open the commitments, so keep it as secret as the witness and do not log sessions with real secrets in production.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProvingLog {
+    pub transcript_label: Vec<u8>,
+    pub rng_seed: [u8; 32],
+    pub values: Vec<Scalar>,
+    pub blindings: Vec<Scalar>
+}
+
+impl ProvingLog {
+    /// `transcript_label` is the label of `Transcript::new` for the session. Pick a fresh random `rng_seed` for each
+    /// session, a reused seed with a different witness leaks the witness as with any reused proof randomness.
+    pub fn new(transcript_label: &[u8], rng_seed: [u8; 32]) -> ProvingLog {
+        ProvingLog {
+            transcript_label: transcript_label.to_vec(),
+            rng_seed,
+            values: vec![],
+            blindings: vec![]
+        }
+    }
+
+    /// Commit to `value` with a blinding from `rng` and record both
+    pub fn commit<R: RngCore + CryptoRng>(&mut self, prover: &mut Prover, value: Scalar, rng: &mut R) -> (CompressedRistretto, AllocatedScalar) {
+        let blinding = Scalar::random(rng);
+        self.values.push(value);
+        self.blindings.push(blinding);
+        let (com, var) = prover.commit(value, blinding);
+        (com, AllocatedScalar::known(var, value))
+    }
+
+    /// Prove with the randomness seeded by `rng_seed`, so the same session gives the same proof
+    pub fn prove(&self, prover: Prover, bp_gens: &BulletproofGens) -> Result<R1CSProof, R1CSError> {
+        let mut rng = StdRng::from_seed(self.rng_seed);
+        prover.prove_with_rng(bp_gens, &mut rng)
+    }
+
+    /// Commit to the recorded values with the recorded blindings, build the circuit with `build_fn` over the
+    /// committed values and prove. The session's transcript is `Transcript::new(transcript_label)` so the label
+    /// needs to be the logged one. `build_fn` must add the same constraints as the logged session, including any
+    /// message the session appended to the transcript after creating the prover.
+    pub fn replay<F>(&self, transcript_label: &'static [u8], pc_gens: &PedersenGens, bp_gens: &BulletproofGens, build_fn: F) -> Result<(R1CSProof, Vec<CompressedRistretto>), R1CSError>
+        where F: FnOnce(&mut Prover, Vec<AllocatedScalar>) -> Result<(), R1CSError> {
+        if transcript_label != self.transcript_label.as_slice() {
+            return Err(R1CSError::GadgetError {
+                description: String::from("Transcript label differs from the logged label")
+            })
+        }
+        if self.values.len() != self.blindings.len() {
+            return Err(R1CSError::GadgetError {
+                description: format!("Log has {} values but {} blindings", self.values.len(), self.blindings.len())
+            })
+        }
+        let mut transcript = Transcript::new(transcript_label);
+        let mut prover = Prover::new(pc_gens, &mut transcript);
+        let mut comms = vec![];
+        let mut allocs = vec![];
+        for (v, b) in self.values.iter().zip(self.blindings.iter()) {
+            let (com, var) = prover.commit(*v, *b);
+            comms.push(com);
+            allocs.push(AllocatedScalar::known(var, *v));
+        }
+        build_fn(&mut prover, allocs)?;
+        Ok((self.prove(prover, bp_gens)?, comms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::r1cs::Verifier;
+    use crate::gadget_poseidon::{PoseidonParams, SboxType, Poseidon_hash_2, Poseidon_hash_2_gadget,
+                                 allocate_statics_for_prover, allocate_statics_for_verifier};
+
+    #[test]
+    fn test_proving_log_replay() {
+        let params = PoseidonParams::default();
+        let sbox = SboxType::Inverse;
+        let (xl, xr) = (Scalar::from(1u64), Scalar::from(2u64));
+        let output = Poseidon_hash_2(xl, xr, &params, &sbox).unwrap().into_scalar();
+        let num_statics = params.width - 2;
+        let pc_gens = PedersenGens::default();
+        let bp_gens = BulletproofGens::new(2048, 1);
+        let label = b"ProvingLogTest";
+
+        let mut rng = rand::thread_rng();
+        let mut rng_seed = [0u8; 32];
+        rng.fill_bytes(&mut rng_seed);
+        let mut log = ProvingLog::new(label, rng_seed);
+        let (proof, comms) = {
+            let mut transcript = Transcript::new(label);
+            let mut prover = Prover::new(&pc_gens, &mut transcript);
+            let (com_l, l_alloc) = log.commit(&mut prover, xl, &mut rng);
+            let (com_r, r_alloc) = log.commit(&mut prover, xr, &mut rng);
+            let statics = allocate_statics_for_prover(&mut prover, num_statics);
+            Poseidon_hash_2_gadget(&mut prover, l_alloc, r_alloc, statics, &params, &sbox, &output).unwrap();
+            (log.prove(prover, &bp_gens).unwrap(), vec![com_l, com_r])
+        };
+
+        let verify = |proof: R1CSProof, comms: &[CompressedRistretto]| {
+            let mut transcript = Transcript::new(label);
+            let mut verifier = Verifier::new(&mut transcript);
+            let l_alloc = AllocatedScalar::unknown(verifier.commit(comms[0]));
+            let r_alloc = AllocatedScalar::unknown(verifier.commit(comms[1]));
+            let statics = allocate_statics_for_verifier(&mut verifier, num_statics, &pc_gens);
+            Poseidon_hash_2_gadget(&mut verifier, l_alloc, r_alloc, statics, &params, &sbox, &output)?;
+            verifier.verify(&proof, &pc_gens, &bp_gens)
+        };
+        assert!(verify(proof.clone(), &comms).is_ok());
+
+        // Replay from the serialized log
+        let log: ProvingLog = serde_json::from_str(&serde_json::to_string(&log).unwrap()).unwrap();
+        assert_eq!(log.values, vec![xl, xr]);
+        let (replayed_proof, replayed_comms) = log.replay(label, &pc_gens, &bp_gens, |prover, allocs| {
+            let statics = allocate_statics_for_prover(prover, num_statics);
+            Poseidon_hash_2_gadget(prover, allocs[0], allocs[1], statics, &params, &sbox, &output).map(|_| ())
+        }).unwrap();
+        assert_eq!(replayed_comms, comms);
+        assert_eq!(replayed_proof.to_bytes(), proof.to_bytes());
+        assert!(verify(replayed_proof, &replayed_comms).is_ok());
+
+        assert!(log.replay(b"OtherLabel", &pc_gens, &bp_gens, |_, _| Ok(())).is_err());
+
+        let mut broken = log.clone();
+        broken.blindings.pop();
+        assert!(broken.replay(label, &pc_gens, &bp_gens, |_, _| Ok(())).is_err());
+    }
+}
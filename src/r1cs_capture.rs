@@ -0,0 +1,181 @@
+extern crate bulletproofs;
+extern crate curve25519_dalek;
+extern crate merlin;
+
+use bulletproofs::r1cs::{ConstraintSystem, Prover, R1CSError, Variable};
+use bulletproofs::r1cs::LinearCombination;
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+
+/// Sparse row of a constraint matrix, the columns are the variables.
+pub type SparseRow = Vec<(Variable, Scalar)>;
+
+/// Constraints of a circuit as R1CS matrices such that for each row `i`, `<A_i, z> * <B_i, z> = <C_i, z>` where `z`
+/// is the assignment of all variables and `Variable::One()` is 1. The first `num_multipliers` rows are the
+/// multiplication gates `left_i * right_i = output_i` and the rest are the linear constraints `lc * 1 = 0`, including
+/// the ones `multiply` adds to tie its inputs to the gate.
+#[derive(Clone, Debug)]
+pub struct ConstraintMatrices {
+    pub num_multipliers: usize,
+    pub num_linear_constraints: usize,
+    pub a: Vec<SparseRow>,
+    pub b: Vec<SparseRow>,
+    pub c: Vec<SparseRow>
+}
+
+impl ConstraintMatrices {
+    pub fn num_rows(&self) -> usize {
+        self.a.len()
+    }
+}
+
+/// Number of multipliers and linear constraints a constraint system already has
+pub trait ConstraintCounts {
+    fn num_multipliers(&self) -> usize;
+    fn num_constraints(&self) -> usize;
+}
+
+impl ConstraintCounts for Prover<'_, '_> {
+    fn num_multipliers(&self) -> usize {
+        Prover::num_multipliers(self)
+    }
+
+    fn num_constraints(&self) -> usize {
+        Prover::num_constraints(self)
+    }
+}
+
+/// Constraint system which passes every call to `inner` while recording the multipliers and linear constraints.
+/// Only the multipliers and constraints added after creating it are recorded.
+pub struct RecordingConstraintSystem<'a, CS: ConstraintSystem> {
+    inner: &'a mut CS,
+    start_multipliers: usize,
+    start_constraints: usize,
+    linear_constraints: Vec<LinearCombination>
+}
+
+impl<'a, CS: ConstraintSystem + ConstraintCounts> RecordingConstraintSystem<'a, CS> {
+    pub fn new(inner: &'a mut CS) -> Self {
+        let start_multipliers = inner.num_multipliers();
+        let start_constraints = inner.num_constraints();
+        RecordingConstraintSystem {
+            inner,
+            start_multipliers,
+            start_constraints,
+            linear_constraints: vec![]
+        }
+    }
+
+    pub fn into_matrices(self) -> ConstraintMatrices {
+        let unit = |v: Variable| vec![(v, Scalar::one())];
+        let end_multipliers = self.inner.num_multipliers();
+        debug_assert_eq!(self.inner.num_constraints() - self.start_constraints, self.linear_constraints.len());
+        let (mut a, mut b, mut c) = (vec![], vec![], vec![]);
+        for i in self.start_multipliers..end_multipliers {
+            a.push(unit(Variable::MultiplierLeft(i)));
+            b.push(unit(Variable::MultiplierRight(i)));
+            c.push(unit(Variable::MultiplierOutput(i)));
+        }
+        let num_linear_constraints = self.linear_constraints.len();
+        for lc in self.linear_constraints {
+            a.push(lc.get_terms());
+            b.push(unit(Variable::One()));
+            c.push(vec![]);
+        }
+        ConstraintMatrices {
+            num_multipliers: end_multipliers - self.start_multipliers,
+            num_linear_constraints,
+            a,
+            b,
+            c
+        }
+    }
+}
+
+impl<'a, CS: ConstraintSystem> ConstraintSystem for RecordingConstraintSystem<'a, CS> {
+    fn transcript(&mut self) -> &mut Transcript {
+        self.inner.transcript()
+    }
+
+    fn multiply(&mut self, left: LinearCombination, right: LinearCombination) -> (Variable, Variable, Variable) {
+        let (l, r, o) = self.inner.multiply(left.clone(), right.clone());
+        self.linear_constraints.push(left - l);
+        self.linear_constraints.push(right - r);
+        (l, r, o)
+    }
+
+    fn allocate(&mut self, assignment: Option<Scalar>) -> Result<Variable, R1CSError> {
+        self.inner.allocate(assignment)
+    }
+
+    fn allocate_multiplier(&mut self, input_assignments: Option<(Scalar, Scalar)>) -> Result<(Variable, Variable, Variable), R1CSError> {
+        self.inner.allocate_multiplier(input_assignments)
+    }
+
+    fn allocate_single(&mut self, assignment: Option<Scalar>) -> Result<(Variable, Option<Variable>), R1CSError> {
+        self.inner.allocate_single(assignment)
+    }
+
+    fn constrain(&mut self, lc: LinearCombination) {
+        self.linear_constraints.push(lc.clone());
+        self.inner.constrain(lc);
+    }
+
+    fn evaluate_lc(&self, lc: &LinearCombination) -> Option<Scalar> {
+        self.inner.evaluate_lc(lc)
+    }
+}
+
+/// Run `build_fn` over `cs` and return the constraints it added as R1CS matrices, without the ones `cs` already had.
+/// The constraints are also added to `cs` so it can be used for proving after.
+pub fn capture_constraints<CS, F>(cs: &mut CS, build_fn: F) -> Result<ConstraintMatrices, R1CSError>
+    where CS: ConstraintSystem + ConstraintCounts, F: FnOnce(&mut RecordingConstraintSystem<CS>) -> Result<(), R1CSError> {
+    let mut recorder = RecordingConstraintSystem::new(cs);
+    build_fn(&mut recorder)?;
+    Ok(recorder.into_matrices())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::r1cs::Prover;
+    use bulletproofs::PedersenGens;
+    use crate::r1cs_utils::AllocatedScalar;
+    use crate::gadget_poseidon::{PoseidonParams, SboxType, Poseidon_permutation, Poseidon_permutation_gadget};
+
+    #[test]
+    fn test_capture_poseidon_permutation() {
+        let s_params = PoseidonParams::default();
+        let width = s_params.width;
+        let sbox_type = SboxType::Inverse;
+        let input: Vec<Scalar> = (0..width).map(|i| Scalar::from(i as u64)).collect();
+        let expected_output = Poseidon_permutation(&input, &s_params, &sbox_type);
+
+        let pc_gens = PedersenGens::default();
+        let mut prover_transcript = Transcript::new(b"CaptureConstraints");
+        let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+        let allocs: Vec<AllocatedScalar> = input.iter().map(|i| {
+            let (_, var) = prover.commit(*i, Scalar::one());
+            AllocatedScalar::known(var, *i)
+        }).collect();
+
+        // Constraints added before capturing are not captured
+        let (_, _, o) = prover.multiply(allocs[0].variable.into(), allocs[1].variable.into());
+        prover.constrain(o.into());
+        let (multipliers_before, constraints_before) = (prover.num_multipliers(), prover.num_constraints());
+
+        let matrices = capture_constraints(&mut prover, |cs| {
+            Poseidon_permutation_gadget(cs, allocs, &s_params, &sbox_type, &expected_output)
+        }).unwrap();
+
+        assert_eq!(matrices.num_multipliers, prover.num_multipliers() - multipliers_before);
+        assert_eq!(matrices.num_linear_constraints, prover.num_constraints() - constraints_before);
+        assert_eq!(matrices.num_rows(), matrices.num_multipliers + matrices.num_linear_constraints);
+        assert!(matrices.a.iter().flatten().all(|(v, _)| match v {
+            Variable::MultiplierLeft(i) | Variable::MultiplierRight(i) | Variable::MultiplierOutput(i) => *i >= multipliers_before,
+            _ => true
+        }));
+        assert_eq!(matrices.a.len(), matrices.b.len());
+        assert_eq!(matrices.a.len(), matrices.c.len());
+    }
+}
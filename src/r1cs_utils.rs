@@ -2,6 +2,7 @@ use bulletproofs::r1cs::{ConstraintSystem, R1CSError, Variable};
 use curve25519_dalek::scalar::Scalar;
 use bulletproofs::{BulletproofGens, PedersenGens};
 use bulletproofs::r1cs::LinearCombination;
+use std::collections::HashMap;
 
 /// Represents a variable for quantity, along with its assignment.
 #[derive(Copy, Clone, Debug)]
@@ -16,18 +17,73 @@ pub struct AllocatedScalar {
     pub assignment: Option<Scalar>
 }
 
+impl AllocatedScalar {
+    pub fn new(variable: Variable, assignment: Option<Scalar>) -> Self {
+        AllocatedScalar { variable, assignment }
+    }
+
+    /// For the prover, which knows the value of the variable.
+    ///
+    /// ```
+    /// use bulletproofs_examples::prelude::*;
+    ///
+    /// let params = PoseidonParams::default();
+    /// let sbox = SboxType::Inverse;
+    /// let (xl, xr) = (Scalar::from(1u64), Scalar::from(2u64));
+    /// let expected_output = Poseidon_hash_2(xl, xr, &params, &sbox).unwrap();
+    ///
+    /// let pc_gens = PedersenGens::default();
+    /// let mut prover_transcript = Transcript::new(b"AllocatedScalar");
+    /// let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+    /// let (_, var_l) = prover.commit(xl, Scalar::from(10u64));
+    /// let (_, var_r) = prover.commit(xr, Scalar::from(20u64));
+    /// let statics = allocate_statics_for_prover(&mut prover, params.width - 2);
+    /// assert!(Poseidon_hash_2_gadget(&mut prover, AllocatedScalar::known(var_l, xl), AllocatedScalar::known(var_r, xr),
+    ///                                statics, &params, &sbox, &expected_output).is_ok());
+    /// ```
+    pub fn known(variable: Variable, value: Scalar) -> Self {
+        AllocatedScalar { variable, assignment: Some(value) }
+    }
+
+    /// For the verifier, which does not know the value of the variable.
+    pub fn unknown(variable: Variable) -> Self {
+        AllocatedScalar { variable, assignment: None }
+    }
+}
+
 /// Enforces that the quantity of v is in the range [0, 2^n).
 pub fn positive_no_gadget<CS: ConstraintSystem>(
     cs: &mut CS,
     v: AllocatedQuantity,
     bit_size: usize) -> Result<(), R1CSError> {
+    bit_decompose(cs, v, bit_size)?;
+    Ok(())
+}
+
+/// Decompose the quantity of v into `bit_size` bits, constraining each to be a bit and their weighted sum to be v.
+/// Returns the variables for bits, least significant bit first. Errors on the prover side if v needs more than
+/// `bit_size` bits since the constraints would not be satisfiable.
+pub fn bit_decompose<CS: ConstraintSystem>(
+    cs: &mut CS,
+    v: AllocatedQuantity,
+    bit_size: usize) -> Result<Vec<Variable>, R1CSError> {
+    if let Some(q) = v.assignment {
+        let bit_length = (64 - q.leading_zeros()) as usize;
+        if bit_length > bit_size {
+            return Err(R1CSError::GadgetError {
+                description: format!("Value {} has bit length {} which is more than the {} bits allowed", q, bit_length, bit_size)
+            })
+        }
+    }
+
+    let mut bits = vec![];
     let mut constraint_v = vec![(v.variable, -Scalar::one())];
     let mut exp_2 = Scalar::one();
     for i in 0..bit_size {
         // Create low-level variables and add them to constraints
 
         let (a, b, o) = cs.allocate_multiplier(v.assignment.map(|q| {
-            let bit: u64 = (q >> i) & 1;
+            let bit: u64 = if i < 64 { (q >> i) & 1 } else { 0 };
             ((1 - bit).into(), bit.into())
         }))?;
 
@@ -39,15 +95,77 @@ pub fn positive_no_gadget<CS: ConstraintSystem>(
 
         constraint_v.push((b, exp_2)  );
         exp_2 = exp_2 + exp_2;
+        bits.push(b);
     }
 
     // Enforce that -v + Sum(b_i * 2^i, i = 0..n-1) = 0 => Sum(b_i * 2^i, i = 0..n-1) = v
     cs.constrain(constraint_v.iter().collect());
 
-    Ok(())
+    Ok(bits)
 }
 
 /// Constrain a linear combination to be equal to a scalar
 pub fn constrain_lc_with_scalar<CS: ConstraintSystem>(cs: &mut CS, lc: LinearCombination, scalar: &Scalar) {
     cs.constrain(lc - LinearCombination::from(*scalar));
-}
\ No newline at end of file
+}
+
+/// Evaluate `lc` with the values of its variables in `assignment`, outside of any constraint system. `Variable::One()`
+/// is 1 and need not be in `assignment`. Returns None if a variable is missing from `assignment`.
+pub fn eval_lc(lc: &LinearCombination, assignment: &HashMap<Variable, Scalar>) -> Option<Scalar> {
+    let mut sum = Scalar::zero();
+    for (var, coeff) in lc.clone().get_terms() {
+        let val = match var {
+            Variable::One() => Scalar::one(),
+            _ => *assignment.get(&var)?
+        };
+        sum += coeff * val;
+    }
+    Some(sum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::r1cs::Prover;
+    use merlin::Transcript;
+
+    #[test]
+    fn test_eval_lc() {
+        let (x, y) = (Variable::MultiplierLeft(0), Variable::Committed(1));
+        let lc: LinearCombination = x * Scalar::from(3u64) + y - Scalar::from(10u64);
+
+        let mut assignment = HashMap::new();
+        assignment.insert(x, Scalar::from(5u64));
+        // y is missing
+        assert_eq!(eval_lc(&lc, &assignment), None);
+
+        assignment.insert(y, Scalar::from(7u64));
+        assert_eq!(eval_lc(&lc, &assignment), Some(Scalar::from(12u64)));
+
+        assert_eq!(eval_lc(&LinearCombination::from(Scalar::from(4u64)), &HashMap::new()), Some(Scalar::from(4u64)));
+        assert_eq!(eval_lc(&LinearCombination::default(), &HashMap::new()), Some(Scalar::zero()));
+    }
+
+    #[test]
+    fn test_bit_decompose_overflow() {
+        let pc_gens = PedersenGens::default();
+        let mut prover_transcript = Transcript::new(b"BitDecomposeTest");
+        let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+        let mut rng = rand::thread_rng();
+
+        let v = 300u64;
+        let (_, var) = prover.commit(v.into(), Scalar::random(&mut rng));
+        let quantity = AllocatedQuantity {
+            variable: var,
+            assignment: Some(v),
+        };
+
+        // 300 needs 9 bits
+        assert_eq!(bit_decompose(&mut prover, quantity, 9).unwrap().len(), 9);
+        match bit_decompose(&mut prover, quantity, 8) {
+            Err(R1CSError::GadgetError { description }) => assert!(description.contains("bit length 9")),
+            _ => panic!("Expected an error for value larger than 8 bits"),
+        }
+        assert!(positive_no_gadget(&mut prover, quantity, 8).is_err());
+    }
+}
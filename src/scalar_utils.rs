@@ -6,6 +6,7 @@ use rand::SeedableRng;
 use rand::rngs::OsRng;
 use self::byteorder::{ByteOrder, LittleEndian};
 use curve25519_dalek::scalar::Scalar;
+use sha3::{Digest, Sha3_512};
 use std::fmt;
 
 pub type ScalarBytes = [u8; 32];
@@ -159,6 +160,16 @@ pub fn scalar_to_u64_array(scalar: &Scalar) -> [u64; 4] {
     result
 }
 
+/// Returns None if the scalar does not fit in a u64
+pub fn scalar_to_u64(scalar: &Scalar) -> Option<u64> {
+    let array = scalar_to_u64_array(scalar);
+    if array[1..].iter().any(|a| *a != 0) {
+        None
+    } else {
+        Some(array[0])
+    }
+}
+
 pub fn u64_array_to_scalar(array: &[u64; 4]) -> Scalar {
     let mut result: [u8; 32] = [0; 32];
     LittleEndian::write_u64_into(array, &mut result);
@@ -189,13 +200,9 @@ pub fn get_base_4_repr(scalar: &Scalar, limit_bytes: usize) -> Vec<u8> {
 /// Following code for handling Hex is taken from https://play.rust-lang.org/?version=stable&mode=debug&edition=2015&gist=e241493d100ecaadac3c99f37d0f766f
 use std::num::ParseIntError;
 
+/// Decode a hex string with an optional "0x" or "0X" prefix
 pub fn decode_hex(s: &str) -> Result<Vec<u8>, DecodeHexError> {
-    let s = if s[0..2] == *"0x" || s[0..2] == *"0X" {
-        match s.char_indices().skip(2).next() {
-            Some((pos, _)) => &s[pos..],
-            None => "",
-        }
-    } else { s };
+    let s = if s.starts_with("0x") || s.starts_with("0X") { &s[2..] } else { s };
     if s.len() % 2 != 0 {
         Err(DecodeHexError::OddLength)
     } else {
@@ -210,6 +217,8 @@ pub fn decode_hex(s: &str) -> Result<Vec<u8>, DecodeHexError> {
 pub enum DecodeHexError {
     OddLength,
     ParseInt(ParseIntError),
+    /// Decoded bytes are more than the expected number of bytes
+    TooLong(usize),
 }
 
 impl From<ParseIntError> for DecodeHexError {
@@ -223,21 +232,90 @@ impl fmt::Display for DecodeHexError {
         match self {
             DecodeHexError::OddLength => "input string has an odd number of bytes".fmt(f),
             DecodeHexError::ParseInt(e) => e.fmt(f),
+            DecodeHexError::TooLong(n) => write!(f, "input string has {} bytes, more than 32", n),
         }
     }
 }
 
 impl std::error::Error for DecodeHexError {}
 
+/// Get scalar from its big-endian hex representation, optionally "0x" or "0X" prefixed. Hex strings
+/// shorter than 32 bytes are left-padded with zeroes.
 pub fn get_scalar_from_hex(hex_str: &str) -> Result<Scalar, DecodeHexError> {
     let bytes = decode_hex(hex_str)?;
+    if bytes.len() > 32 {
+        return Err(DecodeHexError::TooLong(bytes.len()))
+    }
     let mut result: [u8; 32] = [0; 32];
-    result.copy_from_slice(&bytes);
+    result[32 - bytes.len()..].copy_from_slice(&bytes);
     // `Scalar` expects byte ordering as little endian
     result.reverse();
     Ok(Scalar::from_bytes_mod_order(result))
 }
 
+/// Inverse of `get_scalar_from_hex`. Returns the "0x" prefixed big-endian hex string of the scalar.
+pub fn get_hex_from_scalar(scalar: &Scalar) -> String {
+    let mut bytes = scalar.to_bytes();
+    bytes.reverse();
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("0x{}", hex)
+}
+
+/// Whether the little-endian `bytes` are the canonical encoding of a scalar, i.e. less than the group order
+pub fn is_canonical(bytes: &ScalarBytes) -> bool {
+    Scalar::from_canonical_bytes(*bytes).is_some()
+}
+
+/// Reduce 64 little-endian bytes modulo the group order
+pub fn reduce_from_wide(bytes: &[u8; 64]) -> Scalar {
+    Scalar::from_bytes_mod_order_wide(bytes)
+}
+
+/// Blinding derived from `seed` and `index`, the SHA3-512 hash of a domain separator, the seed and the
+/// little-endian index reduced modulo the group order. The same seed and index always give the same blinding.
+pub fn derive_blinding(seed: &[u8], index: u64) -> Scalar {
+    let mut hasher = Sha3_512::new();
+    hasher.input(b"derive_blinding");
+    hasher.input(&(seed.len() as u64).to_le_bytes());
+    hasher.input(seed);
+    hasher.input(&index.to_le_bytes());
+    let mut wide = [0u8; 64];
+    wide.copy_from_slice(&hasher.result());
+    reduce_from_wide(&wide)
+}
+
+/// `base^exp` where `exp` is little-endian bytes, by square and multiply from the most significant bit
+pub fn scalar_pow(base: &Scalar, exp: &ScalarBytes) -> Scalar {
+    let mut result = Scalar::one();
+    for byte in exp.iter().rev() {
+        for i in (0..8).rev() {
+            result = result * result;
+            if (byte >> i) & 1 == 1 {
+                result = result * base;
+            }
+        }
+    }
+    result
+}
+
+/// (l - 5) / 8 where l is the group order, little-endian
+const SQRT_EXP: ScalarBytes = [125, 186, 158, 75, 99, 76, 2, 203, 154, 243, 94, 212, 59, 223, 155, 2,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+
+/// A square root of `a` or None when `a` is not a quadratic residue. Since l = 5 mod 8, uses Atkin's method,
+/// b = (2a)^((l-5)/8), i = 2a*b^2 and the root is a*b*(i - 1).
+pub fn scalar_sqrt(a: &Scalar) -> Option<Scalar> {
+    let two_a = a + a;
+    let b = scalar_pow(&two_a, &SQRT_EXP);
+    let i = two_a * b * b;
+    let root = a * b * (i - Scalar::one());
+    if root * root == *a {
+        Some(root)
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -308,5 +386,83 @@ mod tests {
         let x = Scalar::zero();
         println!("Inverse {:?}", x.invert());
     }
-}
 
+    #[test]
+    fn test_hex_from_scalar() {
+        let mut csprng: OsRng = OsRng::default();
+        for _ in 0..100 {
+            let r: Scalar = Scalar::random(&mut csprng);
+            let h = get_hex_from_scalar(&r);
+            assert_eq!(h.len(), 66);
+            assert_eq!(get_scalar_from_hex(&h).unwrap(), r);
+        }
+    }
+
+    #[test]
+    fn test_scalar_from_hex() {
+        let unprefixed = "0e4538f256946a6c9c75f885b0420bd2949f85087dd3c63999367b7ad58c80bc";
+        let s = get_scalar_from_hex(unprefixed).unwrap();
+        assert_eq!(get_scalar_from_hex(&format!("0x{}", unprefixed)).unwrap(), s);
+        assert_eq!(get_scalar_from_hex(&format!("0X{}", unprefixed)).unwrap(), s);
+        assert_eq!(get_hex_from_scalar(&s), format!("0x{}", unprefixed));
+
+        // Short strings are left-padded
+        assert_eq!(get_scalar_from_hex("0x0101").unwrap(), Scalar::from(257u64));
+        assert_eq!(get_scalar_from_hex("0x").unwrap(), Scalar::zero());
+        assert_eq!(get_scalar_from_hex("").unwrap(), Scalar::zero());
+
+        assert!(get_scalar_from_hex("0xZZ").is_err());
+        assert!(get_scalar_from_hex("0x123").is_err());
+        assert_eq!(get_scalar_from_hex(&format!("0x00{}", unprefixed)), Err(DecodeHexError::TooLong(33)));
+    }
+
+
+    #[test]
+    fn test_is_canonical() {
+        assert!(is_canonical(&Scalar::from(5u64).to_bytes()));
+        assert!(is_canonical(&(BASEPOINT_ORDER - Scalar::one()).to_bytes()));
+
+        // Group order and group order + 5 are not canonical, the latter is another encoding of 5
+        let order = BASEPOINT_ORDER.to_bytes();
+        assert!(!is_canonical(&order));
+        let mut order_plus_5 = order;
+        order_plus_5[0] += 5;
+        assert!(!is_canonical(&order_plus_5));
+        assert!(!is_canonical(&[0xffu8; 32]));
+
+        let mut wide = [0u8; 64];
+        wide[..32].copy_from_slice(&order_plus_5);
+        assert_eq!(reduce_from_wide(&wide), Scalar::from(5u64));
+        // 2^256 mod l
+        let mut wide = [0u8; 64];
+        wide[32] = 1;
+        let two_128 = Scalar::from(u64::max_value()) * Scalar::from(u64::max_value()) + Scalar::from(u64::max_value()) * Scalar::from(2u64) + Scalar::one();
+        assert_eq!(reduce_from_wide(&wide), two_128 * two_128);
+    }
+
+    #[test]
+    fn test_scalar_sqrt() {
+        assert_eq!(scalar_pow(&Scalar::from(3u64), &Scalar::from(5u64).to_bytes()), Scalar::from(243u64));
+        assert_eq!(scalar_sqrt(&Scalar::zero()), Some(Scalar::zero()));
+
+        let mut csprng: OsRng = OsRng::default();
+        for _ in 0..20 {
+            let r: Scalar = Scalar::random(&mut csprng);
+            let root = scalar_sqrt(&(r * r)).unwrap();
+            assert!(root == r || root == -r);
+        }
+        // 2 is not a quadratic residue
+        assert!(scalar_sqrt(&Scalar::from(2u64)).is_none());
+        assert!(scalar_sqrt(&(Scalar::from(2u64) * Scalar::from(9u64))).is_none());
+    }
+
+    #[test]
+    fn test_derive_blinding() {
+        let b = derive_blinding(b"seed", 0);
+        assert_eq!(derive_blinding(b"seed", 0), b);
+        assert_ne!(derive_blinding(b"seed", 1), b);
+        assert_ne!(derive_blinding(b"seed1", 0), b);
+        assert_ne!(derive_blinding(b"", 0), derive_blinding(b"", 1));
+        assert_ne!(b, Scalar::zero());
+    }
+}